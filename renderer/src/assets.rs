@@ -2,7 +2,6 @@
 cfg_if! branches are separated into functions mainly to help the LSP
 */
 
-use std::io::{BufReader, Cursor};
 #[cfg(not(target_arch = "wasm32"))]
 use std::{
     fs::{self},
@@ -11,9 +10,9 @@ use std::{
 
 use anyhow::{anyhow, Result};
 use cfg_if::cfg_if;
-use wgpu::util::DeviceExt;
+use futures::io::Cursor;
 
-use crate::{model, texture};
+use crate::{lod, model, scene_file, texture};
 
 #[cfg(target_arch = "wasm32")]
 fn format_url(file_name: &str) -> reqwest::Url {
@@ -101,104 +100,745 @@ async fn load_binary_native(file_name: &str) -> Result<Vec<u8>> {
 
 pub async fn load_texture(
     file_name: &str,
+    options: texture::TextureOptions,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
+    mipmap_generator: Option<&texture::MipmapGenerator>,
 ) -> Result<texture::Texture> {
     let data = load_binary(file_name).await?;
-    texture::Texture::from_bytes(device, queue, &data, file_name)
+    texture::Texture::from_bytes(device, queue, &data, file_name, options, mipmap_generator)
 }
 
+/// Cubemap from six images, in the order +X, -X, +Y, -Y, +Z, -Z.
+pub async fn load_cubemap(
+    file_names: [&str; 6],
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> Result<texture::Texture> {
+    let faces = futures::future::try_join_all(file_names.map(|file_name| async move {
+        let data = load_binary(file_name).await?;
+        Ok::<_, anyhow::Error>(image::load_from_memory(&data)?)
+    }))
+    .await?;
+    let faces: [image::DynamicImage; 6] = faces.try_into().unwrap();
+
+    texture::Texture::create_cubemap(device, queue, &faces, file_names[0])
+}
+
+/// Cubemap with faces of `face_size` texels from an equirectangular
+/// panorama, e.g. a Radiance `.hdr` environment map.
+pub async fn load_equirectangular(
+    file_name: &str,
+    face_size: u32,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> Result<texture::Texture> {
+    let data = load_binary(file_name).await?;
+    let img = image::load_from_memory(&data)?;
+
+    Ok(texture::Texture::create_cubemap_from_equirectangular(
+        device, queue, &img, face_size, file_name,
+    ))
+}
+
+/// Scene description in RON or, going by the extension, JSON.
+pub async fn load_scene(file_name: &str) -> Result<scene_file::SceneFile> {
+    let format = scene_file::SceneFormat::from_file_name(file_name)?;
+    let text = load_string(file_name).await?;
+
+    scene_file::SceneFile::parse(&text, format)
+}
+
+/// Loads a Wavefront OBJ or, going by the extension, a glTF (`.gltf` or
+/// `.glb`) model.
 pub async fn load_model(
     file_name: &str,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     layout: &wgpu::BindGroupLayout,
+    mipmap_generator: Option<&texture::MipmapGenerator>,
 ) -> Result<model::Model> {
+    let extension = std::path::Path::new(file_name)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+
+    match extension.as_deref() {
+        Some("gltf" | "glb") => load_gltf(file_name, device, queue, layout, mipmap_generator).await,
+        _ => load_obj(file_name, device, queue, layout, mipmap_generator).await,
+    }
+}
+
+/// Loads the meshes of a Wavefront OBJ as a level of detail of `model`.
+/// Its materials are matched to the ones of `model` by name, without
+/// loading their textures again.
+pub async fn load_lod(
+    file_name: &str,
+    threshold: lod::LodThreshold,
+    model: &model::Model,
+    device: &wgpu::Device,
+) -> Result<lod::Lod> {
+    let (models, obj_materials) = parse_obj(file_name).await?;
+    let material_indices = obj_materials?
+        .iter()
+        .map(|obj_material| {
+            model
+                .materials
+                .iter()
+                .position(|material| material.name == obj_material.name)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "{file_name} uses material {} the model doesn't have.",
+                        obj_material.name
+                    )
+                })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut meshes = create_obj_meshes(file_name, models, device)?;
+    for mesh in &mut meshes {
+        mesh.material = material_indices.get(mesh.material).copied().unwrap_or(0);
+    }
+
+    Ok(lod::Lod { meshes, threshold })
+}
+
+async fn load_obj(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    mipmap_generator: Option<&texture::MipmapGenerator>,
+) -> Result<model::Model> {
+    let (models, obj_materials) = parse_obj(file_name).await?;
+
+    let materials = futures::future::try_join_all(
+        obj_materials?
+            .into_iter()
+            .map(|m| load_obj_material(m, device, queue, layout, mipmap_generator)),
+    )
+    .await?;
+    let meshes = create_obj_meshes(file_name, models, device)?;
+
+    Ok(model::Model {
+        meshes,
+        materials,
+        lods: Vec::new(),
+    })
+}
+
+async fn parse_obj(
+    file_name: &str,
+) -> Result<(
+    Vec<tobj::Model>,
+    std::result::Result<Vec<tobj::Material>, tobj::LoadError>,
+)> {
     let obj_text = load_string(file_name).await?;
-    let obj_cursor = Cursor::new(obj_text);
-    let mut obj_reader = BufReader::new(obj_cursor);
 
-    let (models, obj_materials) = tobj::load_obj_buf_async(
-        &mut obj_reader,
+    Ok(tobj::futures::load_obj_buf(
+        Cursor::new(obj_text),
         &tobj::LoadOptions {
             triangulate: true,
             single_index: true,
             ..Default::default()
         },
         |p| async move {
-            let mat_text = load_string(&p).await.unwrap();
-            tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
+            let mat_text = load_string(&p.to_string_lossy()).await.unwrap();
+            tobj::futures::load_mtl_buf(Cursor::new(mat_text)).await
         },
     )
-    .await?;
-
-    let materials = futures::future::try_join_all(obj_materials?.into_iter().map(|m| async {
-        let diffuse_texture = load_texture(
-            &m.diffuse_texture
-                .ok_or(anyhow!("Material dosn't have a texture name."))?,
-            device,
-            queue,
-        )
-        .await?;
-
-        let texture_view = wgpu::BindingResource::TextureView(&diffuse_texture.view);
-        let texture_sampler = wgpu::BindingResource::Sampler(&diffuse_texture.sampler);
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: None,
-            layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: texture_view,
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: texture_sampler,
-                },
-            ],
-        });
-
-        Ok::<_, anyhow::Error>(model::Material {
-            name: m.name,
-            diffuse_texture,
-            bind_group,
-        })
-    }))
-    .await?;
+    .await?)
+}
 
-    let meshes = models
+/// Meshes of the OBJ `models`, with the material indices of the OBJ.
+fn create_obj_meshes(
+    file_name: &str,
+    models: Vec<tobj::Model>,
+    device: &wgpu::Device,
+) -> Result<Vec<model::Mesh>> {
+    models
         .into_iter()
         .map(|m| {
-            let vertices: Vec<_> = (0..m.mesh.positions.len() / 3)
+            let mut vertices: Vec<_> = (0..m.mesh.positions.len() / 3)
                 .map(|i| {
                     anyhow::Ok(model::ModelVertex {
                         position: m.mesh.positions[i * 3..i * 3 + 3].try_into()?,
                         tex_coords: m.mesh.texcoords[i * 2..i * 2 + 2].try_into()?,
                         normal: m.mesh.normals[i * 3..i * 3 + 3].try_into()?,
+                        // Calculated below
+                        tangent: [0.0; 3],
+                        bitangent: [0.0; 3],
                     })
                 })
                 .collect::<Result<_>>()?;
 
-            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Vertex Buffer", file_name)),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
-            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Vertex Buffer", file_name)),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
-                usage: wgpu::BufferUsages::INDEX,
-            });
-
-            Ok(model::Mesh {
-                name: file_name.to_string(),
-                vertex_buffer,
-                index_buffer,
-                num_elements: m.mesh.indices.len() as u32,
-                material: m.mesh.material_id.unwrap_or(0),
-            })
+            compute_tangents(&mut vertices, &m.mesh.indices);
+
+            Ok(model::Mesh::new(
+                device,
+                file_name,
+                &vertices,
+                &m.mesh.indices,
+                m.mesh.material_id.unwrap_or(0),
+            ))
         })
-        .collect::<Result<Vec<_>>>()?;
+        .collect()
+}
+
+/// Reads the metallic-roughness model from the PBR extension of MTL (`Pr`,
+/// `Pm`, `Ke` and their maps, `norm`), falling back to the classic
+/// parameters: `Kd`/`map_Kd` for the base color, `Ns` for the roughness and
+/// `map_Bump` for the normal map.
+async fn load_obj_material(
+    m: tobj::Material,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    mipmap_generator: Option<&texture::MipmapGenerator>,
+) -> Result<model::Material> {
+    let param = |key: &str| m.unknown_param.get(key).map(|value| value.trim());
+    let float_param = |key: &str| param(key).and_then(|value| value.parse::<f32>().ok());
+    let load = |file_name: Option<&str>, options| {
+        let file_name = file_name.map(str::to_string);
+        async move {
+            match file_name {
+                Some(file_name) => {
+                    { load_texture(&file_name, options, device, queue, mipmap_generator) }
+                        .await
+                        .map(Some)
+                }
+                None => Ok(None),
+            }
+        }
+    };
+
+    let [r, g, b] = m.diffuse.unwrap_or([1.0; 3]);
+    // The factors scale their maps, so a map without its factor is used as
+    // is. Without either, classic MTL materials are dielectrics with a
+    // Blinn-Phong exponent.
+    let has_map = |key: &str| param(key).is_some();
+    let factors = model::MaterialFactors {
+        base_color: [r, g, b, m.dissolve.unwrap_or(1.0)],
+        metallic: float_param("Pm").unwrap_or(if has_map("map_Pm") { 1.0 } else { 0.0 }),
+        roughness: float_param("Pr").unwrap_or_else(|| {
+            if has_map("map_Pr") {
+                1.0
+            } else {
+                (2.0 / (m.shininess.unwrap_or(0.0) + 2.0)).sqrt()
+            }
+        }),
+        emissive: m.emissive.unwrap_or(if has_map("map_Ke") {
+            [1.0; 3]
+        } else {
+            [0.0; 3]
+        }),
+        ..Default::default()
+    };
+
+    let metallic_roughness = match (param("map_Pm"), param("map_Pr")) {
+        (None, None) => None,
+        (metallic, roughness) => {
+            let load_image = |file_name: Option<&str>| {
+                let file_name = file_name.map(str::to_string);
+                async move {
+                    match file_name {
+                        Some(file_name) => {
+                            let data = load_binary(&file_name).await?;
+                            anyhow::Ok(Some(image::load_from_memory(&data)?))
+                        }
+                        None => Ok(None),
+                    }
+                }
+            };
+            let image = combine_metallic_roughness(
+                load_image(metallic).await?,
+                load_image(roughness).await?,
+            );
+            Some(texture::Texture::from_image(
+                device,
+                queue,
+                &image,
+                Some("metallic_roughness_texture"),
+                texture::TextureOptions::LINEAR,
+                mipmap_generator,
+            )?)
+        }
+    };
+
+    let textures = model::MaterialTextures {
+        base_color: load(m.diffuse_texture.as_deref(), texture::TextureOptions::COLOR).await?,
+        metallic_roughness,
+        normal: load(
+            param("norm").or(m.normal_texture.as_deref()),
+            texture::TextureOptions::LINEAR,
+        )
+        .await?,
+        occlusion: None,
+        emissive: load(param("map_Ke"), texture::TextureOptions::COLOR).await?,
+    };
+
+    model::Material::new(device, queue, &m.name, textures, factors, layout)
+}
+
+/// MTL has separate grayscale metallic and roughness maps, they go in the
+/// blue and green channels of a single texture like in glTF. A missing map
+/// leaves its factor as is.
+fn combine_metallic_roughness(
+    metallic: Option<image::DynamicImage>,
+    roughness: Option<image::DynamicImage>,
+) -> image::DynamicImage {
+    let (width, height) = [&metallic, &roughness]
+        .into_iter()
+        .flatten()
+        .map(|image| (image.width(), image.height()))
+        .max()
+        .unwrap_or((1, 1));
+    let to_size = |image: Option<image::DynamicImage>| {
+        image.map(|image| {
+            image
+                .resize_exact(width, height, image::imageops::FilterType::Triangle)
+                .into_luma8()
+        })
+    };
+    let metallic = to_size(metallic);
+    let roughness = to_size(roughness);
+
+    image::DynamicImage::ImageRgba8(image::RgbaImage::from_fn(width, height, |x, y| {
+        let channel = |image: &Option<image::GrayImage>| {
+            image
+                .as_ref()
+                .map_or(255, |image| image.get_pixel(x, y).0[0])
+        };
+        image::Rgba([255, channel(&roughness), channel(&metallic), 255])
+    }))
+}
+
+/// Loads every triangle primitive of the default scene as its own mesh, with
+/// the node transforms baked into the vertices.
+///
+/// Buffers and images can be embedded in a `.glb`, stored as data URIs or
+/// in files next to the model.
+async fn load_gltf(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    mipmap_generator: Option<&texture::MipmapGenerator>,
+) -> Result<model::Model> {
+    use cgmath::{Matrix4, SquareMatrix};
+
+    let gltf = gltf::Gltf::from_slice(&load_binary(file_name).await?)?;
+    let document = &gltf.document;
+    let blob = gltf.blob.as_ref();
 
-    Ok(model::Model { meshes, materials })
+    let buffers = futures::future::try_join_all(document.buffers().map(|buffer| async move {
+        match buffer.source() {
+            gltf::buffer::Source::Bin => blob
+                .cloned()
+                .ok_or(anyhow!("{file_name} doesn't have a binary chunk.")),
+            gltf::buffer::Source::Uri(uri) => load_uri(file_name, uri).await,
+        }
+    }))
+    .await?;
+
+    // Primitives without a material use the default glTF material, which
+    // goes after the others.
+    let default_material = document
+        .meshes()
+        .flat_map(|mesh| mesh.primitives())
+        .map(|primitive| primitive.material())
+        .find(|material| material.index().is_none());
+    let materials =
+        futures::future::try_join_all(document.materials().chain(default_material).map(
+            |material| {
+                load_gltf_material(
+                    file_name,
+                    material,
+                    &buffers,
+                    device,
+                    queue,
+                    layout,
+                    mipmap_generator,
+                )
+            },
+        ))
+        .await?;
+
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or(anyhow!("{file_name} doesn't have a scene."))?;
+
+    let mut meshes = Vec::new();
+    let mut nodes: Vec<_> = scene
+        .nodes()
+        .map(|node| (node, Matrix4::identity()))
+        .collect();
+    while let Some((node, parent_transform)) = nodes.pop() {
+        let transform = parent_transform * Matrix4::from(node.transform().matrix());
+        nodes.extend(node.children().map(|child| (child, transform)));
+
+        let Some(mesh) = node.mesh() else {
+            continue;
+        };
+        for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                log::warn!(
+                    "Skipping {:?} primitive of {file_name}, only triangles are supported.",
+                    primitive.mode()
+                );
+                continue;
+            }
+
+            let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+            let positions: Vec<_> = reader
+                .read_positions()
+                .ok_or(anyhow!("Primitive of {file_name} doesn't have positions."))?
+                .collect();
+            let mut indices: Vec<_> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..positions.len() as u32).collect(),
+            };
+            if let Some(index) = indices.iter().find(|&&i| i as usize >= positions.len()) {
+                return Err(anyhow!(
+                    "Primitive of {file_name} uses vertex {index}, it only has {}.",
+                    positions.len()
+                ));
+            }
+            if indices.len() % 3 != 0 {
+                return Err(anyhow!(
+                    "Primitive of {file_name} has {} indices, not whole triangles.",
+                    indices.len()
+                ));
+            }
+            let normals: Vec<_> = match reader.read_normals() {
+                Some(normals) => normals.collect(),
+                None => compute_normals(&positions, &indices),
+            };
+            let tex_coords: Vec<_> = match reader.read_tex_coords(0) {
+                Some(tex_coords) => tex_coords.into_f32().collect(),
+                None => vec![[0.0; 2]; positions.len()],
+            };
+            // Zipping attributes of different lengths would drop vertices.
+            if normals.len() != positions.len() || tex_coords.len() != positions.len() {
+                return Err(anyhow!(
+                    "Primitive of {file_name} has {} positions, {} normals and {} UVs.",
+                    positions.len(),
+                    normals.len(),
+                    tex_coords.len()
+                ));
+            }
+
+            // Mirroring transforms flip the winding order of the triangles.
+            if transform.determinant() < 0.0 {
+                indices
+                    .chunks_exact_mut(3)
+                    .for_each(|triangle| triangle.swap(1, 2));
+            }
+
+            let mut vertices = transform_vertices(transform, &positions, &normals, &tex_coords);
+            compute_tangents(&mut vertices, &indices);
+
+            meshes.push(model::Mesh::new(
+                device,
+                mesh.name().unwrap_or(file_name),
+                &vertices,
+                &indices,
+                primitive
+                    .material()
+                    .index()
+                    .unwrap_or(document.materials().len()),
+            ));
+        }
+    }
+
+    Ok(model::Model {
+        meshes,
+        materials,
+        lods: Vec::new(),
+    })
+}
+
+async fn load_gltf_material<'a>(
+    file_name: &str,
+    material: gltf::Material<'a>,
+    buffers: &[Vec<u8>],
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    mipmap_generator: Option<&texture::MipmapGenerator>,
+) -> Result<model::Material> {
+    let name = material.name().unwrap_or("default");
+    let pbr = material.pbr_metallic_roughness();
+
+    let load = |texture: Option<gltf::Texture<'a>>, options| async move {
+        match texture {
+            Some(texture) => load_gltf_texture(
+                file_name,
+                texture,
+                options,
+                buffers,
+                device,
+                queue,
+                mipmap_generator,
+            )
+            .await
+            .map(Some),
+            None => Ok(None),
+        }
+    };
+
+    let textures = model::MaterialTextures {
+        base_color: load(
+            pbr.base_color_texture().map(|info| info.texture()),
+            texture::TextureOptions::COLOR,
+        )
+        .await?,
+        metallic_roughness: load(
+            pbr.metallic_roughness_texture().map(|info| info.texture()),
+            texture::TextureOptions::LINEAR,
+        )
+        .await?,
+        normal: load(
+            material.normal_texture().map(|info| info.texture()),
+            texture::TextureOptions::LINEAR,
+        )
+        .await?,
+        occlusion: load(
+            material.occlusion_texture().map(|info| info.texture()),
+            texture::TextureOptions::LINEAR,
+        )
+        .await?,
+        emissive: load(
+            material.emissive_texture().map(|info| info.texture()),
+            texture::TextureOptions::COLOR,
+        )
+        .await?,
+    };
+
+    let factors = model::MaterialFactors {
+        base_color: pbr.base_color_factor(),
+        metallic: pbr.metallic_factor(),
+        roughness: pbr.roughness_factor(),
+        emissive: material.emissive_factor(),
+        normal_scale: material
+            .normal_texture()
+            .map_or(1.0, |normal| normal.scale()),
+        occlusion_strength: material
+            .occlusion_texture()
+            .map_or(1.0, |occlusion| occlusion.strength()),
+    };
+
+    model::Material::new(device, queue, name, textures, factors, layout)
+}
+
+async fn load_gltf_texture(
+    file_name: &str,
+    texture: gltf::Texture<'_>,
+    options: texture::TextureOptions,
+    buffers: &[Vec<u8>],
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    mipmap_generator: Option<&texture::MipmapGenerator>,
+) -> Result<texture::Texture> {
+    let image = texture.source();
+    let label = image.name().unwrap_or(file_name);
+
+    match image.source() {
+        gltf::image::Source::View { view, .. } => {
+            let buffer = &buffers[view.buffer().index()];
+            let bytes = buffer
+                .get(view.offset()..view.offset() + view.length())
+                .ok_or(anyhow!("Image {label} is out of its buffer's bounds."))?;
+            texture::Texture::from_bytes(device, queue, bytes, label, options, mipmap_generator)
+        }
+        gltf::image::Source::Uri { uri, .. } => {
+            let bytes = load_uri(file_name, uri).await?;
+            texture::Texture::from_bytes(device, queue, &bytes, label, options, mipmap_generator)
+        }
+    }
+}
+
+/// Decodes base64 data URIs, anything else is a path relative to the model.
+async fn load_uri(file_name: &str, uri: &str) -> Result<Vec<u8>> {
+    use base64::Engine;
+
+    if let Some(data) = uri.strip_prefix("data:") {
+        let (_, encoded) = data
+            .split_once(";base64,")
+            .ok_or(anyhow!("Only base64 data URIs are supported."))?;
+        return Ok(base64::engine::general_purpose::STANDARD.decode(encoded)?);
+    }
+
+    let path = match file_name.rfind('/') {
+        Some(i) => format!("{}{uri}", &file_name[..=i]),
+        None => uri.to_string(),
+    };
+    load_binary(&path).await
+}
+
+fn transform_vertices(
+    transform: cgmath::Matrix4<f32>,
+    positions: &[[f32; 3]],
+    normals: &[[f32; 3]],
+    tex_coords: &[[f32; 2]],
+) -> Vec<model::ModelVertex> {
+    use cgmath::{InnerSpace, Matrix, Matrix3, Point3, SquareMatrix, Transform, Vector3};
+
+    let rotation_scale = Matrix3::from_cols(
+        transform.x.truncate(),
+        transform.y.truncate(),
+        transform.z.truncate(),
+    );
+    // Keeps normals perpendicular to non-uniformly scaled surfaces.
+    let normal_matrix = rotation_scale
+        .invert()
+        .unwrap_or(rotation_scale)
+        .transpose();
+
+    positions
+        .iter()
+        .zip(normals)
+        .zip(tex_coords)
+        .map(|((position, normal), tex_coords)| model::ModelVertex {
+            position: transform.transform_point(Point3::from(*position)).into(),
+            tex_coords: *tex_coords,
+            normal: (normal_matrix * Vector3::from(*normal)).normalize().into(),
+            // Calculated afterwards
+            tangent: [0.0; 3],
+            bitangent: [0.0; 3],
+        })
+        .collect()
+}
+
+/// Smooth normals averaged from the faces around each vertex, for meshes
+/// that don't have any.
+fn compute_normals(positions: &[[f32; 3]], indices: &[u32]) -> Vec<[f32; 3]> {
+    use cgmath::{InnerSpace, Vector3, Zero};
+
+    let mut normals = vec![Vector3::<f32>::zero(); positions.len()];
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| Vector3::from(positions[triangle[i] as usize]));
+        // Not normalized so bigger faces weigh more.
+        let face_normal = (b - a).cross(c - a);
+        for &i in triangle {
+            normals[i as usize] += face_normal;
+        }
+    }
+
+    normals
+        .into_iter()
+        .map(|normal| {
+            if normal.is_zero() {
+                Vector3::unit_y().into()
+            } else {
+                normal.normalize().into()
+            }
+        })
+        .collect()
+}
+
+/// Square on the XZ plane centered on the origin, facing up, with a single
+/// rough dielectric material of the linear `color`.
+pub fn create_plane(
+    size: f32,
+    color: [f32; 4],
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) -> Result<model::Model> {
+    let half = size * 0.5;
+    let corners = [
+        ([-half, 0.0, -half], [0.0, 0.0]),
+        ([-half, 0.0, half], [0.0, 1.0]),
+        ([half, 0.0, half], [1.0, 1.0]),
+        ([half, 0.0, -half], [1.0, 0.0]),
+    ];
+    let mut vertices: Vec<_> = corners
+        .into_iter()
+        .map(|(position, tex_coords)| model::ModelVertex {
+            position,
+            tex_coords,
+            normal: [0.0, 1.0, 0.0],
+            tangent: [0.0; 3],
+            bitangent: [0.0; 3],
+        })
+        .collect();
+    let indices = [0, 1, 2, 0, 2, 3];
+    compute_tangents(&mut vertices, &indices);
+
+    let factors = model::MaterialFactors {
+        base_color: color,
+        metallic: 0.0,
+        roughness: 0.8,
+        ..Default::default()
+    };
+    let material = model::Material::new(
+        device,
+        queue,
+        "plane",
+        model::MaterialTextures::default(),
+        factors,
+        layout,
+    )?;
+
+    Ok(model::Model {
+        meshes: vec![model::Mesh::new(device, "plane", &vertices, &indices, 0)],
+        materials: vec![material],
+        lods: Vec::new(),
+    })
+}
+
+/// Fills in the tangent and bitangent of every vertex from the UVs of the
+/// triangles it belongs to, averaging over shared vertices.
+fn compute_tangents(vertices: &mut [model::ModelVertex], indices: &[u32]) {
+    use cgmath::{InnerSpace, Vector2, Vector3, Zero};
+
+    let mut tangents = vec![Vector3::<f32>::zero(); vertices.len()];
+    let mut bitangents = vec![Vector3::<f32>::zero(); vertices.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let [i0, i1, i2] = [0, 1, 2].map(|i| triangle[i] as usize);
+        let [v0, v1, v2] = [i0, i1, i2].map(|i| vertices[i]);
+
+        let pos0 = Vector3::from(v0.position);
+        let pos1 = Vector3::from(v1.position);
+        let pos2 = Vector3::from(v2.position);
+
+        let uv0 = Vector2::from(v0.tex_coords);
+        let uv1 = Vector2::from(v1.tex_coords);
+        let uv2 = Vector2::from(v2.tex_coords);
+
+        // Edges of the triangle in model space and in UV space
+        let delta_pos1 = pos1 - pos0;
+        let delta_pos2 = pos2 - pos0;
+        let delta_uv1 = uv1 - uv0;
+        let delta_uv2 = uv2 - uv0;
+
+        // Solving
+        //     delta_pos1 = delta_uv1.x * T + delta_uv1.y * B
+        //     delta_pos2 = delta_uv2.x * T + delta_uv2.y * B
+        let det = delta_uv1.x * delta_uv2.y - delta_uv1.y * delta_uv2.x;
+        if det.abs() < f32::EPSILON {
+            // Degenerate UVs, leave these vertices to other triangles.
+            continue;
+        }
+        let r = 1.0 / det;
+        let tangent = (delta_pos1 * delta_uv2.y - delta_pos2 * delta_uv1.y) * r;
+        // Flipped because wgpu textures have their origin on the top left.
+        let bitangent = (delta_pos2 * delta_uv1.x - delta_pos1 * delta_uv2.x) * -r;
+
+        for i in [i0, i1, i2] {
+            tangents[i] += tangent;
+            bitangents[i] += bitangent;
+        }
+    }
+
+    for ((vertex, tangent), bitangent) in vertices.iter_mut().zip(tangents).zip(bitangents) {
+        if !tangent.is_zero() {
+            vertex.tangent = tangent.normalize().into();
+        }
+        if !bitangent.is_zero() {
+            vertex.bitangent = bitangent.normalize().into();
+        }
+    }
 }
@@ -0,0 +1,379 @@
+/*!
+Bloom: bright parts of the HDR scene bleeding light into their surroundings.
+
+What's brighter than the threshold is extracted into a half size texture,
+then downsampled again and again into smaller ones. Going back up, each level
+is blurred and added to the next bigger one, so the blur spreads further the
+smaller the level it started from. The biggest level ends up added on top of
+the scene before tonemapping.
+
+The levels are separate textures rather than the mips of one, some backends
+(e.g. GL) can't render from and sample different mips of the same texture.
+*/
+
+use wgpu::util::DeviceExt;
+
+use crate::texture;
+
+/// Most downsampled levels, the blur doesn't get much wider past that.
+const MAX_LEVELS: usize = 6;
+/// Levels stop once they'd be smaller than this in either dimension.
+const MIN_LEVEL_SIZE: u32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BloomSettings {
+    /// Scale of the bloom added to the scene.
+    pub intensity: f32,
+    /// Brightness above which colors bloom, in HDR units before exposure.
+    pub threshold: f32,
+    /// Spread of the blur between two levels, in texels. Bigger values give
+    /// a wider but blockier bloom.
+    pub radius: f32,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self {
+            intensity: 0.3,
+            threshold: 1.0,
+            radius: 1.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BloomUniform {
+    threshold: f32,
+    knee: f32,
+    intensity: f32,
+    radius: f32,
+}
+
+impl From<BloomSettings> for BloomUniform {
+    fn from(settings: BloomSettings) -> Self {
+        Self {
+            threshold: settings.threshold,
+            knee: settings.threshold * 0.5,
+            intensity: settings.intensity,
+            radius: settings.radius,
+        }
+    }
+}
+
+pub struct Bloom {
+    settings: BloomSettings,
+    format: wgpu::TextureFormat,
+    levels: Vec<texture::Texture>,
+    sampler: wgpu::Sampler,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    /// Samples the scene, read by the bright-pass.
+    scene_bind_group: wgpu::BindGroup,
+    level_bind_groups: Vec<wgpu::BindGroup>,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    prefilter_pipeline: wgpu::RenderPipeline,
+    downsample_pipeline: wgpu::RenderPipeline,
+    upsample_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+}
+
+impl Bloom {
+    /// `scene` is the HDR texture view of the surface size the bloom is
+    /// extracted from and added back to, in `format`.
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+        scene: &wgpu::TextureView,
+        settings: BloomSettings,
+    ) -> Self {
+        let texture_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("bloom_texture_bind_group_layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+            });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("bloom_bind_group_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Bloom Buffer"),
+            contents: bytemuck::cast_slice(&[BloomUniform::from(settings)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("bloom_bind_group"),
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Bloom Pipeline Layout"),
+            bind_group_layouts: &[&texture_bind_group_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = texture::create_fullscreen_shader(
+            device,
+            "bloom.wgsl",
+            include_str!("shaders/bloom.wgsl"),
+        );
+        let additive = wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::Zero,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+        };
+        let create_pipeline = |entry_point, blend| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(&format!("Bloom Pipeline ({entry_point})")),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: Some(blend),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        let prefilter_pipeline = create_pipeline("fs_prefilter", wgpu::BlendState::REPLACE);
+        let downsample_pipeline = create_pipeline("fs_downsample", wgpu::BlendState::REPLACE);
+        let upsample_pipeline = create_pipeline("fs_upsample", additive);
+        let composite_pipeline = create_pipeline("fs_composite", additive);
+
+        let scene_bind_group =
+            Self::create_texture_bind_group(device, &texture_bind_group_layout, scene, &sampler);
+        let levels = Self::create_levels(device, config, format);
+        let level_bind_groups = levels
+            .iter()
+            .map(|level| {
+                Self::create_texture_bind_group(
+                    device,
+                    &texture_bind_group_layout,
+                    &level.view,
+                    &sampler,
+                )
+            })
+            .collect();
+
+        Self {
+            settings,
+            format,
+            levels,
+            sampler,
+            texture_bind_group_layout,
+            scene_bind_group,
+            level_bind_groups,
+            buffer,
+            bind_group,
+            prefilter_pipeline,
+            downsample_pipeline,
+            upsample_pipeline,
+            composite_pipeline,
+        }
+    }
+
+    /// Half the surface size and smaller, down to [`MIN_LEVEL_SIZE`].
+    fn create_levels(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+    ) -> Vec<texture::Texture> {
+        (1..=MAX_LEVELS as u32)
+            .map(|level| (config.width >> level, config.height >> level))
+            .take_while(|&(width, height)| width.min(height) >= MIN_LEVEL_SIZE)
+            .enumerate()
+            .map(|(i, (width, height))| {
+                texture::Texture::create_intermediate_target_sized(
+                    device,
+                    width,
+                    height,
+                    format,
+                    &format!("bloom_texture_{i}"),
+                )
+            })
+            .collect()
+    }
+
+    fn create_texture_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        view: &wgpu::TextureView,
+        sampler: &wgpu::Sampler,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("bloom_texture_bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        })
+    }
+
+    /// Recreates the levels for the new surface size, `scene` being the
+    /// resized HDR texture.
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        scene: &wgpu::TextureView,
+    ) {
+        self.scene_bind_group = Self::create_texture_bind_group(
+            device,
+            &self.texture_bind_group_layout,
+            scene,
+            &self.sampler,
+        );
+        self.levels = Self::create_levels(device, config, self.format);
+        self.level_bind_groups = self
+            .levels
+            .iter()
+            .map(|level| {
+                Self::create_texture_bind_group(
+                    device,
+                    &self.texture_bind_group_layout,
+                    &level.view,
+                    &self.sampler,
+                )
+            })
+            .collect();
+    }
+
+    pub fn settings(&self) -> BloomSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, queue: &wgpu::Queue, settings: BloomSettings) {
+        self.settings = settings;
+        queue.write_buffer(
+            &self.buffer,
+            0,
+            bytemuck::cast_slice(&[BloomUniform::from(settings)]),
+        );
+    }
+
+    /// Records the passes extracting the bloom from `scene` and adding it
+    /// back on top. `scene` must be the view given to [`Self::new`] or
+    /// [`Self::resize`].
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, scene: &wgpu::TextureView) {
+        // Too small a surface for even one level.
+        if self.levels.is_empty() {
+            return;
+        }
+
+        let mut pass = |label, pipeline, source, target, load| {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some(label),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: target,
+                    resolve_target: None,
+                    ops: wgpu::Operations { load, store: true },
+                })],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, source, &[]);
+            render_pass.set_bind_group(1, &self.bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        };
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+
+        pass(
+            "Bloom Prefilter Pass",
+            &self.prefilter_pipeline,
+            &self.scene_bind_group,
+            &self.levels[0].view,
+            clear,
+        );
+        for i in 1..self.levels.len() {
+            pass(
+                "Bloom Downsample Pass",
+                &self.downsample_pipeline,
+                &self.level_bind_groups[i - 1],
+                &self.levels[i].view,
+                clear,
+            );
+        }
+        for i in (1..self.levels.len()).rev() {
+            pass(
+                "Bloom Upsample Pass",
+                &self.upsample_pipeline,
+                &self.level_bind_groups[i],
+                &self.levels[i - 1].view,
+                wgpu::LoadOp::Load,
+            );
+        }
+        pass(
+            "Bloom Composite Pass",
+            &self.composite_pipeline,
+            &self.level_bind_groups[0],
+            scene,
+            wgpu::LoadOp::Load,
+        );
+    }
+}
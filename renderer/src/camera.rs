@@ -1,11 +1,15 @@
-use winit::event::{ElementState, VirtualKeyCode};
+use cgmath::prelude::*;
+use winit::dpi::PhysicalPosition;
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, VirtualKeyCode};
+
+use crate::culling;
 
 #[rustfmt::skip]
-const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
+pub(crate) const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
     0.0, 1.0, 0.0, 0.0,
-    0.0, 0.0, 0.5, 0.5,
-    0.0, 0.0, 0.0, 1.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
 );
 
 pub struct Camera {
@@ -24,33 +28,80 @@ impl Camera {
         let proj = cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar);
         OPENGL_TO_WGPU_MATRIX * proj * view
     }
+
+    pub fn frustum(&self) -> culling::Frustum {
+        culling::Frustum::from_view_projection(&self.build_view_projection_matrix())
+    }
 }
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct CameraUniform {
+    // vec4 instead of vec3 to keep the uniform 16 byte aligned
+    view_position: [f32; 4],
     view_proj: [[f32; 4]; 4],
+    /// Takes clip space back to world space, e.g. to find the view
+    /// direction of each pixel.
+    inv_view_proj: [[f32; 4]; 4],
+}
+
+impl Default for CameraUniform {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CameraUniform {
     pub fn new() -> Self {
-        use cgmath::SquareMatrix;
         Self {
+            view_position: [0.0; 4],
             view_proj: cgmath::Matrix4::identity().into(),
+            inv_view_proj: cgmath::Matrix4::identity().into(),
         }
     }
 
     pub fn update_view_proj(&mut self, camera: &Camera) {
-        self.view_proj = camera.build_view_projection_matrix().into();
+        self.view_position = camera.eye.to_homogeneous().into();
+        let view_proj = camera.build_view_projection_matrix();
+        self.view_proj = view_proj.into();
+        self.inv_view_proj = view_proj.invert().unwrap_or(view_proj).into();
     }
 }
 
+/// Radians the camera orbits per pixel the cursor is dragged.
+const ORBIT_SENSITIVITY: f32 = 0.005;
+/// Fraction of the distance to the target the camera pans per pixel.
+const PAN_SENSITIVITY: f32 = 0.0015;
+/// Factor the distance to the target is multiplied by per wheel line.
+const ZOOM_STEP: f32 = 0.9;
+/// Pixels of a touchpad scroll counted as one wheel line.
+const PIXELS_PER_LINE: f32 = 20.0;
+/// Closest the camera gets to its target when zooming.
+pub const MIN_DISTANCE: f32 = 1.0;
+/// Furthest the camera gets from its target when zooming.
+pub const MAX_DISTANCE: f32 = 100.0;
+/// Furthest above or below the target the camera orbits, short of looking
+/// straight along [`Camera::up`] where the view flips.
+pub const MAX_PITCH: cgmath::Deg<f32> = cgmath::Deg(89.0);
+
+/// Moves the camera around its target: WASD or the arrow keys and dragging
+/// with the left mouse button orbit, dragging with the right button pans and
+/// the wheel zooms.
 pub struct CameraController {
     speed: f32,
     is_forward_pressed: bool,
     is_backward_pressed: bool,
     is_left_pressed: bool,
     is_right_pressed: bool,
+    is_orbit_pressed: bool,
+    is_pan_pressed: bool,
+    cursor: Option<PhysicalPosition<f64>>,
+    /// Cursor movement while dragging since the last update, in pixels.
+    orbit_delta: cgmath::Vector2<f32>,
+    pan_delta: cgmath::Vector2<f32>,
+    /// Wheel lines scrolled since the last update, positive towards the
+    /// target.
+    zoom_delta: f32,
 }
 
 impl CameraController {
@@ -61,6 +112,12 @@ impl CameraController {
             is_backward_pressed: false,
             is_left_pressed: false,
             is_right_pressed: false,
+            is_orbit_pressed: false,
+            is_pan_pressed: false,
+            cursor: None,
+            orbit_delta: cgmath::Vector2::zero(),
+            pan_delta: cgmath::Vector2::zero(),
+            zoom_delta: 0.0,
         }
     }
 
@@ -75,35 +132,126 @@ impl CameraController {
         };
     }
 
-    pub fn update_camera(&self, camera: &mut Camera) {
-        use cgmath::InnerSpace;
-        let forward = camera.target - camera.eye;
-        let forward_norm = forward.normalize();
-        let forward_mag = forward.magnitude();
-
-        // Prevents glitching when camera gets too close to the
-        // center of the scene.
-        if self.is_forward_pressed && forward_mag > self.speed {
-            camera.eye += forward_norm * self.speed;
+    pub fn process_mouse_button(&mut self, state: &ElementState, button: &MouseButton) {
+        let is_pressed = *state == ElementState::Pressed;
+        match button {
+            MouseButton::Left => self.is_orbit_pressed = is_pressed,
+            MouseButton::Right => self.is_pan_pressed = is_pressed,
+            _ => {}
         }
-        if self.is_backward_pressed {
-            camera.eye -= forward_norm * self.speed;
+    }
+
+    pub fn process_cursor_moved(&mut self, position: PhysicalPosition<f64>) {
+        if let Some(last) = self.cursor.replace(position) {
+            let delta = cgmath::vec2((position.x - last.x) as f32, (position.y - last.y) as f32);
+            if self.is_orbit_pressed {
+                self.orbit_delta += delta;
+            } else if self.is_pan_pressed {
+                self.pan_delta += delta;
+            }
         }
+    }
 
-        let right = forward_norm.cross(camera.up);
+    /// Forgets where the cursor was, so it coming back elsewhere doesn't
+    /// count as a drag.
+    pub fn process_cursor_left(&mut self) {
+        self.cursor = None;
+    }
 
-        // Redo radius calc in case the fowrard/backward is pressed.
-        let forward = camera.target - camera.eye;
-        let forward_mag = forward.magnitude();
+    pub fn process_scroll(&mut self, delta: &MouseScrollDelta) {
+        self.zoom_delta += match delta {
+            MouseScrollDelta::LineDelta(_, lines) => *lines,
+            MouseScrollDelta::PixelDelta(position) => position.y as f32 / PIXELS_PER_LINE,
+        };
+    }
 
+    pub fn update_camera(&mut self, camera: &mut Camera) {
+        let mut distance_factor = ZOOM_STEP.powf(std::mem::take(&mut self.zoom_delta));
+        let mut yaw = -self.orbit_delta.x * ORBIT_SENSITIVITY;
+        let pitch = self.orbit_delta.y * ORBIT_SENSITIVITY;
+        let pan = std::mem::replace(&mut self.pan_delta, cgmath::Vector2::zero());
+        self.orbit_delta = cgmath::Vector2::zero();
+
+        let forward = camera.target - camera.eye;
+        let distance = forward.magnitude().max(f32::EPSILON);
+        if self.is_forward_pressed {
+            distance_factor *= 1.0 - self.speed / distance;
+        }
+        if self.is_backward_pressed {
+            distance_factor *= 1.0 + self.speed / distance;
+        }
+        // About `speed` along the circle around the target, however far it
+        // is.
         if self.is_right_pressed {
-            // Rescale the distance between the target and eye so
-            // that it doesn't change. The eye therefore still
-            // lies on the circle made by the target and eye.
-            camera.eye = camera.target - (forward + right * self.speed).normalize() * forward_mag;
+            yaw -= self.speed / distance;
         }
         if self.is_left_pressed {
-            camera.eye = camera.target - (forward - right * self.speed).normalize() * forward_mag;
+            yaw += self.speed / distance;
+        }
+
+        if pan != cgmath::Vector2::zero() && !forward.is_zero() {
+            // Drags the scene along with the cursor.
+            let mut right = forward.cross(camera.up);
+            if right.magnitude2() < f32::EPSILON * distance * distance {
+                // Looking along up, any direction across it will do.
+                right = perpendicular(camera.up);
+            }
+            let right = right.normalize();
+            let up = right.cross(forward / distance).normalize();
+            let offset = (up * pan.y - right * pan.x) * distance * PAN_SENSITIVITY;
+            camera.eye += offset;
+            camera.target += offset;
+        }
+
+        if yaw != 0.0 || pitch != 0.0 || distance_factor != 1.0 {
+            orbit(
+                camera,
+                cgmath::Rad(yaw),
+                cgmath::Rad(pitch),
+                distance_factor,
+            );
         }
     }
 }
+
+/// Turns the camera around its target by `yaw` around [`Camera::up`] and by
+/// `pitch` towards it, keeping the pitch within [`MAX_PITCH`], and scales the
+/// distance to the target by `distance_factor` within [`MIN_DISTANCE`] and
+/// [`MAX_DISTANCE`].
+fn orbit(
+    camera: &mut Camera,
+    yaw: cgmath::Rad<f32>,
+    pitch: cgmath::Rad<f32>,
+    distance_factor: f32,
+) {
+    let up = camera.up.normalize();
+    let offset = camera.eye - camera.target;
+    // The eye can sit on the target, e.g. set that way by hand.
+    let distance = offset.magnitude().max(f32::EPSILON);
+    let height = offset.dot(up);
+
+    // Straight above, below or on the target, any direction around it will
+    // do.
+    let mut around = offset - up * height;
+    if around.magnitude2() < f32::EPSILON * distance * distance {
+        around = perpendicular(up);
+    }
+    let around = cgmath::Quaternion::from_axis_angle(up, yaw) * around.normalize();
+
+    let max_pitch: cgmath::Rad<f32> = MAX_PITCH.into();
+    let current_pitch = cgmath::Rad((height / distance).clamp(-1.0, 1.0).asin());
+    let pitch = (current_pitch + pitch).0.clamp(-max_pitch.0, max_pitch.0);
+    let distance = (distance * distance_factor).clamp(MIN_DISTANCE, MAX_DISTANCE);
+
+    camera.eye = camera.target + (around * pitch.cos() + up * pitch.sin()) * distance;
+}
+
+/// Some direction perpendicular to `axis`.
+fn perpendicular(axis: cgmath::Vector3<f32>) -> cgmath::Vector3<f32> {
+    let across = axis.cross(cgmath::Vector3::unit_x());
+    if across.magnitude2() < f32::EPSILON {
+        axis.cross(cgmath::Vector3::unit_z())
+    } else {
+        across
+    }
+}
@@ -0,0 +1,192 @@
+/*!
+Reading rendered frames back from the GPU.
+
+Copies have to respect `wgpu::COPY_BYTES_PER_ROW_ALIGNMENT`, so the staging
+buffer rows are padded and the padding is stripped when building the image.
+
+Bytes are kept as they are stored in the texture. For sRGB formats that's
+already the encoded color, and for non-sRGB surfaces the display interprets
+the stored bytes as sRGB anyway, so in both cases the PNG matches what was on
+screen. Only the channel order of BGRA surfaces needs fixing.
+*/
+
+use std::future::Future;
+use std::io::Cursor;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::JsCast;
+
+const BYTES_PER_PIXEL: u32 = 4;
+
+/// A texture copy that has been recorded into a staging buffer and can be
+/// mapped once the encoder that wrote it has been submitted.
+pub struct Readback {
+    buffer: wgpu::Buffer,
+    width: u32,
+    height: u32,
+    padded_bytes_per_row: u32,
+    format: wgpu::TextureFormat,
+}
+
+impl Readback {
+    /// Records a copy of the first mip level of `texture` into a new
+    /// mappable buffer.
+    pub fn new(
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+    ) -> Self {
+        let width = texture.width();
+        let height = texture.height();
+        let format = texture.format();
+
+        let unpadded_bytes_per_row = width * BYTES_PER_PIXEL;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback Buffer"),
+            size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        Self {
+            buffer,
+            width,
+            height,
+            padded_bytes_per_row,
+            format,
+        }
+    }
+
+    /// Maps the staging buffer and converts its contents into an image.
+    ///
+    /// The copy must already be submitted. The returned future owns the
+    /// buffer, so it can be spawned on wasm where nothing blocks on the map.
+    pub fn read(self, device: &wgpu::Device) -> impl Future<Output = Result<image::RgbaImage>> {
+        let (sender, receiver) = futures::channel::oneshot::channel();
+        self.buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let _ = sender.send(result);
+            });
+
+        // Native backends only resolve the map request when polled, the
+        // browser does it on its own.
+        if cfg!(not(target_arch = "wasm32")) {
+            device.poll(wgpu::Maintain::Wait);
+        }
+
+        async move {
+            receiver.await??;
+            self.to_image()
+        }
+    }
+
+    fn to_image(&self) -> Result<image::RgbaImage> {
+        use wgpu::TextureFormat as TF;
+        let is_bgra = match self.format {
+            TF::Rgba8Unorm | TF::Rgba8UnormSrgb => false,
+            TF::Bgra8Unorm | TF::Bgra8UnormSrgb => true,
+            format => return Err(anyhow!("Can't read back textures in {format:?}.")),
+        };
+
+        let unpadded_bytes_per_row = (self.width * BYTES_PER_PIXEL) as usize;
+        let data = self.buffer.slice(..).get_mapped_range();
+        let mut pixels: Vec<u8> = data
+            .chunks(self.padded_bytes_per_row as usize)
+            .flat_map(|row| &row[..unpadded_bytes_per_row])
+            .copied()
+            .collect();
+        drop(data);
+        self.buffer.unmap();
+
+        if is_bgra {
+            pixels
+                .chunks_exact_mut(BYTES_PER_PIXEL as usize)
+                .for_each(|pixel| pixel.swap(0, 2));
+        }
+
+        image::RgbaImage::from_raw(self.width, self.height, pixels)
+            .ok_or(anyhow!("Readback buffer is smaller than the image."))
+    }
+}
+
+/// Milliseconds since the unix epoch, used to give screenshots unique names.
+pub fn timestamp() -> u64 {
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            js_sys::Date::now() as u64
+        } else {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64)
+        }
+    }
+}
+
+pub fn encode_png(image: &image::RgbaImage) -> Result<Vec<u8>> {
+    let mut png = Cursor::new(Vec::new());
+    image.write_to(&mut png, image::ImageOutputFormat::Png)?;
+
+    Ok(png.into_inner())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn save_png(image: &image::RgbaImage, path: impl AsRef<Path>) -> Result<()> {
+    std::fs::write(path, encode_png(image)?)?;
+
+    Ok(())
+}
+
+/// Hands the PNG to the browser as a Blob URL and starts a download of it.
+/// Returns the URL so it can also be shown or attached somewhere else.
+#[cfg(target_arch = "wasm32")]
+pub fn download_png(image: &image::RgbaImage, file_name: &str) -> Result<String> {
+    let png = encode_png(image)?;
+
+    let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(png.as_slice()));
+    let options = web_sys::BlobPropertyBag::new();
+    options.set_type("image/png");
+    let blob = web_sys::Blob::new_with_u8_array_sequence_and_options(&parts, &options)
+        .map_err(|e| anyhow!("Couldn't create screenshot blob: {e:?}"))?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob)
+        .map_err(|e| anyhow!("Couldn't create screenshot url: {e:?}"))?;
+
+    let anchor = web_sys::window()
+        .and_then(|win| win.document())
+        .and_then(|doc| doc.create_element("a").ok())
+        .and_then(|el| el.dyn_into::<web_sys::HtmlAnchorElement>().ok())
+        .ok_or(anyhow!("Couldn't create screenshot download link."))?;
+    anchor.set_href(&url);
+    anchor.set_download(file_name);
+    anchor.click();
+
+    Ok(url)
+}
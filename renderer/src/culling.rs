@@ -0,0 +1,152 @@
+/*!
+View frustum culling on the CPU.
+
+Every mesh gets an axis-aligned bounding box when it's created. Each frame
+the box of a model (all its meshes) is moved to where every instance is and
+tested against the planes of the camera frustum, and only the instances that
+may be visible get drawn. Boxes are tested conservatively: some instances
+just outside a corner of the frustum are kept, visible ones never get culled.
+*/
+
+use cgmath::prelude::*;
+
+/// Axis-aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: cgmath::Point3<f32>,
+    pub max: cgmath::Point3<f32>,
+}
+
+impl Aabb {
+    /// Smallest box around `points`, `None` when there are none.
+    pub fn from_points(points: impl IntoIterator<Item = cgmath::Point3<f32>>) -> Option<Self> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        Some(points.fold(Self::new(first, first), |aabb, point| {
+            aabb.union(&Self::new(point, point))
+        }))
+    }
+
+    pub fn new(min: cgmath::Point3<f32>, max: cgmath::Point3<f32>) -> Self {
+        Self { min, max }
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            min: cgmath::Point3::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            max: cgmath::Point3::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        }
+    }
+
+    pub fn center(&self) -> cgmath::Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    /// Half the size along each axis.
+    pub fn extents(&self) -> cgmath::Vector3<f32> {
+        (self.max - self.min) * 0.5
+    }
+
+    /// Box around this one once moved by `transform`.
+    pub fn transform(&self, transform: &cgmath::Matrix4<f32>) -> Self {
+        let center = transform.transform_point(self.center());
+        let extents = self.extents();
+        // Each axis of the box adds its projected length to every axis.
+        let extents = cgmath::Vector3::new(
+            transform.x.x.abs() * extents.x
+                + transform.y.x.abs() * extents.y
+                + transform.z.x.abs() * extents.z,
+            transform.x.y.abs() * extents.x
+                + transform.y.y.abs() * extents.y
+                + transform.z.y.abs() * extents.z,
+            transform.x.z.abs() * extents.x
+                + transform.y.z.abs() * extents.y
+                + transform.z.z.abs() * extents.z,
+        );
+
+        Self {
+            min: center - extents,
+            max: center + extents,
+        }
+    }
+}
+
+/// Plane of the points `p` where `normal · p + distance` is 0, positive on
+/// the side `normal` points to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Plane {
+    pub normal: cgmath::Vector3<f32>,
+    pub distance: f32,
+}
+
+impl Plane {
+    /// Plane from the `ax + by + cz + d` coefficients, normalized.
+    fn from_coefficients(coefficients: cgmath::Vector4<f32>) -> Self {
+        let normal = coefficients.truncate();
+        let length = normal.magnitude();
+        Self {
+            normal: normal / length,
+            distance: coefficients.w / length,
+        }
+    }
+
+    pub fn signed_distance(&self, point: cgmath::Point3<f32>) -> f32 {
+        self.normal.dot(point.to_vec()) + self.distance
+    }
+}
+
+/// The six planes bounding what a camera sees, pointing inwards.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes of a view projection matrix with wgpu's 0 to 1
+    /// depth range, e.g. [`crate::camera::Camera::build_view_projection_matrix`].
+    pub fn from_view_projection(view_proj: &cgmath::Matrix4<f32>) -> Self {
+        let row = |i: usize| {
+            cgmath::Vector4::new(
+                view_proj.x[i],
+                view_proj.y[i],
+                view_proj.z[i],
+                view_proj.w[i],
+            )
+        };
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+
+        Self {
+            planes: [
+                w + x, // Left
+                w - x, // Right
+                w + y, // Bottom
+                w - y, // Top
+                z,     // Near
+                w - z, // Far
+            ]
+            .map(Plane::from_coefficients),
+        }
+    }
+
+    /// Whether any of `aabb` may be inside the frustum.
+    pub fn intersects(&self, aabb: &Aabb) -> bool {
+        let center = aabb.center();
+        let extents = aabb.extents();
+        self.planes.iter().all(|plane| {
+            // Distance from the center to the box corner furthest along
+            // the normal.
+            let radius = plane.normal.x.abs() * extents.x
+                + plane.normal.y.abs() * extents.y
+                + plane.normal.z.abs() * extents.z;
+            plane.signed_distance(center) >= -radius
+        })
+    }
+}
@@ -0,0 +1,450 @@
+/*!
+View frustum culling on the GPU, for scenes with more instances than are
+worth testing on the CPU every frame.
+
+A compute pass tests the instances of each model against the camera frustum
+the same way [`crate::culling`] does. The visible ones are packed into a
+vertex buffer, next to their indices among the model's instances, and counted
+into the `draw_indexed_indirect` arguments of every mesh of the model. The
+scene pass then draws them with [`crate::model::DrawModel::draw_model_indirect`]
+without the CPU ever learning how many there are.
+
+Instances come sorted by level of detail (see [`crate::lod`]). Each level
+keeps its own segment of the visible instances, starting where its instances
+start, and its own draw arguments.
+
+Needs compute shaders, storage buffers and indirect draws, which the WebGL2
+downlevel limits don't have, see [`GpuCulling::is_supported`].
+*/
+
+use std::future::Future;
+use std::ops::Range;
+
+use anyhow::Result;
+use wgpu::util::DeviceExt;
+
+use crate::{culling, instance, lod, model};
+
+/// Has to match `@workgroup_size` in `cull.wgsl`.
+const WORKGROUP_SIZE: u32 = 64;
+/// Storage buffers `cull.wgsl` binds.
+const STORAGE_BUFFERS: u32 = 4;
+
+/// Size of the indirect draw arguments of one mesh.
+pub const DRAW_ARGS_SIZE: wgpu::BufferAddress =
+    std::mem::size_of::<wgpu::util::DrawIndexedIndirect>() as wgpu::BufferAddress;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullingUniform {
+    planes: [[f32; 4]; 6],
+    bounds_center: [f32; 3],
+    instance_count: u32,
+    bounds_extents: [f32; 3],
+    enabled: u32,
+    level_ends: [u32; lod::MAX_LEVELS],
+    level_first_draws: [u32; lod::MAX_LEVELS],
+    level_mesh_counts: [u32; lod::MAX_LEVELS],
+    level_count: u32,
+    _padding: [u32; 3],
+}
+
+/// The culling pipeline, shared by the [`ModelCulling`] of every model.
+pub struct GpuCulling {
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl GpuCulling {
+    /// Whether `device` can cull on the GPU, WebGL2 can't.
+    pub fn is_supported(adapter: &wgpu::Adapter, device: &wgpu::Device) -> bool {
+        let flags = adapter.get_downlevel_capabilities().flags;
+        let limits = device.limits();
+        flags.contains(
+            wgpu::DownlevelFlags::COMPUTE_SHADERS | wgpu::DownlevelFlags::INDIRECT_EXECUTION,
+        ) && limits.max_storage_buffers_per_shader_stage >= STORAGE_BUFFERS
+            && limits.max_compute_workgroup_size_x >= WORKGROUP_SIZE
+    }
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("culling_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage(1, true),
+                storage(2, false),
+                storage(3, false),
+                storage(4, false),
+            ],
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Culling Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::include_wgsl!("shaders/cull.wgsl"));
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Culling Pipeline"),
+            layout: Some(&layout),
+            module: &shader,
+            entry_point: "cs_main",
+        });
+
+        Self {
+            bind_group_layout,
+            pipeline,
+        }
+    }
+
+    /// Records the pass culling the instances of `models`, after resetting
+    /// their draw arguments.
+    pub fn cull(&self, encoder: &mut wgpu::CommandEncoder, models: &[&ModelCulling]) {
+        for model in models {
+            encoder.copy_buffer_to_buffer(
+                &model.initial_draws,
+                0,
+                &model.draws,
+                0,
+                model.initial_draws.size(),
+            );
+        }
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Culling Pass"),
+        });
+        compute_pass.set_pipeline(&self.pipeline);
+        for model in models.iter().filter(|model| model.instance_count > 0) {
+            compute_pass.set_bind_group(0, &model.bind_group, &[]);
+            compute_pass.dispatch_workgroups(model.instance_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+        }
+    }
+}
+
+/// Buffers culling the instances of one model.
+pub struct ModelCulling {
+    uniform_buffer: wgpu::Buffer,
+    /// Instances the buffers have room for, the same as the instance buffer
+    /// they were bound with.
+    capacity: usize,
+    instance_count: u32,
+    /// Instances of each level of detail, in `visible` too.
+    level_segments: Vec<Range<u32>>,
+    /// Index in `draws` of the arguments of the first mesh of each level.
+    level_first_draws: Vec<u32>,
+    visible: wgpu::Buffer,
+    visible_indices: wgpu::Buffer,
+    /// Draw arguments of every mesh without any instance, copied over
+    /// `draws` before each pass.
+    initial_draws: wgpu::Buffer,
+    draws: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl ModelCulling {
+    pub fn new(
+        device: &wgpu::Device,
+        culling: &GpuCulling,
+        model: &model::Model,
+        instances: &instance::InstanceBuffer,
+    ) -> Self {
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Culling Buffer"),
+            size: std::mem::size_of::<CullingUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut level_first_draws = Vec::new();
+        let mut draw_count = 0;
+        for level in 0..model.level_count() {
+            level_first_draws.push(draw_count);
+            draw_count += model.level_meshes(level).len() as u32;
+        }
+
+        // Empty models still bind one set of arguments.
+        let mut draw_args: Vec<u8> = Vec::new();
+        let meshes = (0..model.level_count()).flat_map(|level| model.level_meshes(level));
+        for mesh in meshes {
+            let args = wgpu::util::DrawIndexedIndirect {
+                vertex_count: mesh.num_elements,
+                instance_count: 0,
+                base_index: 0,
+                vertex_offset: 0,
+                base_instance: 0,
+            };
+            draw_args.extend_from_slice(args.as_bytes());
+        }
+        draw_args.resize(draw_args.len().max(DRAW_ARGS_SIZE as usize), 0);
+
+        let initial_draws = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Initial Draw Buffer"),
+            contents: &draw_args,
+            usage: wgpu::BufferUsages::COPY_SRC,
+        });
+        let draws = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Draw Buffer"),
+            size: initial_draws.size(),
+            usage: wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let capacity = instances.capacity();
+        let (visible, visible_indices) = Self::create_visible_buffers(device, capacity);
+        let bind_group = Self::create_bind_group(
+            device,
+            culling,
+            &uniform_buffer,
+            instances,
+            &visible,
+            &visible_indices,
+            &draws,
+        );
+
+        Self {
+            uniform_buffer,
+            capacity,
+            instance_count: 0,
+            level_segments: Vec::new(),
+            level_first_draws,
+            visible,
+            visible_indices,
+            initial_draws,
+            draws,
+            bind_group,
+        }
+    }
+
+    fn create_visible_buffers(
+        device: &wgpu::Device,
+        capacity: usize,
+    ) -> (wgpu::Buffer, wgpu::Buffer) {
+        let visible = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Visible Instance Buffer"),
+            size: (capacity * std::mem::size_of::<instance::InstanceRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let visible_indices = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Visible Index Buffer"),
+            size: (capacity * std::mem::size_of::<u32>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        (visible, visible_indices)
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        culling: &GpuCulling,
+        uniform_buffer: &wgpu::Buffer,
+        instances: &instance::InstanceBuffer,
+        visible: &wgpu::Buffer,
+        visible_indices: &wgpu::Buffer,
+        draws: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        let buffers = [
+            uniform_buffer,
+            instances.buffer(),
+            visible,
+            visible_indices,
+            draws,
+        ];
+        let entries: Vec<_> = buffers
+            .iter()
+            .enumerate()
+            .map(|(binding, buffer)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect();
+
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("culling_bind_group"),
+            layout: &culling.bind_group_layout,
+            entries: &entries,
+        })
+    }
+
+    /// Makes the next [`GpuCulling::cull`] test `instances` against
+    /// `frustum`, or keep all of them when there's no frustum. `instances`
+    /// must be the buffer of the model the culling was created for, with
+    /// `level_counts` instances of each level of detail one after the other.
+    #[allow(clippy::too_many_arguments)]
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        culling: &GpuCulling,
+        model: &model::Model,
+        instances: &instance::InstanceBuffer,
+        level_counts: &[u32],
+        frustum: Option<&culling::Frustum>,
+    ) {
+        // The instance buffer is replaced when it grows.
+        if instances.capacity() != self.capacity {
+            self.capacity = instances.capacity();
+            (self.visible, self.visible_indices) =
+                Self::create_visible_buffers(device, self.capacity);
+            self.bind_group = Self::create_bind_group(
+                device,
+                culling,
+                &self.uniform_buffer,
+                instances,
+                &self.visible,
+                &self.visible_indices,
+                &self.draws,
+            );
+        }
+        self.instance_count = instances.len();
+
+        let mut start = 0;
+        self.level_segments = level_counts
+            .iter()
+            .map(|count| {
+                start += count;
+                start - count..start
+            })
+            .collect();
+        let mut level_ends = [self.instance_count; lod::MAX_LEVELS];
+        let mut level_first_draws = [0; lod::MAX_LEVELS];
+        let mut level_mesh_counts = [0; lod::MAX_LEVELS];
+        for (level, segment) in self.level_segments.iter().enumerate() {
+            level_ends[level] = segment.end;
+            level_first_draws[level] = self.level_first_draws[level];
+            level_mesh_counts[level] = model.level_meshes(level).len() as u32;
+        }
+
+        let bounds = model.bounds();
+        let (bounds_center, bounds_extents) = bounds
+            .map(|bounds| (bounds.center().into(), bounds.extents().into()))
+            .unwrap_or_default();
+        let planes = frustum.map_or([[0.0; 4]; 6], |frustum| {
+            frustum
+                .planes
+                .map(|plane| plane.normal.extend(plane.distance).into())
+        });
+        let uniform = CullingUniform {
+            planes,
+            bounds_center,
+            instance_count: self.instance_count,
+            bounds_extents,
+            enabled: (frustum.is_some() && bounds.is_some()) as u32,
+            level_ends,
+            level_first_draws,
+            level_mesh_counts,
+            level_count: self.level_segments.len() as u32,
+            _padding: [0; 3],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+
+    /// Vertex buffer of the visible instances.
+    pub fn visible_buffer(&self) -> &wgpu::Buffer {
+        &self.visible
+    }
+
+    /// Indirect draw arguments of each mesh, [`DRAW_ARGS_SIZE`] apart.
+    pub fn draw_buffer(&self) -> &wgpu::Buffer {
+        &self.draws
+    }
+
+    /// Where the visible instances of `level` start in
+    /// [`Self::visible_buffer`], and the offset of the arguments of its
+    /// first mesh in [`Self::draw_buffer`]. `None` for levels without
+    /// instances.
+    pub fn level(&self, level: usize) -> Option<(u32, wgpu::BufferAddress)> {
+        let segment = self.level_segments.get(level)?;
+        (!segment.is_empty()).then(|| {
+            (
+                segment.start,
+                self.level_first_draws[level] as wgpu::BufferAddress * DRAW_ARGS_SIZE,
+            )
+        })
+    }
+
+    /// Reads back the indices of the instances the last pass found visible,
+    /// level after level in the order they were drawn.
+    pub fn read_visible_indices(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> impl Future<Output = Result<Vec<u32>>> {
+        let draws_size = self.draws.size();
+        let indices_size = self.visible_indices.size();
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Culling Readback Buffer"),
+            size: draws_size + indices_size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Culling Readback Encoder"),
+        });
+        encoder.copy_buffer_to_buffer(&self.draws, 0, &readback, 0, draws_size);
+        encoder.copy_buffer_to_buffer(
+            &self.visible_indices,
+            0,
+            &readback,
+            draws_size,
+            indices_size,
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let (sender, receiver) = futures::channel::oneshot::channel();
+        readback
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let _ = sender.send(result);
+            });
+        // Native backends only resolve the map request when polled.
+        if cfg!(not(target_arch = "wasm32")) {
+            device.poll(wgpu::Maintain::Wait);
+        }
+
+        let levels: Vec<_> = (0..self.level_segments.len())
+            .filter_map(|level| self.level(level))
+            .collect();
+        async move {
+            receiver.await??;
+            let data = readback.slice(..).get_mapped_range();
+            let words: &[u32] = bytemuck::cast_slice(&data);
+            let (draws, visible_indices) = words.split_at((draws_size / 4) as usize);
+
+            let mut indices = Vec::new();
+            for (start, draw_offset) in levels {
+                // The instance count of the arguments of the level's first
+                // mesh.
+                let count = draws[(draw_offset / 4) as usize + 1];
+                indices.extend_from_slice(&visible_indices[start as usize..][..count as usize]);
+            }
+            drop(data);
+            readback.unmap();
+
+            Ok(indices)
+        }
+    }
+}
@@ -0,0 +1,270 @@
+/*!
+HDR render target the scene is drawn into, and the tonemapping pass that
+maps it to the final output.
+
+Float render targets aren't available everywhere (WebGL2 needs
+`EXT_color_buffer_float`), without them the scene goes into an 8 bit target
+instead. Tonemapping and exposure still work there, but highlights are
+clipped to 1 before reaching them.
+*/
+
+use wgpu::util::DeviceExt;
+
+use crate::texture;
+
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+/// Used instead of [`HDR_FORMAT`] when it can't be rendered into.
+pub const FALLBACK_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+/// Curve mapping HDR colors to the `0..1` range of the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tonemapper {
+    Reinhard,
+    /// Fit of the ACES filmic curve, more contrast than Reinhard.
+    Aces,
+}
+
+impl Tonemapper {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Reinhard => Self::Aces,
+            Self::Aces => Self::Reinhard,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct TonemapUniform {
+    exposure: f32,
+    tonemapper: u32,
+    apply_gamma: u32,
+    _padding: u32,
+}
+
+pub struct Hdr {
+    format: wgpu::TextureFormat,
+    texture: texture::Texture,
+    exposure: f32,
+    tonemapper: Tonemapper,
+    apply_gamma: bool,
+    buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl Hdr {
+    /// `config` describes the output of the tonemapping pass.
+    pub fn new(
+        adapter: &wgpu::Adapter,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> Self {
+        let format = if adapter
+            .get_texture_format_features(HDR_FORMAT)
+            .allowed_usages
+            .contains(wgpu::TextureUsages::RENDER_ATTACHMENT)
+        {
+            HDR_FORMAT
+        } else {
+            log::warn!(
+                "{HDR_FORMAT:?} can't be rendered into, falling back to {FALLBACK_FORMAT:?}"
+            );
+            FALLBACK_FORMAT
+        };
+
+        let texture =
+            texture::Texture::create_intermediate_target(device, config, format, "hdr_texture");
+
+        let exposure = 1.0;
+        let tonemapper = Tonemapper::Aces;
+        let apply_gamma = !config.format.is_srgb();
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Tonemap Buffer"),
+            contents: bytemuck::cast_slice(&[Self::uniform(exposure, tonemapper, apply_gamma)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("hdr_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let bind_group = Self::create_bind_group(device, &bind_group_layout, &texture, &buffer);
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Tonemap Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = texture::create_fullscreen_shader(
+            device,
+            "tonemap.wgsl",
+            include_str!("shaders/tonemap.wgsl"),
+        );
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Tonemap Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        Self {
+            format,
+            texture,
+            exposure,
+            tonemapper,
+            apply_gamma,
+            buffer,
+            bind_group_layout,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    fn uniform(exposure: f32, tonemapper: Tonemapper, apply_gamma: bool) -> TonemapUniform {
+        TonemapUniform {
+            exposure,
+            tonemapper: match tonemapper {
+                Tonemapper::Reinhard => 0,
+                Tonemapper::Aces => 1,
+            },
+            apply_gamma: apply_gamma as u32,
+            _padding: 0,
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        texture: &texture::Texture,
+        buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("hdr_bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    /// Format the scene is rendered in, [`HDR_FORMAT`] or
+    /// [`FALLBACK_FORMAT`].
+    pub fn format(&self) -> wgpu::TextureFormat {
+        self.format
+    }
+
+    /// Where the scene should be rendered into.
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.texture.view
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        self.texture = texture::Texture::create_intermediate_target(
+            device,
+            config,
+            self.format,
+            "hdr_texture",
+        );
+        self.bind_group =
+            Self::create_bind_group(device, &self.bind_group_layout, &self.texture, &self.buffer);
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    /// Multiplies the scene colors before tonemapping them.
+    pub fn set_exposure(&mut self, queue: &wgpu::Queue, exposure: f32) {
+        self.exposure = exposure;
+        self.write_uniform(queue);
+    }
+
+    pub fn tonemapper(&self) -> Tonemapper {
+        self.tonemapper
+    }
+
+    pub fn set_tonemapper(&mut self, queue: &wgpu::Queue, tonemapper: Tonemapper) {
+        self.tonemapper = tonemapper;
+        self.write_uniform(queue);
+    }
+
+    fn write_uniform(&self, queue: &wgpu::Queue) {
+        let uniform = Self::uniform(self.exposure, self.tonemapper, self.apply_gamma);
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    /// Records the pass tonemapping the scene into `output`.
+    pub fn tonemap(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Tonemap Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,435 @@
+/*!
+Image-based lighting: the ambient light coming from an environment map.
+
+Lighting a surface with a whole environment is too slow to do per pixel, so
+it's precomputed when the environment is set (the split sum approximation):
+- An irradiance cubemap, the diffuse light received by surfaces facing each
+  direction.
+- A prefiltered cubemap, the environment blurred by the specular lobe of
+  increasing roughnesses along its mip levels.
+- A BRDF lookup table, the scale and bias the specular BRDF applies to the
+  reflectance at normal incidence, which doesn't depend on the environment.
+
+Without an environment map the ambient light is a uniform [`AMBIENT_COLOR`].
+
+The maps are rendered in the HDR format, so on adapters falling back to an 8
+bit format the environment gets clipped to 1.
+*/
+
+use wgpu::util::DeviceExt;
+
+use crate::texture;
+
+/// Width and height of the irradiance cubemap faces, it has no detail to
+/// speak of.
+pub const IRRADIANCE_SIZE: u32 = 32;
+/// Width and height of the first mip of the prefiltered cubemap faces.
+pub const PREFILTERED_SIZE: u32 = 128;
+/// Mip levels of the prefiltered cubemap, going from roughness 0 to 1.
+pub const PREFILTERED_MIP_LEVELS: u32 = 5;
+const BRDF_LUT_SIZE: u32 = 128;
+/// Light coming from every direction when there's no environment map.
+pub const AMBIENT_COLOR: [f32; 3] = [0.1, 0.1, 0.1];
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct EnvironmentUniform {
+    intensity: f32,
+    prefiltered_max_lod: f32,
+    _padding: [f32; 2],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BakeUniform {
+    face: u32,
+    roughness: f32,
+    _padding: [u32; 2],
+}
+
+pub struct Ibl {
+    format: wgpu::TextureFormat,
+    intensity: f32,
+    prefiltered_max_lod: f32,
+    irradiance: texture::Texture,
+    prefiltered: texture::Texture,
+    brdf_lut: texture::Texture,
+    sampler: wgpu::Sampler,
+    buffer: wgpu::Buffer,
+    environment_layout: wgpu::BindGroupLayout,
+    bake_layout: wgpu::BindGroupLayout,
+    irradiance_pipeline: wgpu::RenderPipeline,
+    prefilter_pipeline: wgpu::RenderPipeline,
+}
+
+impl Ibl {
+    /// Starts without an environment map. The maps are rendered in
+    /// `format`, which must be renderable and filterable.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, format: wgpu::TextureFormat) -> Self {
+        let environment_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("ibl_environment_bind_group_layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::Cube,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+            });
+        let bake_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("ibl_bake_bind_group_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+
+        let shader =
+            texture::create_fullscreen_shader(device, "ibl.wgsl", include_str!("shaders/ibl.wgsl"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("IBL Pipeline Layout"),
+            bind_group_layouts: &[&environment_layout, &bake_layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |layout, entry_point| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(&format!("IBL Pipeline ({entry_point})")),
+                layout,
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point,
+                    targets: &[Some(format.into())],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+        let irradiance_pipeline = create_pipeline(Some(&layout), "fs_irradiance");
+        let prefilter_pipeline = create_pipeline(Some(&layout), "fs_prefilter");
+        // Doesn't read any binding.
+        let brdf_pipeline = create_pipeline(None, "fs_brdf");
+
+        let brdf_lut = texture::Texture::create_intermediate_target_sized(
+            device,
+            BRDF_LUT_SIZE,
+            BRDF_LUT_SIZE,
+            format,
+            "brdf_lut_texture",
+        );
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("BRDF LUT Encoder"),
+        });
+        {
+            let mut render_pass = begin_bake_pass(&mut encoder, &brdf_lut.view);
+            render_pass.set_pipeline(&brdf_pipeline);
+            render_pass.draw(0..3, 0..1);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("ibl_sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let (irradiance, prefiltered) = Self::uniform_maps(device, queue);
+        let intensity = 1.0;
+        let prefiltered_max_lod = 0.0;
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Environment Buffer"),
+            contents: bytemuck::cast_slice(&[EnvironmentUniform {
+                intensity,
+                prefiltered_max_lod,
+                _padding: [0.0; 2],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        Self {
+            format,
+            intensity,
+            prefiltered_max_lod,
+            irradiance,
+            prefiltered,
+            brdf_lut,
+            sampler,
+            buffer,
+            environment_layout,
+            bake_layout,
+            irradiance_pipeline,
+            prefilter_pipeline,
+        }
+    }
+
+    /// 1x1 irradiance and prefiltered maps of [`AMBIENT_COLOR`], since a
+    /// uniform environment stays the same however it's blurred.
+    fn uniform_maps(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> (texture::Texture, texture::Texture) {
+        let [r, g, b] = AMBIENT_COLOR;
+        let data = [r, g, b, 1.0].map(half::f16::from_f32).repeat(6);
+        let create = |label| {
+            texture::Texture::create_cubemap_from_data(
+                device,
+                queue,
+                1,
+                wgpu::TextureFormat::Rgba16Float,
+                bytemuck::cast_slice(&data),
+                label,
+            )
+        };
+
+        (create("irradiance_texture"), create("prefiltered_texture"))
+    }
+
+    /// Precomputes the maps for `environment`, a cubemap such as the ones
+    /// made by [`texture::Texture::create_cubemap`], or goes back to the
+    /// uniform ambient light for `None`.
+    ///
+    /// The bind groups made with [`Self::bind_group_entries`] need to be
+    /// recreated afterwards.
+    pub fn set_environment(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        environment: Option<&texture::Texture>,
+    ) {
+        let Some(environment) = environment else {
+            (self.irradiance, self.prefiltered) = Self::uniform_maps(device, queue);
+            self.prefiltered_max_lod = 0.0;
+            self.write_uniform(queue);
+            return;
+        };
+
+        let environment_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ibl_environment_bind_group"),
+            layout: &self.environment_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&environment.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        });
+
+        let irradiance = texture::Texture::create_cube_target(
+            device,
+            IRRADIANCE_SIZE,
+            1,
+            self.format,
+            "irradiance_texture",
+        );
+        let prefiltered = texture::Texture::create_cube_target(
+            device,
+            PREFILTERED_SIZE,
+            PREFILTERED_MIP_LEVELS,
+            self.format,
+            "prefiltered_texture",
+        );
+
+        // Pipeline, target, face, mip level and roughness of every pass
+        let mut passes = Vec::new();
+        for face in 0..6 {
+            passes.push((&self.irradiance_pipeline, &irradiance, face, 0, 0.0));
+        }
+        for mip in 0..PREFILTERED_MIP_LEVELS {
+            let roughness = mip as f32 / (PREFILTERED_MIP_LEVELS - 1) as f32;
+            for face in 0..6 {
+                passes.push((&self.prefilter_pipeline, &prefiltered, face, mip, roughness));
+            }
+        }
+
+        // One uniform per pass, at offsets aligned for dynamic uniform
+        // buffers.
+        let stride = device.limits().min_uniform_buffer_offset_alignment as usize;
+        let mut contents = vec![0; passes.len() * stride];
+        for (i, &(_, _, face, _, roughness)) in passes.iter().enumerate() {
+            let uniform = BakeUniform {
+                face,
+                roughness,
+                _padding: [0; 2],
+            };
+            contents[i * stride..][..std::mem::size_of::<BakeUniform>()]
+                .copy_from_slice(bytemuck::bytes_of(&uniform));
+        }
+        let bake_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("IBL Bake Buffer"),
+            contents: &contents,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let bake_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ibl_bake_bind_group"),
+            layout: &self.bake_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &bake_buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(std::mem::size_of::<BakeUniform>() as u64),
+                }),
+            }],
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("IBL Encoder"),
+        });
+        for (i, &(pipeline, target, face, mip, _)) in passes.iter().enumerate() {
+            let view = target.texture.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("ibl_face_view"),
+                dimension: Some(wgpu::TextureViewDimension::D2),
+                base_mip_level: mip,
+                mip_level_count: Some(1),
+                base_array_layer: face,
+                array_layer_count: Some(1),
+                ..Default::default()
+            });
+            let mut render_pass = begin_bake_pass(&mut encoder, &view);
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, &environment_bind_group, &[]);
+            render_pass.set_bind_group(1, &bake_bind_group, &[(i * stride) as u32]);
+            render_pass.draw(0..3, 0..1);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+
+        self.irradiance = irradiance;
+        self.prefiltered = prefiltered;
+        self.prefiltered_max_lod = (PREFILTERED_MIP_LEVELS - 1) as f32;
+        self.write_uniform(queue);
+    }
+
+    pub fn intensity(&self) -> f32 {
+        self.intensity
+    }
+
+    /// Scales the ambient light.
+    pub fn set_intensity(&mut self, queue: &wgpu::Queue, intensity: f32) {
+        self.intensity = intensity;
+        self.write_uniform(queue);
+    }
+
+    fn write_uniform(&self, queue: &wgpu::Queue) {
+        let uniform = EnvironmentUniform {
+            intensity: self.intensity,
+            prefiltered_max_lod: self.prefiltered_max_lod,
+            _padding: [0.0; 2],
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    /// Layout of the bindings the lit shaders read the maps from, starting
+    /// at `first_binding`: the irradiance cubemap, the prefiltered cubemap,
+    /// the BRDF lookup table, their sampler and the intensity uniform.
+    pub fn bind_group_layout_entries(first_binding: u32) -> [wgpu::BindGroupLayoutEntry; 5] {
+        let texture = |binding, view_dimension| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension,
+                multisampled: false,
+            },
+            count: None,
+        };
+
+        [
+            texture(first_binding, wgpu::TextureViewDimension::Cube),
+            texture(first_binding + 1, wgpu::TextureViewDimension::Cube),
+            texture(first_binding + 2, wgpu::TextureViewDimension::D2),
+            wgpu::BindGroupLayoutEntry {
+                binding: first_binding + 3,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: first_binding + 4,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ]
+    }
+
+    /// Resources for the entries of [`Self::bind_group_layout_entries`].
+    pub fn bind_group_entries(&self, first_binding: u32) -> [wgpu::BindGroupEntry<'_>; 5] {
+        [
+            wgpu::BindGroupEntry {
+                binding: first_binding,
+                resource: wgpu::BindingResource::TextureView(&self.irradiance.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: first_binding + 1,
+                resource: wgpu::BindingResource::TextureView(&self.prefiltered.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: first_binding + 2,
+                resource: wgpu::BindingResource::TextureView(&self.brdf_lut.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: first_binding + 3,
+                resource: wgpu::BindingResource::Sampler(&self.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: first_binding + 4,
+                resource: self.buffer.as_entire_binding(),
+            },
+        ]
+    }
+}
+
+fn begin_bake_pass<'a>(
+    encoder: &'a mut wgpu::CommandEncoder,
+    view: &'a wgpu::TextureView,
+) -> wgpu::RenderPass<'a> {
+    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("IBL Bake Pass"),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                store: true,
+            },
+        })],
+        depth_stencil_attachment: None,
+    })
+}
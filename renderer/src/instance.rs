@@ -1,30 +1,55 @@
+use cgmath::prelude::*;
+
 pub struct Instance {
     pub position: cgmath::Vector3<f32>,
     pub rotation: cgmath::Quaternion<f32>,
+    /// Applied before the rotation, can differ per axis.
+    pub scale: cgmath::Vector3<f32>,
 }
 
 #[repr(C)]
-#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct InstanceRaw {
     model: [[f32; 4]; 4],
+    /// Inverse transpose of the upper 3x3 of `model`, takes normals to
+    /// world space.
+    normal: [[f32; 3]; 3],
 }
 
 impl Instance {
     pub fn to_raw(&self) -> InstanceRaw {
-        InstanceRaw {
-            model: (cgmath::Matrix4::from_translation(self.position)
-                * cgmath::Matrix4::from(self.rotation))
-            .into(),
-        }
+        InstanceRaw::from_matrix(
+            cgmath::Matrix4::from_translation(self.position)
+                * cgmath::Matrix4::from(self.rotation)
+                * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z),
+        )
     }
 }
 
 impl InstanceRaw {
-    const ATTRIBS: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![
+    /// Instance placed by the `model` to world space transform.
+    pub fn from_matrix(model: cgmath::Matrix4<f32>) -> Self {
+        let linear =
+            cgmath::Matrix3::from_cols(model.x.truncate(), model.y.truncate(), model.z.truncate());
+        // Degenerate scales flatten the model, any normal will do then.
+        let normal = linear
+            .invert()
+            .map_or(cgmath::Matrix3::identity(), |inverse| inverse.transpose());
+
+        Self {
+            model: model.into(),
+            normal: normal.into(),
+        }
+    }
+
+    const ATTRIBS: [wgpu::VertexAttribute; 7] = wgpu::vertex_attr_array![
         8 => Float32x4,
         9 => Float32x4,
         10 => Float32x4,
-        11 => Float32x4
+        11 => Float32x4,
+        12 => Float32x3,
+        13 => Float32x3,
+        14 => Float32x3
     ];
 
     pub fn desc() -> wgpu::VertexBufferLayout<'static> {
@@ -36,3 +61,216 @@ impl InstanceRaw {
         }
     }
 }
+
+/// Changed instances closer than this are written together, one bigger
+/// write is cheaper than many small ones.
+const MAX_WRITE_GAP: usize = 16;
+
+/// Vertex buffer of instances kept in sync with a list that changes from
+/// frame to frame.
+///
+/// A copy of the instances stays on the CPU so updates only write the ranges
+/// that differ. The buffer doubles in size when the instances don't fit
+/// anymore, and never shrinks.
+pub struct InstanceBuffer {
+    buffer: wgpu::Buffer,
+    usage: wgpu::BufferUsages,
+    capacity: usize,
+    instances: Vec<InstanceRaw>,
+}
+
+impl InstanceBuffer {
+    pub fn new(device: &wgpu::Device, capacity: usize) -> Self {
+        Self::with_usage(device, capacity, wgpu::BufferUsages::VERTEX)
+    }
+
+    /// Buffer also usable as `usage`, e.g. `STORAGE` to read the instances
+    /// from a compute shader.
+    pub fn with_usage(device: &wgpu::Device, capacity: usize, usage: wgpu::BufferUsages) -> Self {
+        let capacity = capacity.max(1);
+        let usage = usage | wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST;
+        Self {
+            buffer: Self::create_buffer(device, usage, capacity),
+            usage,
+            capacity,
+            instances: Vec::new(),
+        }
+    }
+
+    fn create_buffer(
+        device: &wgpu::Device,
+        usage: wgpu::BufferUsages,
+        capacity: usize,
+    ) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Instance Buffer"),
+            size: (capacity * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+            usage,
+            mapped_at_creation: false,
+        })
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    /// Instances the buffer holds before it has to grow.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> u32 {
+        self.instances.len() as u32
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    /// Makes the buffer hold `instances`. Returns the number of instances
+    /// written, all of them when the buffer had to grow.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        instances: &[InstanceRaw],
+    ) -> usize {
+        if instances.len() > self.capacity {
+            self.capacity = instances.len().next_power_of_two();
+            self.buffer = Self::create_buffer(device, self.usage, self.capacity);
+            self.instances.clear();
+        }
+
+        let mut written = 0;
+        for range in changed_ranges(&self.instances, instances) {
+            queue.write_buffer(
+                &self.buffer,
+                (range.start * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+                bytemuck::cast_slice(&instances[range.clone()]),
+            );
+            written += range.len();
+        }
+        self.instances.clear();
+        self.instances.extend_from_slice(instances);
+
+        written
+    }
+}
+
+/// Ranges of `new` that differ from `old`, including what `old` doesn't
+/// have.
+fn changed_ranges(old: &[InstanceRaw], new: &[InstanceRaw]) -> Vec<std::ops::Range<usize>> {
+    let mut ranges: Vec<std::ops::Range<usize>> = Vec::new();
+    for (i, instance) in new.iter().enumerate() {
+        if old.get(i) == Some(instance) {
+            continue;
+        }
+        match ranges.last_mut() {
+            Some(last) if i - last.end <= MAX_WRITE_GAP => last.end = i + 1,
+            _ => ranges.push(i..i + 1),
+        }
+    }
+
+    ranges
+}
+
+#[cfg(test)]
+// The expected ranges are lists of ranges, not ranges collected into lists.
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use super::*;
+
+    /// Instances told apart by their translation.
+    fn instances(xs: impl IntoIterator<Item = usize>) -> Vec<InstanceRaw> {
+        xs.into_iter()
+            .map(|x| {
+                InstanceRaw::from_matrix(cgmath::Matrix4::from_translation(cgmath::vec3(
+                    x as f32, 0.0, 0.0,
+                )))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unchanged_instances_write_nothing() {
+        let old = instances(0..8);
+        assert!(changed_ranges(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn nearby_changes_are_merged() {
+        let old = instances(0..100);
+        let mut new = old.clone();
+        for i in [2, 3 + MAX_WRITE_GAP, 50, 51 + MAX_WRITE_GAP + 1] {
+            new[i] = instances([1000 + i]).pop().unwrap();
+        }
+
+        // Up to MAX_WRITE_GAP unchanged instances in between still merge,
+        // one more splits the write.
+        assert_eq!(
+            changed_ranges(&old, &new),
+            [
+                2..4 + MAX_WRITE_GAP,
+                50..51,
+                52 + MAX_WRITE_GAP..53 + MAX_WRITE_GAP
+            ]
+        );
+    }
+
+    #[test]
+    fn growing_writes_the_new_instances() {
+        let old = instances(0..8);
+        let new = instances(0..12);
+        assert_eq!(changed_ranges(&old, &new), [8..12]);
+        assert_eq!(changed_ranges(&[], &new), [0..12]);
+    }
+
+    #[test]
+    fn shrinking_writes_nothing_for_a_prefix() {
+        let old = instances(0..12);
+        assert!(changed_ranges(&old, &old[..8]).is_empty());
+
+        let mut new = old[..8].to_vec();
+        new[7] = instances([100]).pop().unwrap();
+        assert_eq!(changed_ranges(&old, &new), [7..8]);
+    }
+
+    #[test]
+    fn normal_matrix_is_the_inverse_transpose() {
+        let rotation = cgmath::Matrix4::from_angle_y(cgmath::Deg(30.0));
+        let model = cgmath::Matrix4::from_translation(cgmath::vec3(1.0, 2.0, 3.0))
+            * rotation
+            * cgmath::Matrix4::from_nonuniform_scale(2.0, 4.0, 0.5);
+        let raw = InstanceRaw::from_matrix(model);
+
+        let linear =
+            cgmath::Matrix3::from_cols(model.x.truncate(), model.y.truncate(), model.z.truncate());
+        let normal = cgmath::Matrix3::from(raw.normal);
+        // The inverse transpose times the transpose is the identity.
+        let product = normal.transpose() * linear;
+        let identity = cgmath::Matrix3::<f32>::identity();
+        for (column, expected) in [product.x, product.y, product.z]
+            .into_iter()
+            .zip([identity.x, identity.y, identity.z])
+        {
+            assert!((column - expected).magnitude() < 1e-5, "{product:?}");
+        }
+
+        // A normal of the plane x = y stays perpendicular to it once scaled.
+        let along_plane = linear * cgmath::vec3(1.0, 1.0, 0.0);
+        let plane_normal = normal * cgmath::vec3(1.0, -1.0, 0.0);
+        assert!(along_plane.dot(plane_normal).abs() < 1e-5);
+    }
+
+    #[test]
+    fn zero_scale_falls_back_to_the_identity() {
+        let model = cgmath::Matrix4::from_translation(cgmath::vec3(1.0, 2.0, 3.0))
+            * cgmath::Matrix4::from_nonuniform_scale(1.0, 0.0, 1.0);
+        let raw = InstanceRaw::from_matrix(model);
+        assert_eq!(
+            cgmath::Matrix3::from(raw.normal),
+            cgmath::Matrix3::identity()
+        );
+        assert_eq!(cgmath::Matrix4::from(raw.model), model);
+    }
+}
@@ -1,18 +1,32 @@
 //! Wgpu renderer implemented based on https://sotrh.github.io/learn-wgpu/
-mod assets;
-mod camera;
-mod instance;
-mod model;
+pub mod assets;
+pub mod bloom;
+pub mod camera;
+pub mod capture;
+pub mod culling;
+pub mod gpu_culling;
+pub mod hdr;
+pub mod ibl;
+pub mod instance;
+pub mod light;
+pub mod lod;
+pub mod model;
+pub mod post;
+pub mod scene;
+pub mod scene_file;
+pub mod shadow;
+pub mod simplify;
+pub mod skybox;
 mod state;
-mod texture;
+pub mod texture;
 
-use state::State;
+pub use state::{State, Unsupported};
 
 use log::debug;
 use winit::{
     event::*,
     event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
+    window::{Window, WindowBuilder},
 };
 
 #[cfg(target_arch = "wasm32")]
@@ -20,6 +34,12 @@ use wasm_bindgen::prelude::*;
 
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen(start))]
 pub async fn run() {
+    run_scene(None).await
+}
+
+/// Like [`run`], showing the scene file `scene` (see [`scene_file`]) instead
+/// of the default scene when given.
+pub async fn run_scene(scene: Option<&str>) {
     cfg_if::cfg_if! {
         if #[cfg(target_arch = "wasm32")] {
             std::panic::set_hook(Box::new(console_error_panic_hook::hook));
@@ -88,13 +108,18 @@ pub async fn run() {
     debug!("Succesfully configured window.");
 
     let mut state = State::new(window).await;
+    if let Some(scene) = scene {
+        if let Err(e) = state.load_scene(scene).await {
+            log::error!("Couldn't load scene {scene}: {e:?}");
+        }
+    }
 
     event_loop.run(move |event, _, control_flow| match event {
         Event::WindowEvent {
             window_id,
             ref event,
-        } if window_id == state.window().id() => state.input(event, control_flow),
-        Event::RedrawRequested(window_id) if window_id == state.window().id() => {
+        } if state.window().map(Window::id) == Some(window_id) => state.input(event, control_flow),
+        Event::RedrawRequested(window_id) if state.window().map(Window::id) == Some(window_id) => {
             state.update();
             match state.render() {
                 Ok(_) => {}
@@ -103,7 +128,30 @@ pub async fn run() {
                 Err(e) => log::error!("{e:?}"),
             }
         }
-        Event::MainEventsCleared => state.window().request_redraw(),
+        Event::MainEventsCleared => {
+            if let Some(window) = state.window() {
+                window.request_redraw()
+            }
+        }
         _ => {}
     });
 }
+
+/// Renders the scene offscreen with the given pipeline and saves it as a
+/// PNG, used to regenerate the images in `renderer/imgs/`.
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn screenshot(
+    path: &str,
+    width: u32,
+    height: u32,
+    pipeline: usize,
+) -> anyhow::Result<()> {
+    // A library caller may have set up logging already.
+    let _ = env_logger::try_init();
+
+    let mut state = State::new_headless(width, height).await?;
+    state.set_pipeline(pipeline);
+    state.update();
+    let image = state.render_to_image().await?;
+    capture::save_png(&image, path)
+}
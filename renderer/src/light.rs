@@ -0,0 +1,351 @@
+/*!
+Scene lights and the GPU buffer holding them.
+
+Lights live in a storage buffer that grows as needed. WebGL2 has no storage
+buffers, so there they go into a uniform array of [`MAX_UNIFORM_LIGHTS`]
+instead. Both use the same memory layout, only the WGSL declaration of the
+binding changes (see [`Lights::shader_source`]).
+*/
+
+use anyhow::{anyhow, Result};
+
+/// Lights that fit in the uniform fallback, 16 + 64 * 16 bytes stays well
+/// under the WebGL2 uniform buffer size limit.
+pub const MAX_UNIFORM_LIGHTS: usize = 16;
+
+/// Bytes before the light array: the light count padded to 16 bytes.
+const HEADER_SIZE: wgpu::BufferAddress = 16;
+
+const LIGHT_SHADER: &str = include_str!("shaders/lights.wgsl");
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightKind {
+    /// Shines in every direction, fading out until `range`.
+    Point {
+        position: cgmath::Vector3<f32>,
+        range: f32,
+    },
+    /// Infinitely far away light, like the sun.
+    Directional { direction: cgmath::Vector3<f32> },
+    /// Point light limited to a cone. Fully lit inside `inner_angle`,
+    /// fading out until `outer_angle`.
+    Spot {
+        position: cgmath::Vector3<f32>,
+        direction: cgmath::Vector3<f32>,
+        range: f32,
+        inner_angle: cgmath::Deg<f32>,
+        outer_angle: cgmath::Deg<f32>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Light {
+    pub kind: LightKind,
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LightRaw {
+    position: [f32; 3],
+    kind: u32,
+    color: [f32; 3],
+    intensity: f32,
+    direction: [f32; 3],
+    range: f32,
+    inner_cone_cos: f32,
+    outer_cone_cos: f32,
+    // Uniform arrays need a 16 byte stride
+    _padding: [f32; 2],
+}
+
+impl Light {
+    pub fn point(
+        position: cgmath::Vector3<f32>,
+        range: f32,
+        color: [f32; 3],
+        intensity: f32,
+    ) -> Self {
+        Self {
+            kind: LightKind::Point { position, range },
+            color,
+            intensity,
+        }
+    }
+
+    pub fn directional(direction: cgmath::Vector3<f32>, color: [f32; 3], intensity: f32) -> Self {
+        Self {
+            kind: LightKind::Directional { direction },
+            color,
+            intensity,
+        }
+    }
+
+    /// Position of point and spot lights, `None` for directional ones.
+    pub fn position_mut(&mut self) -> Option<&mut cgmath::Vector3<f32>> {
+        match &mut self.kind {
+            LightKind::Point { position, .. } | LightKind::Spot { position, .. } => Some(position),
+            LightKind::Directional { .. } => None,
+        }
+    }
+
+    pub fn to_raw(&self) -> LightRaw {
+        use cgmath::{Angle, InnerSpace, Zero};
+
+        let zero = cgmath::Vector3::zero();
+        let (kind, position, direction, range, inner_angle, outer_angle) = match self.kind {
+            LightKind::Point { position, range } => {
+                (0, position, zero, range, cgmath::Deg(0.0), cgmath::Deg(0.0))
+            }
+            LightKind::Directional { direction } => (
+                1,
+                zero,
+                direction.normalize(),
+                0.0,
+                cgmath::Deg(0.0),
+                cgmath::Deg(0.0),
+            ),
+            LightKind::Spot {
+                position,
+                direction,
+                range,
+                inner_angle,
+                outer_angle,
+            } => (
+                2,
+                position,
+                direction.normalize(),
+                range,
+                inner_angle,
+                outer_angle,
+            ),
+        };
+
+        LightRaw {
+            position: position.into(),
+            kind,
+            color: self.color,
+            intensity: self.intensity,
+            direction: direction.into(),
+            range,
+            inner_cone_cos: inner_angle.cos(),
+            outer_cone_cos: outer_angle.cos(),
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+/// Handle to a light added to [`Lights`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LightId(u32);
+
+pub struct Lights {
+    lights: Vec<(LightId, Light)>,
+    next_id: u32,
+    dirty: bool,
+    uses_storage: bool,
+    capacity: usize,
+    buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Lights {
+    /// `uses_storage` should be false when storage buffers aren't available
+    /// in vertex and fragment shaders, e.g. on WebGL2.
+    pub fn new(device: &wgpu::Device, uses_storage: bool) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("light_bind_group_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                count: None,
+                ty: wgpu::BindingType::Buffer {
+                    ty: if uses_storage {
+                        wgpu::BufferBindingType::Storage { read_only: true }
+                    } else {
+                        wgpu::BufferBindingType::Uniform
+                    },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+            }],
+        });
+
+        let capacity = if uses_storage { 4 } else { MAX_UNIFORM_LIGHTS };
+        let (buffer, bind_group) =
+            Self::create_buffer(device, &bind_group_layout, uses_storage, capacity);
+
+        Self {
+            lights: Vec::new(),
+            next_id: 0,
+            dirty: true,
+            uses_storage,
+            capacity,
+            buffer,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    fn create_buffer(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uses_storage: bool,
+        capacity: usize,
+    ) -> (wgpu::Buffer, wgpu::BindGroup) {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Light Buffer"),
+            size: HEADER_SIZE + (capacity * std::mem::size_of::<LightRaw>()) as wgpu::BufferAddress,
+            usage: if uses_storage {
+                wgpu::BufferUsages::STORAGE
+            } else {
+                wgpu::BufferUsages::UNIFORM
+            } | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("light_bind_group"),
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+
+        (buffer, bind_group)
+    }
+
+    /// Fails when using the uniform fallback and it's already full.
+    pub fn add(&mut self, light: Light) -> Result<LightId> {
+        if !self.uses_storage && self.lights.len() >= MAX_UNIFORM_LIGHTS {
+            return Err(anyhow!(
+                "Can't have more than {MAX_UNIFORM_LIGHTS} lights without storage buffers."
+            ));
+        }
+
+        let id = LightId(self.next_id);
+        self.next_id += 1;
+        self.lights.push((id, light));
+        self.dirty = true;
+
+        Ok(id)
+    }
+
+    /// Replaces every light with `lights`. Fails without changing anything
+    /// when they don't all fit, like [`Self::add`].
+    pub fn replace_all(&mut self, lights: Vec<Light>) -> Result<Vec<LightId>> {
+        if !self.uses_storage && lights.len() > MAX_UNIFORM_LIGHTS {
+            return Err(anyhow!(
+                "Can't have more than {MAX_UNIFORM_LIGHTS} lights without storage buffers."
+            ));
+        }
+
+        self.lights.clear();
+        lights.into_iter().map(|light| self.add(light)).collect()
+    }
+
+    pub fn remove(&mut self, id: LightId) -> Option<Light> {
+        let index = self
+            .lights
+            .iter()
+            .position(|(light_id, _)| *light_id == id)?;
+        self.dirty = true;
+
+        Some(self.lights.remove(index).1)
+    }
+
+    pub fn get(&self, id: LightId) -> Option<&Light> {
+        self.lights
+            .iter()
+            .find(|(light_id, _)| *light_id == id)
+            .map(|(_, light)| light)
+    }
+
+    pub fn get_mut(&mut self, id: LightId) -> Option<&mut Light> {
+        self.dirty = true;
+        self.lights
+            .iter_mut()
+            .find(|(light_id, _)| *light_id == id)
+            .map(|(_, light)| light)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (LightId, &Light)> {
+        self.lights.iter().map(|(id, light)| (*id, light))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (LightId, &mut Light)> {
+        self.dirty = true;
+        self.lights.iter_mut().map(|(id, light)| (*id, light))
+    }
+
+    pub fn len(&self) -> usize {
+        self.lights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    /// Uploads the lights if they changed since the last call, growing the
+    /// storage buffer when they don't fit anymore.
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+
+        if self.lights.len() > self.capacity {
+            self.capacity = self.lights.len().next_power_of_two();
+            (self.buffer, self.bind_group) = Self::create_buffer(
+                device,
+                &self.bind_group_layout,
+                self.uses_storage,
+                self.capacity,
+            );
+        }
+
+        let header = [self.lights.len() as u32, 0, 0, 0];
+        let lights: Vec<_> = self
+            .lights
+            .iter()
+            .map(|(_, light)| light.to_raw())
+            .collect();
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&header));
+        if !lights.is_empty() {
+            queue.write_buffer(&self.buffer, HEADER_SIZE, bytemuck::cast_slice(&lights));
+        }
+    }
+
+    /// Prepends the `Light` definitions and the `lights` binding at `group`
+    /// to `shader`.
+    pub fn shader_source(&self, shader: &str, group: u32) -> String {
+        let (address_space, array) = if self.uses_storage {
+            ("storage, read", "array<Light>".to_string())
+        } else {
+            ("uniform", format!("array<Light, {MAX_UNIFORM_LIGHTS}>"))
+        };
+
+        format!(
+            "{LIGHT_SHADER}
+struct Lights {{
+    count: u32,
+    lights: {array},
+}};
+@group({group}) @binding(0)
+var<{address_space}> lights: Lights;
+
+{shader}"
+        )
+    }
+}
@@ -0,0 +1,117 @@
+/*!
+Levels of detail: coarser versions of a model's meshes, drawn instead of the
+full ones for instances that are small on screen or far from the camera.
+
+The model's own meshes are level 0. Every [`Lod`] added after them takes over
+past its [`LodThreshold`], so the levels go from the most to the least
+detailed. Instances only switch to a coarser level once they're
+[`HYSTERESIS`] past its threshold, and only switch back once they're as far
+on the other side, so an instance sitting right on a threshold doesn't pop
+between two levels from frame to frame.
+*/
+
+use cgmath::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{camera, culling, model};
+
+/// Fraction of a threshold an instance has to be past it to switch levels.
+pub const HYSTERESIS: f32 = 0.1;
+/// Levels a model can have, including its own meshes.
+pub const MAX_LEVELS: usize = 4;
+
+/// When a [`Lod`] is used instead of the levels before it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum LodThreshold {
+    /// Once the instance's bounding sphere covers less than this fraction of
+    /// the viewport height.
+    ScreenSize(f32),
+    /// Once the instance's center is further than this from
+    /// [`camera::Camera::eye`].
+    Distance(f32),
+}
+
+impl LodThreshold {
+    /// Whether an instance with `metrics` is past the threshold, moved
+    /// further away by `margin` times the threshold (closer when negative).
+    pub fn is_passed(&self, metrics: &LodMetrics, margin: f32) -> bool {
+        match *self {
+            Self::ScreenSize(size) => metrics.screen_size < size * (1.0 - margin),
+            Self::Distance(distance) => metrics.distance > distance * (1.0 + margin),
+        }
+    }
+
+    /// Whether a level with this threshold can come after one with
+    /// `previous`: both of the same kind, and further away or smaller on
+    /// screen. Levels out of order would never be selected.
+    pub fn follows(&self, previous: &LodThreshold) -> bool {
+        match (*previous, *self) {
+            (Self::Distance(previous), Self::Distance(distance)) => distance > previous,
+            (Self::ScreenSize(previous), Self::ScreenSize(size)) => size < previous,
+            _ => false,
+        }
+    }
+}
+
+/// Meshes of a level after the most detailed one, using the materials of
+/// the model.
+pub struct Lod {
+    pub meshes: Vec<model::Mesh>,
+    pub threshold: LodThreshold,
+}
+
+/// How an instance looks from the camera.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LodMetrics {
+    /// Fraction of the viewport height covered by the bounding sphere.
+    pub screen_size: f32,
+    /// From [`camera::Camera::eye`] to the center of the bounds.
+    pub distance: f32,
+}
+
+impl LodMetrics {
+    /// Metrics of the model `bounds` placed by `transform`.
+    pub fn new(
+        camera: &camera::Camera,
+        bounds: &culling::Aabb,
+        transform: &cgmath::Matrix4<f32>,
+    ) -> Self {
+        let center = transform.transform_point(bounds.center());
+        let scale = [transform.x, transform.y, transform.z]
+            .iter()
+            .map(|axis| axis.truncate().magnitude())
+            .fold(0.0, f32::max);
+        let radius = bounds.extents().magnitude() * scale;
+
+        let distance = camera.eye.distance(center);
+        let half_height = (cgmath::Deg(camera.fovy) / 2.0).tan() * distance;
+
+        Self {
+            screen_size: radius / half_height.max(f32::EPSILON),
+            distance,
+        }
+    }
+}
+
+/// Level an instance with `metrics` uses, given the thresholds of the levels
+/// after the first and the level it used so far.
+pub fn select_level(
+    thresholds: impl IntoIterator<Item = LodThreshold>,
+    metrics: &LodMetrics,
+    current: usize,
+) -> usize {
+    thresholds
+        .into_iter()
+        .enumerate()
+        .take_while(|(i, threshold)| {
+            // Getting coarser takes going further past the threshold than
+            // staying as coarse does.
+            let margin = if i + 1 > current {
+                HYSTERESIS
+            } else {
+                -HYSTERESIS
+            };
+            threshold.is_passed(metrics, margin)
+        })
+        .count()
+}
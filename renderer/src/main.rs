@@ -1,5 +1,62 @@
-use renderer::run;
+use renderer::{run, run_scene, screenshot, simplify};
+
+const SCREENSHOT_SIZE: (u32, u32) = (800, 600);
 
 fn main() {
-    pollster::block_on(run());
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.as_slice() {
+        [flag, path] if flag == "--screenshot" => screenshot_or_exit(path, 0),
+        [flag, path, pipeline] if flag == "--screenshot" => match pipeline.parse() {
+            Ok(pipeline) => screenshot_or_exit(path, pipeline),
+            Err(_) => exit_with_usage(),
+        },
+        [flag, scene] if flag == "--scene" => pollster::block_on(run_scene(Some(scene))),
+        [command, file_name, ratios @ ..] if command == "simplify" && !ratios.is_empty() => {
+            simplify_or_exit(file_name, ratios)
+        }
+        [] => pollster::block_on(run()),
+        _ => exit_with_usage(),
+    }
+}
+
+fn exit_with_usage() -> ! {
+    eprintln!("Usage: renderer [--scene <scene.ron|scene.json>]");
+    eprintln!("       renderer [--screenshot <path.png> [pipeline]]");
+    eprintln!("       renderer simplify <model.obj> <ratio>...");
+    std::process::exit(2);
+}
+
+fn screenshot_or_exit(path: &str, pipeline: usize) {
+    let (width, height) = SCREENSHOT_SIZE;
+    if let Err(e) = pollster::block_on(screenshot(path, width, height, pipeline)) {
+        eprintln!("Couldn't take screenshot: {e:?}");
+        std::process::exit(1);
+    }
+}
+
+fn simplify_or_exit(file_name: &str, ratios: &[String]) {
+    let ratios: Option<Vec<f32>> = ratios
+        .iter()
+        .map(|ratio| {
+            ratio
+                .parse()
+                .ok()
+                .filter(|ratio| (0.0..=1.0).contains(ratio))
+        })
+        .collect();
+    let Some(ratios) = ratios else {
+        eprintln!("Ratios must be numbers from 0 to 1.");
+        exit_with_usage();
+    };
+    match simplify::simplify_asset(file_name, &ratios) {
+        Ok(paths) => {
+            for path in paths {
+                println!("Wrote {}", path.display());
+            }
+        }
+        Err(e) => {
+            eprintln!("Couldn't simplify {file_name}: {e:?}");
+            std::process::exit(1);
+        }
+    }
 }
@@ -1,6 +1,8 @@
 use std::ops::Range;
 
-use crate::texture;
+use wgpu::util::DeviceExt;
+
+use crate::{culling, gpu_culling, lod, texture};
 
 pub trait Vertex {
     fn desc() -> wgpu::VertexBufferLayout<'static>;
@@ -12,21 +14,70 @@ pub trait DrawModel<'a> {
         mesh: &'a Mesh,
         material: &'a Material,
         camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
     );
     fn draw_mesh_instanced(
         &mut self,
         mesh: &'a Mesh,
         material: &'a Material,
         camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
         instances: Range<u32>,
     );
-    fn draw_model(&mut self, model: &'a Model, camera_bind_group: &'a wgpu::BindGroup);
+    fn draw_model(
+        &mut self,
+        model: &'a Model,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
     fn draw_model_instanced(
         &mut self,
         model: &'a Model,
         camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+        instances: Range<u32>,
+    );
+    /// Draws the meshes of the level of detail `level` of `model`, see
+    /// [`Model::level_meshes`].
+    fn draw_model_level_instanced(
+        &mut self,
+        model: &'a Model,
+        level: usize,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
         instances: Range<u32>,
     );
+    /// Draws `mesh` with the arguments at `indirect_offset` in
+    /// `indirect_buffer`, e.g. written by [`crate::gpu_culling`].
+    fn draw_mesh_indirect(
+        &mut self,
+        mesh: &'a Mesh,
+        material: &'a Material,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+        indirect_buffer: &'a wgpu::Buffer,
+        indirect_offset: wgpu::BufferAddress,
+    );
+    /// Draws every mesh with its own arguments in `indirect_buffer`, one
+    /// [`crate::gpu_culling::DRAW_ARGS_SIZE`] after the other.
+    fn draw_model_indirect(
+        &mut self,
+        model: &'a Model,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+        indirect_buffer: &'a wgpu::Buffer,
+    );
+    /// Like [`Self::draw_model_indirect`] for the meshes of `level`, with
+    /// the arguments of the first one at `indirect_offset`.
+    fn draw_model_level_indirect(
+        &mut self,
+        model: &'a Model,
+        level: usize,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+        indirect_buffer: &'a wgpu::Buffer,
+        indirect_offset: wgpu::BufferAddress,
+    );
 }
 
 impl<'a, 'b> DrawModel<'b> for wgpu::RenderPass<'a>
@@ -38,8 +89,9 @@ where
         mesh: &'b Mesh,
         material: &'b Material,
         camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
     ) {
-        self.draw_mesh_instanced(mesh, material, camera_bind_group, 0..1);
+        self.draw_mesh_instanced(mesh, material, camera_bind_group, light_bind_group, 0..1);
     }
 
     fn draw_mesh_instanced(
@@ -47,30 +99,189 @@ where
         mesh: &'b Mesh,
         material: &'b Material,
         camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
         instances: Range<u32>,
     ) {
         self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
         self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
         self.set_bind_group(0, &material.bind_group, &[]);
         self.set_bind_group(1, camera_bind_group, &[]);
+        self.set_bind_group(2, light_bind_group, &[]);
         self.draw_indexed(0..mesh.num_elements, 0, instances);
     }
 
-    fn draw_model(&mut self, model: &'b Model, camera_bind_group: &'b wgpu::BindGroup) {
-        self.draw_model_instanced(model, camera_bind_group, 0..1);
+    fn draw_model(
+        &mut self,
+        model: &'b Model,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        self.draw_model_instanced(model, camera_bind_group, light_bind_group, 0..1);
     }
 
     fn draw_model_instanced(
         &mut self,
         model: &'b Model,
         camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
         instances: Range<u32>,
     ) {
-        model.meshes.iter().for_each(|mesh| {
+        self.draw_model_level_instanced(model, 0, camera_bind_group, light_bind_group, instances);
+    }
+
+    fn draw_model_level_instanced(
+        &mut self,
+        model: &'b Model,
+        level: usize,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+        instances: Range<u32>,
+    ) {
+        model.level_meshes(level).iter().for_each(|mesh| {
             self.draw_mesh_instanced(
                 mesh,
                 &model.materials[mesh.material],
                 camera_bind_group,
+                light_bind_group,
+                instances.clone(),
+            );
+        })
+    }
+
+    fn draw_mesh_indirect(
+        &mut self,
+        mesh: &'b Mesh,
+        material: &'b Material,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+        indirect_buffer: &'b wgpu::Buffer,
+        indirect_offset: wgpu::BufferAddress,
+    ) {
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_bind_group(0, &material.bind_group, &[]);
+        self.set_bind_group(1, camera_bind_group, &[]);
+        self.set_bind_group(2, light_bind_group, &[]);
+        self.draw_indexed_indirect(indirect_buffer, indirect_offset);
+    }
+
+    fn draw_model_indirect(
+        &mut self,
+        model: &'b Model,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+        indirect_buffer: &'b wgpu::Buffer,
+    ) {
+        self.draw_model_level_indirect(
+            model,
+            0,
+            camera_bind_group,
+            light_bind_group,
+            indirect_buffer,
+            0,
+        );
+    }
+
+    fn draw_model_level_indirect(
+        &mut self,
+        model: &'b Model,
+        level: usize,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+        indirect_buffer: &'b wgpu::Buffer,
+        indirect_offset: wgpu::BufferAddress,
+    ) {
+        for (i, mesh) in model.level_meshes(level).iter().enumerate() {
+            self.draw_mesh_indirect(
+                mesh,
+                &model.materials[mesh.material],
+                camera_bind_group,
+                light_bind_group,
+                indirect_buffer,
+                indirect_offset + i as wgpu::BufferAddress * gpu_culling::DRAW_ARGS_SIZE,
+            );
+        }
+    }
+}
+
+/// Draws models as light markers, without materials.
+pub trait DrawLight<'a> {
+    fn draw_light_mesh(
+        &mut self,
+        mesh: &'a Mesh,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
+    fn draw_light_mesh_instanced(
+        &mut self,
+        mesh: &'a Mesh,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+        instances: Range<u32>,
+    );
+    fn draw_light_model(
+        &mut self,
+        model: &'a Model,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
+    fn draw_light_model_instanced(
+        &mut self,
+        model: &'a Model,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+        instances: Range<u32>,
+    );
+}
+
+impl<'a, 'b> DrawLight<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_light_mesh(
+        &mut self,
+        mesh: &'b Mesh,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        self.draw_light_mesh_instanced(mesh, camera_bind_group, light_bind_group, 0..1);
+    }
+
+    fn draw_light_mesh_instanced(
+        &mut self,
+        mesh: &'b Mesh,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+        instances: Range<u32>,
+    ) {
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_bind_group(0, camera_bind_group, &[]);
+        self.set_bind_group(1, light_bind_group, &[]);
+        self.draw_indexed(0..mesh.num_elements, 0, instances);
+    }
+
+    fn draw_light_model(
+        &mut self,
+        model: &'b Model,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        self.draw_light_model_instanced(model, camera_bind_group, light_bind_group, 0..1);
+    }
+
+    fn draw_light_model_instanced(
+        &mut self,
+        model: &'b Model,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+        instances: Range<u32>,
+    ) {
+        model.meshes.iter().for_each(|mesh| {
+            self.draw_light_mesh_instanced(
+                mesh,
+                camera_bind_group,
+                light_bind_group,
                 instances.clone(),
             );
         })
@@ -83,13 +294,17 @@ pub struct ModelVertex {
     pub position: [f32; 3],
     pub tex_coords: [f32; 2],
     pub normal: [f32; 3],
+    pub tangent: [f32; 3],
+    pub bitangent: [f32; 3],
 }
 
 impl ModelVertex {
-    const ATTRIBS: [wgpu::VertexAttribute; 3] = wgpu::vertex_attr_array![
+    const ATTRIBS: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![
         0 => Float32x3,
         1 => Float32x2,
         2 => Float32x3,
+        3 => Float32x3,
+        4 => Float32x3,
     ];
 }
 
@@ -103,21 +318,383 @@ impl Vertex for ModelVertex {
     }
 }
 
+/// Factors of the metallic-roughness model, multiplied with the matching
+/// textures of a [`Material`]. Defaults to the glTF defaults.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MaterialFactors {
+    /// Linear RGBA.
+    pub base_color: [f32; 4],
+    pub metallic: f32,
+    pub roughness: f32,
+    /// Linear RGB.
+    pub emissive: [f32; 3],
+    pub normal_scale: f32,
+    pub occlusion_strength: f32,
+}
+
+impl Default for MaterialFactors {
+    fn default() -> Self {
+        Self {
+            base_color: [1.0; 4],
+            metallic: 1.0,
+            roughness: 1.0,
+            emissive: [0.0; 3],
+            normal_scale: 1.0,
+            occlusion_strength: 1.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MaterialUniform {
+    base_color: [f32; 4],
+    emissive: [f32; 3],
+    metallic: f32,
+    roughness: f32,
+    normal_scale: f32,
+    occlusion_strength: f32,
+    _padding: f32,
+}
+
+impl From<MaterialFactors> for MaterialUniform {
+    fn from(factors: MaterialFactors) -> Self {
+        Self {
+            base_color: factors.base_color,
+            emissive: factors.emissive,
+            metallic: factors.metallic,
+            roughness: factors.roughness,
+            normal_scale: factors.normal_scale,
+            occlusion_strength: factors.occlusion_strength,
+            _padding: 0.0,
+        }
+    }
+}
+
+/// Textures of a [`Material`], the missing ones are replaced by 1x1
+/// textures that leave the factors as they are.
+#[derive(Default)]
+pub struct MaterialTextures {
+    /// sRGB color and alpha.
+    pub base_color: Option<texture::Texture>,
+    /// Linear, roughness in green and metallic in blue like in glTF.
+    pub metallic_roughness: Option<texture::Texture>,
+    /// Linear, tangent space.
+    pub normal: Option<texture::Texture>,
+    /// Linear, occlusion in red.
+    pub occlusion: Option<texture::Texture>,
+    /// sRGB.
+    pub emissive: Option<texture::Texture>,
+}
+
 pub struct Material {
     pub name: String,
-    pub diffuse_texture: texture::Texture,
+    pub base_color_texture: texture::Texture,
+    pub metallic_roughness_texture: texture::Texture,
+    pub normal_texture: texture::Texture,
+    pub occlusion_texture: texture::Texture,
+    pub emissive_texture: texture::Texture,
+    pub factors: MaterialFactors,
+    factors_buffer: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
 }
 
+impl Material {
+    /// Layout of [`Material::bind_group`]: a texture and sampler pair for
+    /// the base color, normal, metallic-roughness, occlusion and emissive
+    /// maps, in that order, followed by the factors.
+    pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let sampler_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("texture_bind_group_layout"),
+            entries: &[
+                texture_entry(0),
+                sampler_entry(1),
+                texture_entry(2),
+                sampler_entry(3),
+                texture_entry(4),
+                sampler_entry(5),
+                texture_entry(6),
+                sampler_entry(7),
+                texture_entry(8),
+                sampler_entry(9),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 10,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        })
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        name: &str,
+        textures: MaterialTextures,
+        factors: MaterialFactors,
+        layout: &wgpu::BindGroupLayout,
+    ) -> anyhow::Result<Self> {
+        let or_color = |texture: Option<texture::Texture>, color, label, is_linear| match texture {
+            Some(texture) => Ok(texture),
+            None => texture::Texture::from_color(device, queue, color, label, is_linear),
+        };
+        let white = [255, 255, 255, 255];
+
+        let base_color_texture = or_color(
+            textures.base_color,
+            white,
+            "default_base_color_texture",
+            false,
+        )?;
+        let metallic_roughness_texture = or_color(
+            textures.metallic_roughness,
+            white,
+            "default_metallic_roughness_texture",
+            true,
+        )?;
+        // Flat normal pointing straight out of the surface.
+        let normal_texture = or_color(
+            textures.normal,
+            [128, 128, 255, 255],
+            "flat_normal_texture",
+            true,
+        )?;
+        let occlusion_texture =
+            or_color(textures.occlusion, white, "default_occlusion_texture", true)?;
+        let emissive_texture =
+            or_color(textures.emissive, white, "default_emissive_texture", false)?;
+
+        let factors_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Material Buffer", name)),
+            contents: bytemuck::cast_slice(&[MaterialUniform::from(factors)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let texture_entries = [
+            &base_color_texture,
+            &normal_texture,
+            &metallic_roughness_texture,
+            &occlusion_texture,
+            &emissive_texture,
+        ]
+        .into_iter()
+        .enumerate()
+        .flat_map(|(i, texture)| {
+            [
+                wgpu::BindGroupEntry {
+                    binding: i as u32 * 2,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: i as u32 * 2 + 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+            ]
+        });
+        let entries: Vec<_> = texture_entries
+            .chain(std::iter::once(wgpu::BindGroupEntry {
+                binding: 10,
+                resource: factors_buffer.as_entire_binding(),
+            }))
+            .collect();
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(name),
+            layout,
+            entries: &entries,
+        });
+
+        Ok(Self {
+            name: name.to_string(),
+            base_color_texture,
+            metallic_roughness_texture,
+            normal_texture,
+            occlusion_texture,
+            emissive_texture,
+            factors,
+            factors_buffer,
+            bind_group,
+        })
+    }
+
+    /// Uploads new factors, the textures stay the same.
+    pub fn set_factors(&mut self, queue: &wgpu::Queue, factors: MaterialFactors) {
+        self.factors = factors;
+        queue.write_buffer(
+            &self.factors_buffer,
+            0,
+            bytemuck::cast_slice(&[MaterialUniform::from(factors)]),
+        );
+    }
+}
+
 pub struct Mesh {
     pub name: String,
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
     pub num_elements: u32,
     pub material: usize,
+    /// Bounds of the vertices in model space.
+    pub bounds: culling::Aabb,
+}
+
+impl Mesh {
+    pub fn new(
+        device: &wgpu::Device,
+        name: &str,
+        vertices: &[ModelVertex],
+        indices: &[u32],
+        material: usize,
+    ) -> Self {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Vertex Buffer", name)),
+            contents: bytemuck::cast_slice(vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Index Buffer", name)),
+            contents: bytemuck::cast_slice(indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        let origin = cgmath::Point3::new(0.0, 0.0, 0.0);
+        let bounds = culling::Aabb::from_points(
+            vertices
+                .iter()
+                .map(|vertex| cgmath::Point3::from(vertex.position)),
+        )
+        .unwrap_or(culling::Aabb::new(origin, origin));
+
+        Self {
+            name: name.to_string(),
+            vertex_buffer,
+            index_buffer,
+            num_elements: indices.len() as u32,
+            material,
+            bounds,
+        }
+    }
 }
 
 pub struct Model {
+    /// The most detailed level.
     pub meshes: Vec<Mesh>,
     pub materials: Vec<Material>,
+    /// Coarser levels, from the most to the least detailed, see
+    /// [`Self::add_lod`].
+    pub lods: Vec<lod::Lod>,
+}
+
+impl Model {
+    /// Adds a level less detailed than the others, its threshold has to
+    /// [`lod::LodThreshold::follows`] the one of the last level.
+    pub fn add_lod(&mut self, lod: lod::Lod) -> anyhow::Result<()> {
+        self.check_lod(self.lods.last(), self.lods.len(), &lod)?;
+        self.lods.push(lod);
+
+        Ok(())
+    }
+
+    /// Fails with the error [`Self::add_lod`] would give adding `lods` in
+    /// order, in place of the current levels of detail.
+    pub fn check_lods(&self, lods: &[lod::Lod]) -> anyhow::Result<()> {
+        for (index, lod) in lods.iter().enumerate() {
+            let previous = index.checked_sub(1).map(|previous| &lods[previous]);
+            self.check_lod(previous, index, lod)?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether `lod` can be added after `previous`, with `lod_count` levels
+    /// of detail before it.
+    fn check_lod(
+        &self,
+        previous: Option<&lod::Lod>,
+        lod_count: usize,
+        lod: &lod::Lod,
+    ) -> anyhow::Result<()> {
+        if lod_count + 1 == lod::MAX_LEVELS {
+            return Err(anyhow::anyhow!(
+                "Models can't have more than {} levels of detail.",
+                lod::MAX_LEVELS
+            ));
+        }
+        if lod.meshes.is_empty() {
+            return Err(anyhow::anyhow!("Levels of detail need meshes."));
+        }
+        if let Some(previous) = previous {
+            if !lod.threshold.follows(&previous.threshold) {
+                return Err(anyhow::anyhow!(
+                    "A level with threshold {:?} can't follow one with {:?}, levels need \
+                     thresholds of one kind, each further away or smaller than the last.",
+                    lod.threshold,
+                    previous.threshold
+                ));
+            }
+        }
+        if let Some(mesh) = lod
+            .meshes
+            .iter()
+            .find(|mesh| mesh.material >= self.materials.len())
+        {
+            return Err(anyhow::anyhow!(
+                "Mesh {} uses material {} of a model with {} materials.",
+                mesh.name,
+                mesh.material,
+                self.materials.len()
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Number of levels of detail, including the model's own meshes.
+    pub fn level_count(&self) -> usize {
+        self.lods.len() + 1
+    }
+
+    /// Meshes of the level of detail `level`, `meshes` for level 0.
+    pub fn level_meshes(&self, level: usize) -> &[Mesh] {
+        match level {
+            0 => &self.meshes,
+            _ => &self.lods[level - 1].meshes,
+        }
+    }
+
+    /// Level of detail an instance with `metrics` switches to from the level
+    /// `current`, see [`lod::select_level`].
+    pub fn select_level(&self, metrics: &lod::LodMetrics, current: usize) -> usize {
+        lod::select_level(self.lods.iter().map(|lod| lod.threshold), metrics, current)
+    }
+
+    /// Bounds of all the meshes, `None` without meshes.
+    pub fn bounds(&self) -> Option<culling::Aabb> {
+        self.meshes
+            .iter()
+            .map(|mesh| mesh.bounds)
+            .reduce(|bounds, mesh_bounds| bounds.union(&mesh_bounds))
+    }
 }
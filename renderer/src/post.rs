@@ -0,0 +1,424 @@
+/*!
+Chain of full-screen passes applied to the tonemapped frame.
+
+Each pass is a WGSL fragment shader reading the output of the previous one.
+The passes render back and forth between two intermediate textures, the last
+enabled one writes straight into the final output. Passes are looked up by
+name and can be toggled, reordered and have their parameters changed between
+frames.
+
+Pass shaders only define `fs_main`, the vertex shader comes from
+`shaders/fullscreen.wgsl` and the bindings from `shaders/post/prelude.wgsl`:
+- `t_input` and `s_input`, the previous pass' output and its sampler.
+- `post.params`, four floats whose meaning is up to the pass.
+- `post.texel_size`, the size of one pixel in texture coordinates.
+*/
+
+use anyhow::{anyhow, Result};
+use wgpu::util::DeviceExt;
+
+use crate::texture;
+
+const PRELUDE: &str = include_str!("shaders/post/prelude.wgsl");
+
+/// Effects that come with the renderer, see their shaders for the meaning
+/// of each parameter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    Grayscale,
+    Vignette,
+    Fxaa,
+    ChromaticAberration,
+}
+
+impl Effect {
+    pub const ALL: [Self; 4] = [
+        Self::Grayscale,
+        Self::Vignette,
+        Self::Fxaa,
+        Self::ChromaticAberration,
+    ];
+
+    /// Name of the pass the effect is added as.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Grayscale => "grayscale",
+            Self::Vignette => "vignette",
+            Self::Fxaa => "fxaa",
+            Self::ChromaticAberration => "chromatic_aberration",
+        }
+    }
+
+    pub fn default_params(self) -> [f32; 4] {
+        match self {
+            Self::Grayscale => [1.0, 0.0, 0.0, 0.0],
+            Self::Vignette => [0.6, 0.4, 0.6, 0.0],
+            Self::Fxaa => [8.0, 1.0 / 8.0, 1.0 / 128.0, 0.0],
+            Self::ChromaticAberration => [4.0, 0.0, 0.0, 0.0],
+        }
+    }
+
+    fn shader(self) -> &'static str {
+        match self {
+            Self::Grayscale => include_str!("shaders/post/grayscale.wgsl"),
+            Self::Vignette => include_str!("shaders/post/vignette.wgsl"),
+            Self::Fxaa => include_str!("shaders/post/fxaa.wgsl"),
+            Self::ChromaticAberration => include_str!("shaders/post/chromatic_aberration.wgsl"),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PostUniform {
+    params: [f32; 4],
+    texel_size: [f32; 2],
+    _padding: [f32; 2],
+}
+
+pub struct PostPass {
+    name: String,
+    pub enabled: bool,
+    /// Available to the shader as `post.params`.
+    pub params: [f32; 4],
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl PostPass {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+pub struct PostProcess {
+    format: wgpu::TextureFormat,
+    texel_size: [f32; 2],
+    passes: Vec<PostPass>,
+    targets: [texture::Texture; 2],
+    input_layout: wgpu::BindGroupLayout,
+    input_bind_groups: [wgpu::BindGroup; 2],
+    params_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+}
+
+impl PostProcess {
+    /// Starts with every [`Effect`] added but disabled. `config` describes
+    /// the final output, the intermediate textures use the same format.
+    pub fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
+        let input_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("post_input_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let params_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("post_params_bind_group_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Post Pipeline Layout"),
+            bind_group_layouts: &[&input_layout, &params_layout],
+            push_constant_ranges: &[],
+        });
+
+        let targets = Self::create_targets(device, config);
+        let input_bind_groups = Self::create_input_bind_groups(device, &input_layout, &targets);
+
+        let mut post_process = Self {
+            format: config.format,
+            texel_size: Self::texel_size(config),
+            passes: Vec::new(),
+            targets,
+            input_layout,
+            input_bind_groups,
+            params_layout,
+            pipeline_layout,
+        };
+        for effect in Effect::ALL {
+            post_process.add_effect(device, effect).unwrap();
+            post_process.passes.last_mut().unwrap().enabled = false;
+        }
+
+        post_process
+    }
+
+    fn texel_size(config: &wgpu::SurfaceConfiguration) -> [f32; 2] {
+        [1.0 / config.width as f32, 1.0 / config.height as f32]
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> [texture::Texture; 2] {
+        core::array::from_fn(|i| {
+            texture::Texture::create_intermediate_target(
+                device,
+                config,
+                config.format,
+                &format!("post_texture_{i}"),
+            )
+        })
+    }
+
+    fn create_input_bind_groups(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        targets: &[texture::Texture; 2],
+    ) -> [wgpu::BindGroup; 2] {
+        core::array::from_fn(|i| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("post_input_bind_group"),
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&targets[i].view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&targets[i].sampler),
+                    },
+                ],
+            })
+        })
+    }
+
+    /// Appends an enabled pass running the `fs_main` of `shader`, see the
+    /// module documentation for what it can use. Fails if a pass called
+    /// `name` already exists or the shader doesn't compile, leaving the
+    /// chain as it was.
+    pub async fn add_pass(
+        &mut self,
+        device: &wgpu::Device,
+        name: &str,
+        shader: &str,
+        params: [f32; 4],
+    ) -> Result<()> {
+        self.check_name(name)?;
+
+        // Catch the validation errors of the shader here, rather than in
+        // the device's uncaptured error handler, which panics.
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let pass = self.create_pass(device, name, shader, params);
+        if let Some(error) = device.pop_error_scope().await {
+            return Err(anyhow!(
+                "Couldn't create the post-processing pass {name}: {error}"
+            ));
+        }
+        self.passes.push(pass);
+
+        Ok(())
+    }
+
+    /// Appends an enabled pass for `effect` with its default parameters.
+    pub fn add_effect(&mut self, device: &wgpu::Device, effect: Effect) -> Result<()> {
+        self.check_name(effect.name())?;
+        let pass = self.create_pass(
+            device,
+            effect.name(),
+            effect.shader(),
+            effect.default_params(),
+        );
+        self.passes.push(pass);
+
+        Ok(())
+    }
+
+    fn check_name(&self, name: &str) -> Result<()> {
+        if self.pass(name).is_some() {
+            return Err(anyhow!(
+                "There's already a post-processing pass called {name}"
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn create_pass(
+        &self,
+        device: &wgpu::Device,
+        name: &str,
+        shader: &str,
+        params: [f32; 4],
+    ) -> PostPass {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{name} Post Buffer")),
+            contents: bytemuck::cast_slice(&[PostUniform {
+                params,
+                texel_size: self.texel_size,
+                _padding: [0.0; 2],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&format!("{name}_post_bind_group")),
+            layout: &self.params_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+
+        let shader = texture::create_fullscreen_shader(
+            device,
+            &format!("{name} Post Shader"),
+            &format!("{PRELUDE}\n{shader}"),
+        );
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(&format!("{name} Post Pipeline")),
+            layout: Some(&self.pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: self.format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        PostPass {
+            name: name.to_string(),
+            enabled: true,
+            params,
+            buffer,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    pub fn remove_pass(&mut self, name: &str) -> Option<PostPass> {
+        let index = self.position(name)?;
+
+        Some(self.passes.remove(index))
+    }
+
+    /// Moves the pass at `from` so it ends up at `to`, shifting the ones in
+    /// between.
+    ///
+    /// # Panics
+    ///
+    /// If either index is out of bounds.
+    pub fn move_pass(&mut self, from: usize, to: usize) {
+        let pass = self.passes.remove(from);
+        self.passes.insert(to, pass);
+    }
+
+    /// Index of the pass called `name` in the chain.
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.passes.iter().position(|pass| pass.name == name)
+    }
+
+    pub fn pass(&self, name: &str) -> Option<&PostPass> {
+        self.passes.iter().find(|pass| pass.name == name)
+    }
+
+    pub fn pass_mut(&mut self, name: &str) -> Option<&mut PostPass> {
+        self.passes.iter_mut().find(|pass| pass.name == name)
+    }
+
+    /// Passes in the order they run.
+    pub fn passes(&self) -> &[PostPass] {
+        &self.passes
+    }
+
+    pub fn passes_mut(&mut self) -> &mut [PostPass] {
+        &mut self.passes
+    }
+
+    /// Whether any pass is enabled. When none is, the frame should be drawn
+    /// directly into the output instead of [`Self::input_view`].
+    pub fn is_active(&self) -> bool {
+        self.passes.iter().any(|pass| pass.enabled)
+    }
+
+    /// Where the frame should be drawn for the first pass to read it.
+    pub fn input_view(&self) -> &wgpu::TextureView {
+        &self.targets[0].view
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        self.texel_size = Self::texel_size(config);
+        self.targets = Self::create_targets(device, config);
+        self.input_bind_groups =
+            Self::create_input_bind_groups(device, &self.input_layout, &self.targets);
+    }
+
+    /// Uploads the parameters of the enabled passes.
+    pub fn update(&self, queue: &wgpu::Queue) {
+        for pass in self.passes.iter().filter(|pass| pass.enabled) {
+            let uniform = PostUniform {
+                params: pass.params,
+                texel_size: self.texel_size,
+                _padding: [0.0; 2],
+            };
+            queue.write_buffer(&pass.buffer, 0, bytemuck::cast_slice(&[uniform]));
+        }
+    }
+
+    /// Records the enabled passes, from [`Self::input_view`] to `output`.
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        let enabled: Vec<_> = self.passes.iter().filter(|pass| pass.enabled).collect();
+
+        for (i, pass) in enabled.iter().enumerate() {
+            let target = if i + 1 == enabled.len() {
+                output
+            } else {
+                &self.targets[(i + 1) % 2].view
+            };
+
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some(&pass.name),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(&pass.pipeline);
+            render_pass.set_bind_group(0, &self.input_bind_groups[i % 2], &[]);
+            render_pass.set_bind_group(1, &pass.bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+}
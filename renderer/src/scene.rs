@@ -0,0 +1,283 @@
+/*!
+Scene graph: a hierarchy of nodes placed relative to their parent.
+
+Every node has a local [`Transform`] and can draw a model, referenced by the
+[`ModelId`] the state gave it. World transforms are only recomputed for nodes
+whose transform, or whose ancestors' transform, changed since the last
+[`Scene::update_world_transforms`], and the instance buffers are only
+rebuilt when something did change.
+*/
+
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+use cgmath::prelude::*;
+
+use crate::instance;
+
+/// Handle to a model the nodes of a [`Scene`] can draw, see
+/// [`crate::State::add_model`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ModelId(pub(crate) usize);
+
+/// Handle to a node added to a [`Scene`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(u32);
+
+/// Translation, rotation and scale relative to the parent node, applied in
+/// reverse order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub translation: cgmath::Vector3<f32>,
+    pub rotation: cgmath::Quaternion<f32>,
+    pub scale: cgmath::Vector3<f32>,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: cgmath::Vector3::zero(),
+            rotation: cgmath::Quaternion::one(),
+            scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl Transform {
+    pub fn from_translation(translation: cgmath::Vector3<f32>) -> Self {
+        Self {
+            translation,
+            ..Default::default()
+        }
+    }
+
+    pub fn to_matrix(&self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::from_translation(self.translation)
+            * cgmath::Matrix4::from(self.rotation)
+            * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+pub struct Node {
+    name: String,
+    transform: Transform,
+    model: Option<ModelId>,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    world_transform: cgmath::Matrix4<f32>,
+    dirty: bool,
+}
+
+impl Node {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+
+    pub fn model(&self) -> Option<ModelId> {
+        self.model
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+
+    /// Transform from the node to world space, as of the last
+    /// [`Scene::update_world_transforms`].
+    pub fn world_transform(&self) -> cgmath::Matrix4<f32> {
+        self.world_transform
+    }
+}
+
+#[derive(Default)]
+pub struct Scene {
+    nodes: BTreeMap<NodeId, Node>,
+    roots: Vec<NodeId>,
+    next_id: u32,
+    /// Whether the instances changed since the last update, e.g. because a
+    /// node was added.
+    changed: bool,
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a node under `parent`, or at the root when `None`. Fails when
+    /// `parent` isn't in the scene.
+    pub fn add_node(
+        &mut self,
+        name: &str,
+        transform: Transform,
+        model: Option<ModelId>,
+        parent: Option<NodeId>,
+    ) -> Result<NodeId> {
+        let id = NodeId(self.next_id);
+        self.siblings_mut(parent)?.push(id);
+        self.next_id += 1;
+        self.nodes.insert(
+            id,
+            Node {
+                name: name.to_string(),
+                transform,
+                model,
+                parent,
+                children: Vec::new(),
+                world_transform: cgmath::Matrix4::identity(),
+                dirty: true,
+            },
+        );
+        self.changed = true;
+
+        Ok(id)
+    }
+
+    /// Removes the node along with all its descendants.
+    pub fn remove_node(&mut self, id: NodeId) -> Option<Node> {
+        let node = self.nodes.remove(&id)?;
+        if let Ok(siblings) = self.siblings_mut(node.parent) {
+            siblings.retain(|&sibling| sibling != id);
+        }
+
+        let mut descendants = node.children.clone();
+        while let Some(descendant) = descendants.pop() {
+            if let Some(removed) = self.nodes.remove(&descendant) {
+                descendants.extend(removed.children);
+            }
+        }
+        self.changed = true;
+
+        Some(node)
+    }
+
+    /// Moves the node under `parent`, or to the root when `None`, keeping
+    /// its local transform. Fails when either node isn't in the scene or
+    /// `parent` is the node itself or one of its descendants.
+    pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>) -> Result<()> {
+        let node = self
+            .nodes
+            .get(&id)
+            .ok_or_else(|| anyhow!("Node {id:?} isn't in the scene."))?;
+        let old_parent = node.parent;
+        if let Some(parent) = parent.filter(|parent| !self.nodes.contains_key(parent)) {
+            return Err(anyhow!("Parent node {parent:?} isn't in the scene."));
+        }
+
+        let mut ancestor = parent;
+        while let Some(ancestor_id) = ancestor {
+            if ancestor_id == id {
+                return Err(anyhow!("Node {id:?} can't be its own ancestor."));
+            }
+            ancestor = self.nodes.get(&ancestor_id).and_then(|node| node.parent);
+        }
+
+        self.siblings_mut(old_parent)?
+            .retain(|&sibling| sibling != id);
+        self.siblings_mut(parent)?.push(id);
+        let node = self.nodes.get_mut(&id).unwrap();
+        node.parent = parent;
+        node.dirty = true;
+
+        Ok(())
+    }
+
+    /// Children of `parent`, or the roots when `None`.
+    fn siblings_mut(&mut self, parent: Option<NodeId>) -> Result<&mut Vec<NodeId>> {
+        match parent {
+            Some(parent) => self
+                .nodes
+                .get_mut(&parent)
+                .map(|node| &mut node.children)
+                .ok_or_else(|| anyhow!("Parent node {parent:?} isn't in the scene.")),
+            None => Ok(&mut self.roots),
+        }
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<&Node> {
+        self.nodes.get(&id)
+    }
+
+    /// First node called `name`, in the order they were added.
+    pub fn find(&self, name: &str) -> Option<NodeId> {
+        self.iter()
+            .find(|(_, node)| node.name == name)
+            .map(|(id, _)| id)
+    }
+
+    /// Marks the node and its descendants for a world transform update.
+    pub fn transform_mut(&mut self, id: NodeId) -> Option<&mut Transform> {
+        let node = self.nodes.get_mut(&id)?;
+        node.dirty = true;
+
+        Some(&mut node.transform)
+    }
+
+    pub fn set_model(&mut self, id: NodeId, model: Option<ModelId>) {
+        if let Some(node) = self.nodes.get_mut(&id) {
+            node.model = model;
+            self.changed = true;
+        }
+    }
+
+    pub fn roots(&self) -> &[NodeId] {
+        &self.roots
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (NodeId, &Node)> {
+        self.nodes.iter().map(|(id, node)| (*id, node))
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Propagates the transforms of dirty nodes down to their descendants.
+    /// Returns whether the instances changed since the last call.
+    pub fn update_world_transforms(&mut self) -> bool {
+        let mut changed = std::mem::take(&mut self.changed);
+
+        let mut stack: Vec<_> = self
+            .roots
+            .iter()
+            .map(|&root| (root, cgmath::Matrix4::identity(), false))
+            .collect();
+        while let Some((id, parent_transform, parent_dirty)) = stack.pop() {
+            let node = self.nodes.get_mut(&id).unwrap();
+            let dirty = node.dirty || parent_dirty;
+            if dirty {
+                node.world_transform = parent_transform * node.transform.to_matrix();
+                node.dirty = false;
+                changed |= node.model.is_some();
+            }
+            stack.extend(
+                node.children
+                    .iter()
+                    .map(|&child| (child, node.world_transform, dirty)),
+            );
+        }
+
+        changed
+    }
+
+    /// Instance data of every node drawing `model`, in the order they were
+    /// added.
+    pub fn instances(&self, model: ModelId) -> Vec<instance::InstanceRaw> {
+        self.nodes
+            .values()
+            .filter(|node| node.model == Some(model))
+            .map(|node| instance::InstanceRaw::from_matrix(node.world_transform))
+            .collect()
+    }
+}
@@ -0,0 +1,335 @@
+/*!
+Scene description files, loaded with [`crate::State::load_scene`].
+
+A scene file lists the models to load, the node hierarchy drawing them, the
+lights and the camera, in RON (`.ron`) or JSON (`.json`). Every field can be
+left out, e.g. a minimal RON scene:
+
+```ron
+(
+    models: [(name: "cube", file: "cube.obj")],
+    nodes: [(name: "cube", translation: (0.0, 0.0, -3.0), model: Some("cube"))],
+)
+```
+
+Rotations are Euler angles in degrees, applied around X, then Y, then Z.
+*/
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{light, lod, model, scene};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SceneFormat {
+    Ron,
+    Json,
+}
+
+impl SceneFormat {
+    /// Goes by the extension of `file_name`.
+    pub fn from_file_name(file_name: &str) -> Result<Self> {
+        let extension = std::path::Path::new(file_name)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+
+        match extension.as_deref() {
+            Some("ron") => Ok(Self::Ron),
+            Some("json") => Ok(Self::Json),
+            _ => Err(anyhow!(
+                "Unknown scene format for {file_name}, use a .ron or .json file."
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SceneFile {
+    pub camera: CameraDescription,
+    /// Equirectangular environment map lighting the scene, see
+    /// [`crate::State::load_environment`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub environment: Option<String>,
+    pub models: Vec<ModelDescription>,
+    /// Root nodes of the scene graph.
+    pub nodes: Vec<NodeDescription>,
+    pub lights: Vec<LightDescription>,
+}
+
+impl SceneFile {
+    pub fn parse(text: &str, format: SceneFormat) -> Result<Self> {
+        Ok(match format {
+            SceneFormat::Ron => ron::from_str(text)?,
+            SceneFormat::Json => serde_json::from_str(text)?,
+        })
+    }
+
+    pub fn to_text(&self, format: SceneFormat) -> Result<String> {
+        Ok(match format {
+            SceneFormat::Ron => ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::new())?,
+            SceneFormat::Json => serde_json::to_string_pretty(self)?,
+        })
+    }
+
+    /// Writes the scene in the format matching the extension of `path`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        let path = path.as_ref();
+        let format = SceneFormat::from_file_name(&path.to_string_lossy())?;
+        std::fs::write(path, self.to_text(format)?)?;
+
+        Ok(())
+    }
+}
+
+/// Where the camera is and what it looks at, the aspect ratio comes from the
+/// render target.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraDescription {
+    pub eye: [f32; 3],
+    pub target: [f32; 3],
+    /// Vertical field of view in degrees.
+    pub fovy: f32,
+    pub znear: f32,
+    pub zfar: f32,
+}
+
+impl Default for CameraDescription {
+    fn default() -> Self {
+        Self {
+            eye: [0.0, 1.0, 2.0],
+            target: [0.0; 3],
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+        }
+    }
+}
+
+/// A model file the nodes refer to by `name`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelDescription {
+    pub name: String,
+    /// Loaded with [`crate::assets::load_model`].
+    pub file: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub materials: Vec<MaterialOverride>,
+    /// Levels of detail after the model itself, from the most to the least
+    /// detailed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lods: Vec<LodDescription>,
+}
+
+/// A level of detail loaded with [`crate::assets::load_lod`], e.g.
+/// `(file: "cube_lod1.obj", threshold: ScreenSize(0.1))` in RON.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LodDescription {
+    pub file: String,
+    pub threshold: lod::LodThreshold,
+}
+
+/// Replaces some of the factors of a model's materials.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MaterialOverride {
+    /// Name of the material to change, all of them when `None`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub material: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_color: Option<[f32; 4]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metallic: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub roughness: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub emissive: Option<[f32; 3]>,
+}
+
+impl MaterialOverride {
+    /// Override setting every factor to the ones of `material`.
+    pub fn from_material(material: &model::Material) -> Self {
+        Self {
+            material: Some(material.name.clone()),
+            base_color: Some(material.factors.base_color),
+            metallic: Some(material.factors.metallic),
+            roughness: Some(material.factors.roughness),
+            emissive: Some(material.factors.emissive),
+        }
+    }
+
+    pub fn applies_to(&self, material: &model::Material) -> bool {
+        self.material
+            .as_ref()
+            .is_none_or(|name| *name == material.name)
+    }
+
+    pub fn apply(&self, factors: &mut model::MaterialFactors) {
+        if let Some(base_color) = self.base_color {
+            factors.base_color = base_color;
+        }
+        if let Some(metallic) = self.metallic {
+            factors.metallic = metallic;
+        }
+        if let Some(roughness) = self.roughness {
+            factors.roughness = roughness;
+        }
+        if let Some(emissive) = self.emissive {
+            factors.emissive = emissive;
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NodeDescription {
+    pub name: String,
+    pub translation: [f32; 3],
+    /// Euler angles in degrees.
+    pub rotation: [f32; 3],
+    pub scale: [f32; 3],
+    /// Name of one of the [`SceneFile::models`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<NodeDescription>,
+}
+
+impl Default for NodeDescription {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            translation: [0.0; 3],
+            rotation: [0.0; 3],
+            scale: [1.0; 3],
+            model: None,
+            children: Vec::new(),
+        }
+    }
+}
+
+impl NodeDescription {
+    pub fn transform(&self) -> scene::Transform {
+        let [x, y, z] = self.rotation.map(cgmath::Deg);
+        scene::Transform {
+            translation: self.translation.into(),
+            rotation: cgmath::Euler { x, y, z }.into(),
+            scale: self.scale.into(),
+        }
+    }
+
+    /// Description of `node` without its model and children.
+    pub fn from_node(node: &scene::Node) -> Self {
+        let transform = node.transform();
+        let rotation = cgmath::Euler::from(transform.rotation);
+        Self {
+            name: node.name().to_string(),
+            translation: transform.translation.into(),
+            rotation: [rotation.x, rotation.y, rotation.z].map(|angle| cgmath::Deg::from(angle).0),
+            scale: transform.scale.into(),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum LightDescription {
+    Point {
+        position: [f32; 3],
+        range: f32,
+        color: [f32; 3],
+        intensity: f32,
+    },
+    Directional {
+        direction: [f32; 3],
+        color: [f32; 3],
+        intensity: f32,
+    },
+    Spot {
+        position: [f32; 3],
+        direction: [f32; 3],
+        range: f32,
+        /// Degrees.
+        inner_angle: f32,
+        /// Degrees.
+        outer_angle: f32,
+        color: [f32; 3],
+        intensity: f32,
+    },
+}
+
+impl From<LightDescription> for light::Light {
+    fn from(description: LightDescription) -> Self {
+        match description {
+            LightDescription::Point {
+                position,
+                range,
+                color,
+                intensity,
+            } => light::Light::point(position.into(), range, color, intensity),
+            LightDescription::Directional {
+                direction,
+                color,
+                intensity,
+            } => light::Light::directional(direction.into(), color, intensity),
+            LightDescription::Spot {
+                position,
+                direction,
+                range,
+                inner_angle,
+                outer_angle,
+                color,
+                intensity,
+            } => light::Light {
+                kind: light::LightKind::Spot {
+                    position: position.into(),
+                    direction: direction.into(),
+                    range,
+                    inner_angle: cgmath::Deg(inner_angle),
+                    outer_angle: cgmath::Deg(outer_angle),
+                },
+                color,
+                intensity,
+            },
+        }
+    }
+}
+
+impl From<&light::Light> for LightDescription {
+    fn from(light: &light::Light) -> Self {
+        let light::Light {
+            kind,
+            color,
+            intensity,
+        } = *light;
+        match kind {
+            light::LightKind::Point { position, range } => Self::Point {
+                position: position.into(),
+                range,
+                color,
+                intensity,
+            },
+            light::LightKind::Directional { direction } => Self::Directional {
+                direction: direction.into(),
+                color,
+                intensity,
+            },
+            light::LightKind::Spot {
+                position,
+                direction,
+                range,
+                inner_angle,
+                outer_angle,
+            } => Self::Spot {
+                position: position.into(),
+                direction: direction.into(),
+                range,
+                inner_angle: inner_angle.0,
+                outer_angle: outer_angle.0,
+                color,
+                intensity,
+            },
+        }
+    }
+}
@@ -0,0 +1,12 @@
+// Copies a texture onto the whole render target with a single triangle
+// covering the screen, filtering with the sampler.
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(t_source, s_source, in.tex_coords);
+}
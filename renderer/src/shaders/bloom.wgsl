@@ -0,0 +1,72 @@
+// Every pass reads `t_source` and writes a texture of a different size, the
+// texel size comes from the source dimensions.
+
+struct BloomUniform {
+    threshold: f32,
+    // Width of the soft transition around the threshold.
+    knee: f32,
+    intensity: f32,
+    // Spread of the upsampling filter, in source texels.
+    radius: f32,
+};
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(1) @binding(0)
+var<uniform> bloom: BloomUniform;
+
+fn source_texel() -> vec2<f32> {
+    return 1.0 / vec2<f32>(textureDimensions(t_source));
+}
+
+// Each bilinear tap averages 2x2 texels, so the four of them cover the 4x4
+// texels around `uv` and halving the size doesn't skip any.
+fn downsample(uv: vec2<f32>) -> vec3<f32> {
+    let texel = source_texel();
+    let a = textureSample(t_source, s_source, uv + texel * vec2<f32>(-1.0, -1.0)).rgb;
+    let b = textureSample(t_source, s_source, uv + texel * vec2<f32>(1.0, -1.0)).rgb;
+    let c = textureSample(t_source, s_source, uv + texel * vec2<f32>(-1.0, 1.0)).rgb;
+    let d = textureSample(t_source, s_source, uv + texel * vec2<f32>(1.0, 1.0)).rgb;
+    return (a + b + c + d) * 0.25;
+}
+
+// Keeps what's brighter than the threshold, with a quadratic curve around it
+// so highlights don't pop in.
+@fragment
+fn fs_prefilter(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = downsample(in.tex_coords);
+    let brightness = max(color.r, max(color.g, color.b));
+    var soft = clamp(brightness - bloom.threshold + bloom.knee, 0.0, 2.0 * bloom.knee);
+    soft = soft * soft / (4.0 * bloom.knee + 1e-4);
+    let contribution = max(soft, brightness - bloom.threshold) / max(brightness, 1e-4);
+    return vec4<f32>(color * contribution, 1.0);
+}
+
+@fragment
+fn fs_downsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(downsample(in.tex_coords), 1.0);
+}
+
+// 3x3 tent filter, added on top of the level it's drawn into.
+@fragment
+fn fs_upsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    let offset = source_texel() * bloom.radius;
+    var color = vec3<f32>(0.0);
+    for (var y = -1; y <= 1; y += 1) {
+        for (var x = -1; x <= 1; x += 1) {
+            let weight = f32((2 - abs(x)) * (2 - abs(y)));
+            let uv = in.tex_coords + vec2<f32>(f32(x), f32(y)) * offset;
+            color += textureSample(t_source, s_source, uv).rgb * weight;
+        }
+    }
+    return vec4<f32>(color / 16.0, 1.0);
+}
+
+// Added on top of the scene.
+@fragment
+fn fs_composite(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_source, s_source, in.tex_coords).rgb;
+    return vec4<f32>(color * bloom.intensity, 0.0);
+}
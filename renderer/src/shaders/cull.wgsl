@@ -0,0 +1,101 @@
+// Frustum culling of the instances of one model. Every visible instance is
+// copied to the next free slot of the segment of `visible` of its level of
+// detail, and counted in the indirect draw of each mesh of that level.
+
+struct Culling {
+    // Inward facing, xyz is the normal and w the distance.
+    planes: array<vec4<f32>, 6>,
+    bounds_center: vec3<f32>,
+    instance_count: u32,
+    bounds_extents: vec3<f32>,
+    // Everything is visible when 0.
+    enabled: u32,
+    // The instances are sorted by level, each level ends where the next
+    // one's segment starts.
+    level_ends: vec4<u32>,
+    // Index in `draws` of the first mesh of each level.
+    level_first_draws: vec4<u32>,
+    level_mesh_counts: vec4<u32>,
+    level_count: u32,
+}
+
+// `InstanceRaw`: the model matrix columns, then the normal matrix columns.
+struct Instance {
+    data: array<f32, 25>,
+}
+
+struct DrawIndexedIndirect {
+    index_count: u32,
+    instance_count: atomic<u32>,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> culling: Culling;
+@group(0) @binding(1)
+var<storage, read> instances: array<Instance>;
+@group(0) @binding(2)
+var<storage, read_write> visible: array<Instance>;
+@group(0) @binding(3)
+var<storage, read_write> visible_indices: array<u32>;
+@group(0) @binding(4)
+var<storage, read_write> draws: array<DrawIndexedIndirect>;
+
+fn is_visible(model: mat4x4<f32>) -> bool {
+    // Box around the model's bounds moved by the instance.
+    let center = (model * vec4<f32>(culling.bounds_center, 1.0)).xyz;
+    let linear = mat3x3<f32>(abs(model[0].xyz), abs(model[1].xyz), abs(model[2].xyz));
+    let extents = linear * culling.bounds_extents;
+
+    for (var i = 0; i < 6; i++) {
+        let plane = culling.planes[i];
+        let radius = dot(abs(plane.xyz), extents);
+        if dot(plane.xyz, center) + plane.w < -radius {
+            return false;
+        }
+    }
+    return true;
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if index >= culling.instance_count {
+        return;
+    }
+
+    let instance = instances[index];
+    let d = instance.data;
+    let model = mat4x4<f32>(
+        vec4<f32>(d[0], d[1], d[2], d[3]),
+        vec4<f32>(d[4], d[5], d[6], d[7]),
+        vec4<f32>(d[8], d[9], d[10], d[11]),
+        vec4<f32>(d[12], d[13], d[14], d[15]),
+    );
+    if culling.enabled != 0u && !is_visible(model) {
+        return;
+    }
+
+    var level = 0u;
+    while level + 1u < culling.level_count && index >= culling.level_ends[level] {
+        level++;
+    }
+    var start = 0u;
+    if level > 0u {
+        start = culling.level_ends[level - 1u];
+    }
+    let first_draw = culling.level_first_draws[level];
+    let mesh_count = culling.level_mesh_counts[level];
+    if mesh_count == 0u {
+        return;
+    }
+
+    let slot = start + atomicAdd(&draws[first_draw].instance_count, 1u);
+    for (var i = 1u; i < mesh_count; i++) {
+        atomicAdd(&draws[first_draw + i].instance_count, 1u);
+    }
+    visible[slot] = instance;
+    visible_indices[slot] = index;
+}
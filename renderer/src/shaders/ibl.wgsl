@@ -0,0 +1,147 @@
+// Precomputes the image-based lighting maps. Each pass renders one cube face
+// (or the BRDF lookup table) with a full-screen triangle.
+
+struct BakeUniform {
+    // Cube layer rendered into: +X, -X, +Y, -Y, +Z, -Z
+    face: u32,
+    roughness: f32,
+};
+
+@group(0) @binding(0)
+var t_environment: texture_cube<f32>;
+@group(0) @binding(1)
+var s_environment: sampler;
+@group(1) @binding(0)
+var<uniform> bake: BakeUniform;
+
+const PI: f32 = 3.14159265;
+const IRRADIANCE_STEP: f32 = 0.1;
+const SAMPLE_COUNT: u32 = 128u;
+
+// Direction through `tex_coords` of the face being rendered, matches
+// `cube_face_direction` in texture.rs.
+fn face_direction(tex_coords: vec2<f32>) -> vec3<f32> {
+    let uv = tex_coords * 2.0 - 1.0;
+    switch bake.face {
+        case 0u: { return normalize(vec3<f32>(1.0, -uv.y, -uv.x)); }
+        case 1u: { return normalize(vec3<f32>(-1.0, -uv.y, uv.x)); }
+        case 2u: { return normalize(vec3<f32>(uv.x, 1.0, uv.y)); }
+        case 3u: { return normalize(vec3<f32>(uv.x, -1.0, -uv.y)); }
+        case 4u: { return normalize(vec3<f32>(uv.x, -uv.y, 1.0)); }
+        default: { return normalize(vec3<f32>(-uv.x, -uv.y, -1.0)); }
+    }
+}
+
+// Any two directions perpendicular to `normal` and each other.
+fn tangent_frame(normal: vec3<f32>) -> mat3x3<f32> {
+    var up = vec3<f32>(0.0, 1.0, 0.0);
+    if abs(normal.y) > 0.999 {
+        up = vec3<f32>(0.0, 0.0, 1.0);
+    }
+    let tangent = normalize(cross(up, normal));
+    let bitangent = cross(normal, tangent);
+    return mat3x3<f32>(tangent, bitangent, normal);
+}
+
+// Cosine weighted average of the environment over the hemisphere around
+// each direction, the diffuse light a surface facing it receives.
+@fragment
+fn fs_irradiance(in: VertexOutput) -> @location(0) vec4<f32> {
+    let frame = tangent_frame(face_direction(in.tex_coords));
+
+    var irradiance = vec3<f32>(0.0);
+    var count = 0.0;
+    for (var phi = 0.0; phi < 2.0 * PI; phi += IRRADIANCE_STEP) {
+        for (var theta = 0.0; theta < 0.5 * PI; theta += IRRADIANCE_STEP) {
+            let local = vec3<f32>(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            let color = textureSampleLevel(t_environment, s_environment, frame * local, 0.0).rgb;
+            irradiance += color * cos(theta) * sin(theta);
+            count += 1.0;
+        }
+    }
+
+    return vec4<f32>(PI * irradiance / count, 1.0);
+}
+
+// Van der Corput sequence, the bits of `i` mirrored around the point.
+// `reverseBits` isn't available on WebGL2.
+fn radical_inverse(i: u32) -> f32 {
+    var bits = i;
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return f32(bits) * 2.3283064365386963e-10;
+}
+
+fn hammersley(i: u32, count: u32) -> vec2<f32> {
+    return vec2<f32>(f32(i) / f32(count), radical_inverse(i));
+}
+
+// Half vector around `normal` distributed like the GGX lobe of `roughness`.
+fn importance_sample_ggx(xi: vec2<f32>, normal: vec3<f32>, roughness: f32) -> vec3<f32> {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    let local = vec3<f32>(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta);
+    return tangent_frame(normal) * local;
+}
+
+// Environment blurred by the GGX lobe of `bake.roughness`, assuming the view
+// direction is the normal (split sum approximation).
+@fragment
+fn fs_prefilter(in: VertexOutput) -> @location(0) vec4<f32> {
+    let normal = face_direction(in.tex_coords);
+
+    var color = vec3<f32>(0.0);
+    var total_weight = 0.0;
+    for (var i = 0u; i < SAMPLE_COUNT; i += 1u) {
+        let half_dir = importance_sample_ggx(hammersley(i, SAMPLE_COUNT), normal, bake.roughness);
+        let light_dir = normalize(2.0 * dot(normal, half_dir) * half_dir - normal);
+        let n_dot_l = dot(normal, light_dir);
+        if n_dot_l > 0.0 {
+            color += textureSampleLevel(t_environment, s_environment, light_dir, 0.0).rgb * n_dot_l;
+            total_weight += n_dot_l;
+        }
+    }
+
+    return vec4<f32>(color / max(total_weight, 1e-4), 1.0);
+}
+
+fn geometry_schlick_ggx(n_dot_v: f32, roughness: f32) -> f32 {
+    // k is remapped for image-based lighting
+    let k = roughness * roughness / 2.0;
+    return n_dot_v / (n_dot_v * (1.0 - k) + k);
+}
+
+// Scale (r) and bias (g) applied to f0 by the specular BRDF integrated over
+// the hemisphere, for n·v along x and the roughness along y.
+@fragment
+fn fs_brdf(in: VertexOutput) -> @location(0) vec4<f32> {
+    let n_dot_v = max(in.tex_coords.x, 1e-3);
+    let roughness = in.tex_coords.y;
+    let view_dir = vec3<f32>(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+    let normal = vec3<f32>(0.0, 0.0, 1.0);
+
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < SAMPLE_COUNT; i += 1u) {
+        let half_dir = importance_sample_ggx(hammersley(i, SAMPLE_COUNT), normal, roughness);
+        let light_dir = normalize(2.0 * dot(view_dir, half_dir) * half_dir - view_dir);
+        let n_dot_l = max(light_dir.z, 0.0);
+        let n_dot_h = max(half_dir.z, 0.0);
+        let v_dot_h = max(dot(view_dir, half_dir), 0.0);
+        if n_dot_l > 0.0 {
+            let geometry = geometry_schlick_ggx(n_dot_v, roughness)
+                * geometry_schlick_ggx(n_dot_l, roughness);
+            let visibility = geometry * v_dot_h / (n_dot_h * n_dot_v);
+            let fresnel = pow(1.0 - v_dot_h, 5.0);
+            scale += (1.0 - fresnel) * visibility;
+            bias += fresnel * visibility;
+        }
+    }
+
+    return vec4<f32>(vec2<f32>(scale, bias) / f32(SAMPLE_COUNT), 0.0, 1.0);
+}
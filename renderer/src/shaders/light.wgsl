@@ -0,0 +1,44 @@
+// Draws a small unlit copy of a model where each light is, one instance per
+// light. Prepended with the `lights` binding at group 1.
+
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+const MARKER_SCALE: f32 = 0.25;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec3<f32>,
+};
+
+@vertex
+fn vs_main(model: VertexInput, @builtin(instance_index) light_index: u32) -> VertexOutput {
+    let light = lights.lights[light_index];
+
+    // Directional lights have no position, collapse their marker so it
+    // doesn't get rasterized.
+    var scale = MARKER_SCALE;
+    if light.kind == LIGHT_DIRECTIONAL {
+        scale = 0.0;
+    }
+    let world_position = model.position * scale + light.position;
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(world_position, 1.0);
+    out.color = light.color;
+
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(in.color, 1.0);
+}
@@ -0,0 +1,54 @@
+// Shared by the shaders using the scene lights. `Light` matches
+// `light::LightRaw` and the `lights` binding itself is added by
+// `Lights::shader_source`.
+
+const LIGHT_POINT: u32 = 0u;
+const LIGHT_DIRECTIONAL: u32 = 1u;
+const LIGHT_SPOT: u32 = 2u;
+
+struct Light {
+    position: vec3<f32>,
+    kind: u32,
+    color: vec3<f32>,
+    intensity: f32,
+    direction: vec3<f32>,
+    range: f32,
+    inner_cone_cos: f32,
+    outer_cone_cos: f32,
+};
+
+// Inverse square falloff windowed to reach zero at `range`.
+fn light_attenuation(distance: f32, range: f32) -> f32 {
+    let ratio = distance / range;
+    let window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+    return window * window / (distance * distance + 1.0);
+}
+
+// Direction from `world_position` towards the light and how much of the
+// light reaches it.
+struct LightIncidence {
+    direction: vec3<f32>,
+    strength: f32,
+};
+
+fn light_incidence(light: Light, world_position: vec3<f32>) -> LightIncidence {
+    var out: LightIncidence;
+
+    if light.kind == LIGHT_DIRECTIONAL {
+        out.direction = -light.direction;
+        out.strength = light.intensity;
+        return out;
+    }
+
+    let to_light = light.position - world_position;
+    let distance = length(to_light);
+    out.direction = to_light / distance;
+    out.strength = light.intensity * light_attenuation(distance, light.range);
+
+    if light.kind == LIGHT_SPOT {
+        let cone_cos = dot(-out.direction, light.direction);
+        out.strength *= smoothstep(light.outer_cone_cos, light.inner_cone_cos, cone_cos);
+    }
+
+    return out;
+}
@@ -0,0 +1,11 @@
+// params.x: offset of the red and blue channels at the corners, in pixels.
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Grows towards the edges like in a real lens.
+    let offset = (in.tex_coords - 0.5) * 2.0 * post.params.x * post.texel_size;
+    let color = textureSample(t_input, s_input, in.tex_coords);
+    let red = textureSample(t_input, s_input, in.tex_coords + offset).r;
+    let blue = textureSample(t_input, s_input, in.tex_coords - offset).b;
+    return vec4<f32>(red, color.g, blue, color.a);
+}
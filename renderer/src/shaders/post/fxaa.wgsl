@@ -0,0 +1,53 @@
+// Fast approximate anti-aliasing, the original FXAA by Timothy Lottes
+// without its quality presets: blurs along the edges found from the luma of
+// the neighbouring pixels.
+//
+// params.x: longest blur along an edge, in pixels.
+// params.y: how much the local contrast shortens the blur.
+// params.z: smallest value that shortening can reach.
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let span_max = post.params.x;
+    let reduce_mul = post.params.y;
+    let reduce_min = post.params.z;
+    let texel = post.texel_size;
+    let uv = in.tex_coords;
+
+    let luma_nw = luma(textureSample(t_input, s_input, uv + vec2<f32>(-1.0, -1.0) * texel).rgb);
+    let luma_ne = luma(textureSample(t_input, s_input, uv + vec2<f32>(1.0, -1.0) * texel).rgb);
+    let luma_sw = luma(textureSample(t_input, s_input, uv + vec2<f32>(-1.0, 1.0) * texel).rgb);
+    let luma_se = luma(textureSample(t_input, s_input, uv + vec2<f32>(1.0, 1.0) * texel).rgb);
+    let center = textureSample(t_input, s_input, uv);
+    let luma_m = luma(center.rgb);
+
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    var direction = vec2<f32>(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se)
+    );
+    let direction_reduce = max(
+        (luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * reduce_mul,
+        reduce_min
+    );
+    let min_direction = min(abs(direction.x), abs(direction.y)) + direction_reduce;
+    direction = clamp(direction / min_direction, vec2<f32>(-span_max), vec2<f32>(span_max)) * texel;
+
+    let color_a = 0.5 * (
+        textureSample(t_input, s_input, uv + direction * (1.0 / 3.0 - 0.5)).rgb
+        + textureSample(t_input, s_input, uv + direction * (2.0 / 3.0 - 0.5)).rgb
+    );
+    let color_b = color_a * 0.5 + 0.25 * (
+        textureSample(t_input, s_input, uv - direction * 0.5).rgb
+        + textureSample(t_input, s_input, uv + direction * 0.5).rgb
+    );
+
+    // The wider blur went past the edge when it leaves the local range.
+    let luma_b = luma(color_b);
+    if luma_b < luma_min || luma_b > luma_max {
+        return vec4<f32>(color_a, center.a);
+    }
+    return vec4<f32>(color_b, center.a);
+}
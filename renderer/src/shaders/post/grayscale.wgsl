@@ -0,0 +1,8 @@
+// params.x: how much of the color is removed, 0 to 1.
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_input, s_input, in.tex_coords);
+    let gray = vec3<f32>(luma(color.rgb));
+    return vec4<f32>(mix(color.rgb, gray, post.params.x), color.a);
+}
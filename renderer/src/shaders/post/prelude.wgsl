@@ -0,0 +1,20 @@
+// Prepended to every post-processing pass. Passes only define `fs_main`,
+// reading the previous pass' output from `t_input` at `in.tex_coords`.
+
+struct PostParams {
+    // Meaning depends on the pass.
+    params: vec4<f32>,
+    // Size of one pixel in texture coordinates.
+    texel_size: vec2<f32>,
+};
+
+@group(0) @binding(0)
+var t_input: texture_2d<f32>;
+@group(0) @binding(1)
+var s_input: sampler;
+@group(1) @binding(0)
+var<uniform> post: PostParams;
+
+fn luma(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
@@ -0,0 +1,11 @@
+// params.x: darkening at the corners, 0 to 1.
+// params.y: distance from the center where the darkening starts.
+// params.z: distance over which it fades in.
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_input, s_input, in.tex_coords);
+    let distance = length(in.tex_coords - 0.5) * sqrt(2.0);
+    let vignette = smoothstep(post.params.y, post.params.y + post.params.z, distance);
+    return vec4<f32>(color.rgb * (1.0 - vignette * post.params.x), color.a);
+}
@@ -1,13 +1,50 @@
 struct CameraUniform {
+    view_pos: vec4<f32>,
     view_proj: mat4x4<f32>,
 };
 @group(1) @binding(0)
 var<uniform> camera: CameraUniform;
 
+// Image-based lighting, see ibl.rs
+struct Environment {
+    intensity: f32,
+    prefiltered_max_lod: f32,
+};
+@group(1) @binding(1)
+var t_irradiance: texture_cube<f32>;
+@group(1) @binding(2)
+var t_prefiltered: texture_cube<f32>;
+@group(1) @binding(3)
+var t_brdf_lut: texture_2d<f32>;
+@group(1) @binding(4)
+var s_environment: sampler;
+@group(1) @binding(5)
+var<uniform> environment: Environment;
+
+// Prepended with the `lights` binding at group 2, the shadow map is at
+// group 3.
+
+struct ShadowUniform {
+    light_view_proj: mat4x4<f32>,
+    light_index: u32,
+    texel_size: f32,
+};
+@group(3) @binding(0)
+var<uniform> shadow: ShadowUniform;
+@group(3) @binding(1)
+var t_shadow: texture_depth_2d;
+@group(3) @binding(2)
+var s_shadow: sampler_comparison;
+
+const PI: f32 = 3.14159265;
+const MIN_ROUGHNESS: f32 = 0.05;
 
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec3<f32>,
+    @location(4) bitangent: vec3<f32>,
 };
 
 struct InstanceInput {
@@ -15,11 +52,18 @@ struct InstanceInput {
     @location(9) model_matrix_1: vec4<f32>,
     @location(10) model_matrix_2: vec4<f32>,
     @location(11) model_matrix_3: vec4<f32>,
+    @location(12) normal_matrix_0: vec3<f32>,
+    @location(13) normal_matrix_1: vec3<f32>,
+    @location(14) normal_matrix_2: vec3<f32>,
 }
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) world_tangent: vec3<f32>,
+    @location(3) world_bitangent: vec3<f32>,
+    @location(4) world_position: vec3<f32>,
 };
 
 @vertex
@@ -32,19 +76,190 @@ fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
         instance.model_matrix_2,
         instance.model_matrix_3
     );
+    // Tangents follow the surface like positions do, normals need the
+    // inverse transpose to stay perpendicular to it under non-uniform scale.
+    let linear = mat3x3<f32>(
+        instance.model_matrix_0.xyz,
+        instance.model_matrix_1.xyz,
+        instance.model_matrix_2.xyz
+    );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2
+    );
 
-    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+    out.clip_position = camera.view_proj * world_position;
+    out.world_position = world_position.xyz;
     out.tex_coords = model.tex_coords;
+    out.world_normal = normal_matrix * model.normal;
+    out.world_tangent = linear * model.tangent;
+    out.world_bitangent = linear * model.bitangent;
 
     return out;
 }
 
+struct MaterialFactors {
+    base_color: vec4<f32>,
+    emissive: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    normal_scale: f32,
+    occlusion_strength: f32,
+};
+
 @group(0) @binding(0)
-var t_diffuse: texture_2d<f32>;
+var t_base_color: texture_2d<f32>;
 @group(0) @binding(1)
-var s_diffuse: sampler;
+var s_base_color: sampler;
+@group(0) @binding(2)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(3)
+var s_normal: sampler;
+@group(0) @binding(4)
+var t_metallic_roughness: texture_2d<f32>;
+@group(0) @binding(5)
+var s_metallic_roughness: sampler;
+@group(0) @binding(6)
+var t_occlusion: texture_2d<f32>;
+@group(0) @binding(7)
+var s_occlusion: sampler;
+@group(0) @binding(8)
+var t_emissive: texture_2d<f32>;
+@group(0) @binding(9)
+var s_emissive: sampler;
+@group(0) @binding(10)
+var<uniform> material: MaterialFactors;
+
+// Fraction of the shadow casting light reaching `world_position`, averaged
+// over the 3x3 shadow map texels around it.
+fn shadow_factor(world_position: vec3<f32>) -> f32 {
+    let light_position = shadow.light_view_proj * vec4<f32>(world_position, 1.0);
+    let ndc = light_position.xyz / light_position.w;
+    // Everything outside of the shadow map is lit.
+    if any(abs(ndc.xy) > vec2<f32>(1.0)) || ndc.z > 1.0 {
+        return 1.0;
+    }
+    let uv = ndc.xy * vec2<f32>(0.5, -0.5) + 0.5;
+
+    var lit = 0.0;
+    for (var y = -1; y <= 1; y += 1) {
+        for (var x = -1; x <= 1; x += 1) {
+            let offset = vec2<f32>(f32(x), f32(y)) * shadow.texel_size;
+            lit += textureSampleCompareLevel(t_shadow, s_shadow, uv + offset, ndc.z);
+        }
+    }
+
+    return lit / 9.0;
+}
+
+// Trowbridge-Reitz GGX normal distribution.
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let denom = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * denom * denom);
+}
+
+// Smith's method with Schlick-GGX for both the view and light directions.
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let r = roughness + 1.0;
+    let k = r * r / 8.0;
+    let ggx_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let ggx_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return ggx_v * ggx_l;
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Rough surfaces reflect less at grazing angles, the ambient light comes
+// from every microfacet orientation at once.
+fn fresnel_schlick_roughness(cos_theta: f32, f0: vec3<f32>, roughness: f32) -> vec3<f32> {
+    let f90 = max(vec3<f32>(1.0 - roughness), f0);
+    return f0 + (f90 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Diffuse and specular light from the environment maps.
+fn ambient_light(
+    normal: vec3<f32>,
+    view_dir: vec3<f32>,
+    base_color: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    f0: vec3<f32>
+) -> vec3<f32> {
+    let n_dot_v = max(dot(normal, view_dir), 1e-4);
+    let fresnel = fresnel_schlick_roughness(n_dot_v, f0, roughness);
+
+    let irradiance = textureSample(t_irradiance, s_environment, normal).rgb;
+    let diffuse = (1.0 - fresnel) * (1.0 - metallic) * base_color * irradiance;
+
+    let reflection = reflect(-view_dir, normal);
+    let lod = roughness * environment.prefiltered_max_lod;
+    let prefiltered = textureSampleLevel(t_prefiltered, s_environment, reflection, lod).rgb;
+    let brdf = textureSample(t_brdf_lut, s_environment, vec2<f32>(n_dot_v, roughness)).rg;
+    let specular = prefiltered * (fresnel * brdf.x + brdf.y);
+
+    return (diffuse + specular) * environment.intensity;
+}
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
-}
\ No newline at end of file
+    let base_color = textureSample(t_base_color, s_base_color, in.tex_coords) * material.base_color;
+    let metallic_roughness = textureSample(t_metallic_roughness, s_metallic_roughness, in.tex_coords);
+    let metallic = metallic_roughness.b * material.metallic;
+    // Perfectly smooth surfaces make the highlights of point lights vanish.
+    let roughness = clamp(metallic_roughness.g * material.roughness, MIN_ROUGHNESS, 1.0);
+    let occlusion = mix(
+        1.0,
+        textureSample(t_occlusion, s_occlusion, in.tex_coords).r,
+        material.occlusion_strength
+    );
+    let emissive = textureSample(t_emissive, s_emissive, in.tex_coords).rgb * material.emissive;
+
+    // Normal maps are in tangent space, the TBN matrix takes them to world
+    // space where the lighting happens.
+    let tangent_matrix = mat3x3<f32>(
+        normalize(in.world_tangent),
+        normalize(in.world_bitangent),
+        normalize(in.world_normal)
+    );
+    let object_normal = textureSample(t_normal, s_normal, in.tex_coords);
+    let tangent_normal = (object_normal.xyz * 2.0 - 1.0) * vec3<f32>(material.normal_scale, material.normal_scale, 1.0);
+    let normal = normalize(tangent_matrix * tangent_normal);
+
+    // Cook-Torrance
+    let view_dir = normalize(camera.view_pos.xyz - in.world_position);
+    let n_dot_v = max(dot(normal, view_dir), 1e-4);
+    // Dielectrics reflect about 4% head on, metals tint the reflection.
+    let f0 = mix(vec3<f32>(0.04), base_color.rgb, metallic);
+    var radiance = vec3<f32>(0.0);
+
+    for (var i = 0u; i < lights.count; i += 1u) {
+        let light = lights.lights[i];
+        var incidence = light_incidence(light, in.world_position);
+        if i == shadow.light_index {
+            incidence.strength *= shadow_factor(in.world_position);
+        }
+        let half_dir = normalize(view_dir + incidence.direction);
+        let n_dot_l = max(dot(normal, incidence.direction), 0.0);
+        let n_dot_h = max(dot(normal, half_dir), 0.0);
+
+        let fresnel = fresnel_schlick(max(dot(half_dir, view_dir), 0.0), f0);
+        let specular = distribution_ggx(n_dot_h, roughness)
+            * geometry_smith(n_dot_v, n_dot_l, roughness)
+            * fresnel / (4.0 * n_dot_v * n_dot_l + 1e-4);
+        // Metals don't have a diffuse part.
+        let diffuse = (1.0 - fresnel) * (1.0 - metallic) * base_color.rgb / PI;
+
+        radiance += (diffuse + specular) * light.color * incidence.strength * n_dot_l;
+    }
+
+    let ambient = ambient_light(normal, view_dir, base_color.rgb, metallic, roughness, f0) * occlusion;
+    let result = ambient + radiance + emissive;
+
+    return vec4<f32>(result, base_color.a);
+}
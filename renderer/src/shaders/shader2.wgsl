@@ -1,4 +1,5 @@
 struct CameraUniform {
+    view_pos: vec4<f32>,
     view_proj: mat4x4<f32>,
 };
 @group(1) @binding(0)
@@ -14,6 +15,9 @@ struct InstanceInput {
     @location(9) model_matrix_1: vec4<f32>,
     @location(10) model_matrix_2: vec4<f32>,
     @location(11) model_matrix_3: vec4<f32>,
+    @location(12) normal_matrix_0: vec3<f32>,
+    @location(13) normal_matrix_1: vec3<f32>,
+    @location(14) normal_matrix_2: vec3<f32>,
 }
 
 struct VertexOutput {
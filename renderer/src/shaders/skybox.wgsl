@@ -0,0 +1,21 @@
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@group(1) @binding(0)
+var t_environment: texture_cube<f32>;
+@group(1) @binding(1)
+var s_environment: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Point on the far plane seen through this pixel
+    let ndc = in.tex_coords * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0);
+    let far = camera.inv_view_proj * vec4<f32>(ndc, 1.0, 1.0);
+    let direction = far.xyz / far.w - camera.view_pos.xyz;
+    return vec4<f32>(textureSample(t_environment, s_environment, direction).rgb, 1.0);
+}
@@ -0,0 +1,54 @@
+// Maps the HDR scene to the displayable range of the output.
+
+const TONEMAPPER_REINHARD: u32 = 0u;
+const TONEMAPPER_ACES: u32 = 1u;
+
+struct Tonemap {
+    exposure: f32,
+    tonemapper: u32,
+    // Set when the output format isn't sRGB and won't encode the colors
+    // itself.
+    apply_gamma: u32,
+};
+
+@group(0) @binding(0)
+var t_hdr: texture_2d<f32>;
+@group(0) @binding(1)
+var s_hdr: sampler;
+@group(0) @binding(2)
+var<uniform> tonemap: Tonemap;
+
+fn reinhard(color: vec3<f32>) -> vec3<f32> {
+    return color / (1.0 + color);
+}
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve. The 0.6 brings the
+// fit's white point in line with the other operators.
+fn aces(color: vec3<f32>) -> vec3<f32> {
+    let x = color * 0.6;
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((x * (a * x + b)) / (x * (c * x + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let hdr = textureSample(t_hdr, s_hdr, in.tex_coords);
+    let exposed = hdr.rgb * tonemap.exposure;
+
+    var color: vec3<f32>;
+    if tonemap.tonemapper == TONEMAPPER_ACES {
+        color = aces(exposed);
+    } else {
+        color = reinhard(exposed);
+    }
+
+    if tonemap.apply_gamma != 0u {
+        color = pow(color, vec3<f32>(1.0 / 2.2));
+    }
+
+    return vec4<f32>(color, hdr.a);
+}
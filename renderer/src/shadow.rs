@@ -0,0 +1,330 @@
+/*!
+Shadow map for the first directional light in [`Lights`].
+
+Every frame the shadow casters are rendered into a depth texture from the
+light's point of view, which the lit shaders then sample with a comparison
+sampler over a 3x3 grid of texels (percentage closer filtering) to get soft
+edges.
+*/
+
+use std::ops::Range;
+
+use cgmath::SquareMatrix;
+use wgpu::util::DeviceExt;
+
+use crate::light::{LightKind, Lights};
+use crate::{camera, model, texture};
+
+/// Value of `light_index` in the shaders when no light casts shadows.
+const NO_SHADOW_LIGHT: u32 = u32::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadowSettings {
+    /// Width and height of the shadow map in texels.
+    pub resolution: u32,
+    /// Constant depth bias added to the shadow casters, fights shadow acne.
+    pub depth_bias: i32,
+    /// Depth bias scaled by the slope of the casters' triangles.
+    pub slope_bias: f32,
+    /// Half the size of the square around the origin covered by the shadow
+    /// map. Nothing outside of it is shadowed.
+    pub extent: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            resolution: 2048,
+            depth_bias: 2,
+            slope_bias: 2.0,
+            extent: 22.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowUniform {
+    light_view_proj: [[f32; 4]; 4],
+    light_index: u32,
+    texel_size: f32,
+    _padding: [u32; 2],
+}
+
+pub struct ShadowMap {
+    settings: ShadowSettings,
+    texture: texture::Texture,
+    buffer: wgpu::Buffer,
+    /// Layout of the bindings used when sampling the shadow map.
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    /// Layout of the bindings used when rendering into the shadow map.
+    pass_bind_group_layout: wgpu::BindGroupLayout,
+    pass_bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl ShadowMap {
+    pub fn new(device: &wgpu::Device, settings: ShadowSettings) -> Self {
+        let uniform_entry = |visibility| wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            count: None,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            visibility,
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("shadow_bind_group_layout"),
+            entries: &[
+                uniform_entry(wgpu::ShaderStages::FRAGMENT),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None,
+                },
+            ],
+        });
+
+        let pass_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("shadow_pass_bind_group_layout"),
+                entries: &[uniform_entry(wgpu::ShaderStages::VERTEX)],
+            });
+
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Shadow Buffer"),
+            contents: bytemuck::cast_slice(&[ShadowUniform {
+                light_view_proj: cgmath::Matrix4::identity().into(),
+                light_index: NO_SHADOW_LIGHT,
+                texel_size: 1.0 / settings.resolution as f32,
+                _padding: [0; 2],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let pass_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("shadow_pass_bind_group"),
+            layout: &pass_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+
+        let (texture, bind_group) =
+            Self::create_texture(device, &bind_group_layout, &buffer, settings);
+        let pipeline = Self::create_pipeline(device, &pass_bind_group_layout, settings);
+
+        Self {
+            settings,
+            texture,
+            buffer,
+            bind_group_layout,
+            bind_group,
+            pass_bind_group_layout,
+            pass_bind_group,
+            pipeline,
+        }
+    }
+
+    fn create_texture(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        buffer: &wgpu::Buffer,
+        settings: ShadowSettings,
+    ) -> (texture::Texture, wgpu::BindGroup) {
+        let texture = texture::Texture::create_depth_texture_sized(
+            device,
+            settings.resolution,
+            settings.resolution,
+            1,
+            "shadow_texture",
+        );
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("shadow_bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+            ],
+        });
+
+        (texture, bind_group)
+    }
+
+    fn create_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        settings: ShadowSettings,
+    ) -> wgpu::RenderPipeline {
+        use model::Vertex;
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            bind_group_layouts: &[layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::include_wgsl!("shaders/shadow.wgsl"));
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Shadow Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[
+                    model::ModelVertex::desc(),
+                    crate::instance::InstanceRaw::desc(),
+                ],
+            },
+            fragment: None,
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState {
+                    constant: settings.depth_bias,
+                    slope_scale: settings.slope_bias,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+
+    pub fn settings(&self) -> ShadowSettings {
+        self.settings
+    }
+
+    /// Recreates the shadow map and its pipeline for the new settings.
+    pub fn set_settings(&mut self, device: &wgpu::Device, settings: ShadowSettings) {
+        if settings.resolution != self.settings.resolution {
+            (self.texture, self.bind_group) =
+                Self::create_texture(device, &self.bind_group_layout, &self.buffer, settings);
+        }
+        self.pipeline = Self::create_pipeline(device, &self.pass_bind_group_layout, settings);
+        self.settings = settings;
+    }
+
+    pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    /// Points the shadow map along the first directional light of `lights`,
+    /// or disables shadows when there is none.
+    pub fn update(&self, queue: &wgpu::Queue, lights: &Lights) {
+        let shadow_light = lights
+            .iter()
+            .enumerate()
+            .find_map(|(i, (_, light))| match light.kind {
+                LightKind::Directional { direction } => Some((i, direction)),
+                _ => None,
+            });
+
+        let (light_index, light_view_proj) = match shadow_light {
+            Some((i, direction)) => (i as u32, self.light_view_proj(direction)),
+            None => (NO_SHADOW_LIGHT, cgmath::Matrix4::identity()),
+        };
+
+        let uniform = ShadowUniform {
+            light_view_proj: light_view_proj.into(),
+            light_index,
+            texel_size: 1.0 / self.settings.resolution as f32,
+            _padding: [0; 2],
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    fn light_view_proj(&self, direction: cgmath::Vector3<f32>) -> cgmath::Matrix4<f32> {
+        use cgmath::InnerSpace;
+
+        let extent = self.settings.extent;
+        let direction = direction.normalize();
+        let up = if direction.y.abs() > 0.99 {
+            cgmath::Vector3::unit_z()
+        } else {
+            cgmath::Vector3::unit_y()
+        };
+        let center = cgmath::Point3::new(0.0, 0.0, 0.0);
+        let eye = center - direction * extent * 2.0;
+
+        let view = cgmath::Matrix4::look_at_rh(eye, center, up);
+        let proj = cgmath::ortho(-extent, extent, -extent, extent, 0.1, extent * 4.0);
+
+        camera::OPENGL_TO_WGPU_MATRIX * proj * view
+    }
+
+    /// Records the pass rendering each `(model, instance_buffer, instances)`
+    /// caster into the shadow map.
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        casters: &[(&model::Model, &wgpu::Buffer, Range<u32>)],
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Shadow Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.pass_bind_group, &[]);
+        for (model, instance_buffer, instances) in casters {
+            render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+            for mesh in &model.meshes {
+                render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                render_pass
+                    .set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                render_pass.draw_indexed(0..mesh.num_elements, 0, instances.clone());
+            }
+        }
+    }
+}
@@ -0,0 +1,565 @@
+/*!
+Mesh simplification with quadric error metrics, to generate levels of detail
+(see [`crate::lod`]) offline.
+
+Every position gets the quadric of the planes of the triangles around it
+(Garland and Heckbert), and the edge whose collapse adds the least error is
+collapsed first, over and over until enough triangles are gone. Collapses
+move a position onto one of its neighbors, so the remaining vertices keep
+their original positions, UVs and normals.
+
+OBJ meshes loaded with `single_index` have a vertex per combination of
+position, UV and normal, so positions on a UV seam have a vertex on each
+side. A position is only collapsed if each of its vertices can follow a
+vertex of the other position on the same side of the seam, which keeps the
+seams intact, and seam and border edges get extra quadrics keeping their
+shape.
+*/
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap};
+use std::fmt::Write as _;
+
+/// Weight of the quadrics keeping seams and borders in place, relative to
+/// the ones of the triangles.
+const SEAM_WEIGHT: f64 = 10.0;
+
+/// Symmetric 4x4 matrix measuring the squared distance to a set of planes.
+#[derive(Debug, Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    /// Quadric of the plane `normal · p + distance = 0`.
+    fn from_plane(normal: [f64; 3], distance: f64, weight: f64) -> Self {
+        let [a, b, c] = normal;
+        let d = distance;
+        Self(
+            [
+                a * a,
+                a * b,
+                a * c,
+                a * d,
+                b * b,
+                b * c,
+                b * d,
+                c * c,
+                c * d,
+                d * d,
+            ]
+            .map(|q| q * weight),
+        )
+    }
+
+    fn add(&mut self, other: &Self) {
+        for (q, o) in self.0.iter_mut().zip(other.0) {
+            *q += o;
+        }
+    }
+
+    fn error(&self, [x, y, z]: [f64; 3]) -> f64 {
+        let q = &self.0;
+        q[0] * x * x
+            + 2.0 * q[1] * x * y
+            + 2.0 * q[2] * x * z
+            + 2.0 * q[3] * x
+            + q[4] * y * y
+            + 2.0 * q[5] * y * z
+            + 2.0 * q[6] * y
+            + q[7] * z * z
+            + 2.0 * q[8] * z
+            + q[9]
+    }
+}
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn normalize(a: [f64; 3]) -> Option<[f64; 3]> {
+    let length = dot(a, a).sqrt();
+    (length > f64::EPSILON).then(|| a.map(|x| x / length))
+}
+
+/// Moving position `from` onto position `to`, with the error it adds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Collapse {
+    error: f64,
+    from: usize,
+    to: usize,
+    /// Versions of the two positions when the error was computed.
+    versions: (u32, u32),
+}
+
+impl Eq for Collapse {}
+
+impl Ord for Collapse {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Smallest error first out of the max-heap, ties broken by the
+        // positions so the result doesn't depend on the order of the pushes.
+        other
+            .error
+            .total_cmp(&self.error)
+            .then_with(|| (other.from, other.to).cmp(&(self.from, self.to)))
+    }
+}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Triangles being collapsed, vertices are indices in the original mesh.
+struct Simplifier {
+    /// Position of each vertex.
+    vertex_positions: Vec<usize>,
+    positions: Vec<[f64; 3]>,
+    /// Vertices at each position, empty once it's been collapsed.
+    position_vertices: Vec<Vec<u32>>,
+    quadrics: Vec<Quadric>,
+    /// Bumped when a position moves or its quadric changes, to skip the
+    /// collapses computed before.
+    versions: Vec<u32>,
+    triangles: Vec<[u32; 3]>,
+    alive: Vec<bool>,
+    alive_count: usize,
+    /// Triangles each vertex is or was part of.
+    vertex_triangles: Vec<Vec<usize>>,
+}
+
+impl Simplifier {
+    fn new(mesh: &tobj::Mesh) -> Self {
+        let vertex_count = mesh.positions.len() / 3;
+
+        // Vertices on seams share their position bit for bit.
+        let mut position_ids = HashMap::new();
+        let mut positions = Vec::new();
+        let mut position_vertices: Vec<Vec<u32>> = Vec::new();
+        let vertex_positions = (0..vertex_count)
+            .map(|vertex| {
+                let position: [f32; 3] = mesh.positions[vertex * 3..vertex * 3 + 3]
+                    .try_into()
+                    .unwrap();
+                let id = *position_ids
+                    .entry(position.map(f32::to_bits))
+                    .or_insert_with(|| {
+                        positions.push(position.map(f64::from));
+                        position_vertices.push(Vec::new());
+                        positions.len() - 1
+                    });
+                position_vertices[id].push(vertex as u32);
+                id
+            })
+            .collect();
+
+        let triangles: Vec<[u32; 3]> = mesh
+            .indices
+            .chunks_exact(3)
+            .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+            .collect();
+        let mut vertex_triangles = vec![Vec::new(); vertex_count];
+        for (i, triangle) in triangles.iter().enumerate() {
+            for &vertex in triangle {
+                vertex_triangles[vertex as usize].push(i);
+            }
+        }
+
+        let mut simplifier = Self {
+            vertex_positions,
+            quadrics: vec![Quadric::default(); positions.len()],
+            versions: vec![0; positions.len()],
+            positions,
+            position_vertices,
+            alive: vec![true; triangles.len()],
+            alive_count: triangles.len(),
+            triangles,
+            vertex_triangles,
+        };
+        simplifier.compute_quadrics();
+
+        simplifier
+    }
+
+    fn position(&self, vertex: u32) -> usize {
+        self.vertex_positions[vertex as usize]
+    }
+
+    fn triangle_positions(&self, triangle: usize) -> [usize; 3] {
+        self.triangles[triangle].map(|vertex| self.position(vertex))
+    }
+
+    fn compute_quadrics(&mut self) {
+        // Edges of a single triangle are on a seam or a border. Sorted so
+        // the quadrics are summed in the same order every time.
+        let mut edge_triangles: BTreeMap<(u32, u32), Vec<usize>> = BTreeMap::new();
+        for (i, triangle) in self.triangles.iter().enumerate() {
+            for corner in 0..3 {
+                let (a, b) = (triangle[corner], triangle[(corner + 1) % 3]);
+                edge_triangles
+                    .entry((a.min(b), a.max(b)))
+                    .or_default()
+                    .push(i);
+            }
+        }
+
+        for i in 0..self.triangles.len() {
+            let [p0, p1, p2] = self.triangle_positions(i).map(|p| self.positions[p]);
+            let normal = cross(sub(p1, p0), sub(p2, p0));
+            let Some(unit_normal) = normalize(normal) else {
+                continue;
+            };
+            let area = dot(normal, normal).sqrt() / 2.0;
+            let quadric = Quadric::from_plane(unit_normal, -dot(unit_normal, p0), area);
+            for position in self.triangle_positions(i) {
+                self.quadrics[position].add(&quadric);
+            }
+        }
+
+        for ((a, b), triangles) in edge_triangles {
+            if triangles.len() != 1 {
+                continue;
+            }
+            let (pa, pb) = (self.position(a), self.position(b));
+            let [p0, p1, p2] = self
+                .triangle_positions(triangles[0])
+                .map(|p| self.positions[p]);
+            let edge = sub(self.positions[pb], self.positions[pa]);
+            let Some(normal) = normalize(cross(sub(p1, p0), sub(p2, p0))) else {
+                continue;
+            };
+            // Plane through the edge, perpendicular to its triangle.
+            let Some(side) = normalize(cross(edge, normal)) else {
+                continue;
+            };
+            let quadric = Quadric::from_plane(
+                side,
+                -dot(side, self.positions[pa]),
+                SEAM_WEIGHT * dot(edge, edge),
+            );
+            self.quadrics[pa].add(&quadric);
+            self.quadrics[pb].add(&quadric);
+        }
+    }
+
+    /// Live triangles around `position`.
+    fn position_triangles(&self, position: usize) -> Vec<usize> {
+        let mut triangles: Vec<_> = self.position_vertices[position]
+            .iter()
+            .flat_map(|&vertex| &self.vertex_triangles[vertex as usize])
+            .copied()
+            .filter(|&triangle| self.alive[triangle])
+            .collect();
+        triangles.sort_unstable();
+        triangles.dedup();
+
+        triangles
+    }
+
+    fn neighbors(&self, position: usize) -> BTreeSet<usize> {
+        self.position_triangles(position)
+            .into_iter()
+            .flat_map(|triangle| self.triangle_positions(triangle))
+            .filter(|&neighbor| neighbor != position)
+            .collect()
+    }
+
+    fn collapse(&self, from: usize, to: usize) -> Collapse {
+        let mut quadric = self.quadrics[from];
+        quadric.add(&self.quadrics[to]);
+        Collapse {
+            error: quadric.error(self.positions[to]),
+            from,
+            to,
+            versions: (self.versions[from], self.versions[to]),
+        }
+    }
+
+    /// Vertex at `to` each vertex at `from` turns into, `None` when the
+    /// collapse would tear a seam, fold triangles over or make the mesh
+    /// non-manifold.
+    fn vertex_map(&self, from: usize, to: usize) -> Option<HashMap<u32, u32>> {
+        let triangles = self.position_triangles(from);
+
+        // Each vertex of `from` follows a vertex of `to` it shares a
+        // triangle with, so both stay on the same side of any seam.
+        let mut vertex_map = HashMap::new();
+        for &vertex in &self.position_vertices[from] {
+            let mut vertex_triangles = self.vertex_triangles[vertex as usize]
+                .iter()
+                .filter(|&&triangle| self.alive[triangle])
+                .peekable();
+            if vertex_triangles.peek().is_none() {
+                continue;
+            }
+            let target = vertex_triangles
+                .flat_map(|&triangle| self.triangles[triangle])
+                .find(|&other| self.position(other) == to)?;
+            vertex_map.insert(vertex, target);
+        }
+
+        // The positions next to both are the ones across the triangles
+        // that go away, or the collapse pinches the surface.
+        let mut shared_triangles = 0;
+        let mut opposite = BTreeSet::new();
+        for &triangle in &triangles {
+            let positions = self.triangle_positions(triangle);
+            if positions.contains(&to) {
+                shared_triangles += 1;
+                opposite.extend(positions.into_iter().filter(|&p| p != from && p != to));
+            }
+        }
+        if shared_triangles == 0 {
+            return None;
+        }
+        let from_neighbors = self.neighbors(from);
+        let common = self
+            .neighbors(to)
+            .into_iter()
+            .filter(|neighbor| from_neighbors.contains(neighbor))
+            .count();
+        if common != opposite.len() {
+            return None;
+        }
+
+        // Triangles that stay mustn't flip.
+        for &triangle in &triangles {
+            let positions = self.triangle_positions(triangle);
+            if positions.contains(&to) {
+                continue;
+            }
+            let corners = positions.map(|p| self.positions[p]);
+            let moved = positions.map(|p| self.positions[if p == from { to } else { p }]);
+            let normal = |[p0, p1, p2]: [[f64; 3]; 3]| cross(sub(p1, p0), sub(p2, p0));
+            let (before, after) = (normalize(normal(corners))?, normalize(normal(moved))?);
+            if dot(before, after) < 0.0 {
+                return None;
+            }
+        }
+
+        Some(vertex_map)
+    }
+
+    fn apply(&mut self, from: usize, to: usize, vertex_map: &HashMap<u32, u32>) {
+        for triangle in self.position_triangles(from) {
+            if self.triangle_positions(triangle).contains(&to) {
+                self.alive[triangle] = false;
+                self.alive_count -= 1;
+                continue;
+            }
+            for vertex in &mut self.triangles[triangle] {
+                if let Some(&target) = vertex_map.get(vertex) {
+                    *vertex = target;
+                    self.vertex_triangles[target as usize].push(triangle);
+                }
+            }
+        }
+
+        self.position_vertices[from].clear();
+        let quadric = self.quadrics[from];
+        self.quadrics[to].add(&quadric);
+        self.versions[from] += 1;
+        self.versions[to] += 1;
+    }
+
+    fn run(&mut self, target_triangles: usize) {
+        let mut queue = BinaryHeap::new();
+        let mut edges = BTreeSet::new();
+        for triangle in 0..self.triangles.len() {
+            let positions = self.triangle_positions(triangle);
+            for corner in 0..3 {
+                let (a, b) = (positions[corner], positions[(corner + 1) % 3]);
+                if a != b && edges.insert((a.min(b), a.max(b))) {
+                    queue.push(self.collapse(a, b));
+                    queue.push(self.collapse(b, a));
+                }
+            }
+        }
+
+        while self.alive_count > target_triangles {
+            let Some(collapse) = queue.pop() else {
+                break;
+            };
+            let Collapse { from, to, .. } = collapse;
+            if collapse.versions != (self.versions[from], self.versions[to])
+                || self.position_vertices[from].is_empty()
+            {
+                continue;
+            }
+            let Some(vertex_map) = self.vertex_map(from, to) else {
+                continue;
+            };
+
+            self.apply(from, to, &vertex_map);
+            for neighbor in self.neighbors(to) {
+                queue.push(self.collapse(to, neighbor));
+                queue.push(self.collapse(neighbor, to));
+            }
+        }
+    }
+}
+
+/// Decimates `mesh` to about `ratio` (from 0 to 1) of its triangles, or as
+/// close as it gets without tearing seams or folding triangles over. `mesh`
+/// has to be loaded with `triangulate` and `single_index`.
+pub fn simplify(mesh: &tobj::Mesh, ratio: f32) -> tobj::Mesh {
+    let triangle_count = mesh.indices.len() / 3;
+    let target_triangles = (triangle_count as f32 * ratio).round() as usize;
+
+    let mut simplifier = Simplifier::new(mesh);
+    simplifier.run(target_triangles.max(1));
+
+    // Only the vertices of the remaining triangles are kept.
+    let mut vertex_indices = HashMap::new();
+    let mut simplified = tobj::Mesh {
+        material_id: mesh.material_id,
+        ..Default::default()
+    };
+    let alive_triangles = simplifier
+        .triangles
+        .iter()
+        .zip(&simplifier.alive)
+        .filter(|(_, &alive)| alive);
+    for (triangle, _) in alive_triangles {
+        for &vertex in triangle {
+            let index = *vertex_indices.entry(vertex).or_insert_with(|| {
+                let vertex = vertex as usize;
+                simplified
+                    .positions
+                    .extend_from_slice(attribute(&mesh.positions, vertex, 3));
+                simplified
+                    .texcoords
+                    .extend_from_slice(attribute(&mesh.texcoords, vertex, 2));
+                simplified
+                    .normals
+                    .extend_from_slice(attribute(&mesh.normals, vertex, 3));
+                (simplified.positions.len() / 3 - 1) as u32
+            });
+            simplified.indices.push(index);
+        }
+    }
+
+    simplified
+}
+
+/// Values of `vertex` in the per-vertex `values` of `size`, empty when the
+/// mesh doesn't have them.
+fn attribute(values: &[f32], vertex: usize, size: usize) -> &[f32] {
+    values
+        .get(vertex * size..vertex * size + size)
+        .unwrap_or(&[])
+}
+
+/// Wavefront OBJ text of `models`, using the materials of the MTL file
+/// `mtllib` by their name in `materials`.
+pub fn write_obj(
+    models: &[tobj::Model],
+    materials: &[tobj::Material],
+    mtllib: Option<&str>,
+) -> String {
+    let mut obj = String::new();
+    if let Some(mtllib) = mtllib {
+        writeln!(obj, "mtllib {mtllib}").unwrap();
+    }
+
+    // OBJ indices start at 1 and keep counting across objects.
+    let mut offset = 1;
+    for model in models {
+        let mesh = &model.mesh;
+        writeln!(obj, "o {}", model.name).unwrap();
+        for position in mesh.positions.chunks_exact(3) {
+            writeln!(obj, "v {} {} {}", position[0], position[1], position[2]).unwrap();
+        }
+        for tex_coords in mesh.texcoords.chunks_exact(2) {
+            writeln!(obj, "vt {} {}", tex_coords[0], tex_coords[1]).unwrap();
+        }
+        for normal in mesh.normals.chunks_exact(3) {
+            writeln!(obj, "vn {} {} {}", normal[0], normal[1], normal[2]).unwrap();
+        }
+        if let Some(material) = mesh.material_id.and_then(|id| materials.get(id)) {
+            writeln!(obj, "usemtl {}", material.name).unwrap();
+        }
+
+        let (has_tex_coords, has_normals) = (!mesh.texcoords.is_empty(), !mesh.normals.is_empty());
+        for triangle in mesh.indices.chunks_exact(3) {
+            obj.push('f');
+            for &index in triangle {
+                let index = index + offset;
+                match (has_tex_coords, has_normals) {
+                    (true, true) => write!(obj, " {index}/{index}/{index}"),
+                    (true, false) => write!(obj, " {index}/{index}"),
+                    (false, true) => write!(obj, " {index}//{index}"),
+                    (false, false) => write!(obj, " {index}"),
+                }
+                .unwrap();
+            }
+            obj.push('\n');
+        }
+        offset += (mesh.positions.len() / 3) as u32;
+    }
+
+    obj
+}
+
+/// Writes a copy of the OBJ asset `file_name` (in `renderer/assets`)
+/// decimated to each of `ratios` next to it, as `<name>_lod1.obj`,
+/// `<name>_lod2.obj` and so on. Returns the paths written. Fails without
+/// writing anything when a ratio isn't from 0 to 1.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn simplify_asset(file_name: &str, ratios: &[f32]) -> anyhow::Result<Vec<std::path::PathBuf>> {
+    if let Some(ratio) = ratios.iter().find(|ratio| !(0.0..=1.0).contains(*ratio)) {
+        return Err(anyhow::anyhow!("Ratio {ratio} isn't from 0 to 1."));
+    }
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("assets")
+        .join(file_name);
+    let (models, materials) = tobj::load_obj(
+        &path,
+        &tobj::LoadOptions {
+            triangulate: true,
+            single_index: true,
+            ..Default::default()
+        },
+    )?;
+    // Materials that don't load are left out of the copies too.
+    let materials = materials.unwrap_or_default();
+    let obj_text = std::fs::read_to_string(&path)?;
+    let mtllib = obj_text
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("mtllib "))
+        .map(str::trim);
+
+    let stem = path
+        .file_stem()
+        .ok_or_else(|| anyhow::anyhow!("{file_name} isn't a file name."))?
+        .to_string_lossy();
+    let mut written = Vec::new();
+    for (i, &ratio) in ratios.iter().enumerate() {
+        let simplified: Vec<_> = models
+            .iter()
+            .map(|model| tobj::Model {
+                mesh: simplify(&model.mesh, ratio),
+                name: model.name.clone(),
+            })
+            .collect();
+        let mut obj = format!("# {file_name} simplified to {ratio} of its triangles\n");
+        obj.push_str(&write_obj(&simplified, &materials, mtllib));
+
+        let lod_path = path.with_file_name(format!("{stem}_lod{}.obj", i + 1));
+        std::fs::write(&lod_path, obj)?;
+        written.push(lod_path);
+    }
+
+    Ok(written)
+}
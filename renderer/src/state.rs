@@ -1,6 +1,13 @@
-use crate::model::{DrawModel, Vertex};
-use crate::{assets, camera, instance, model, texture};
+use crate::model::{DrawLight, DrawModel, Vertex};
+use crate::{
+    assets, bloom, camera, capture, gpu_culling, hdr, ibl, instance, light, lod, model, post,
+    scene, scene_file, shadow, skybox, texture,
+};
 
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use anyhow::{anyhow, Result};
 use cgmath::prelude::*;
 use wgpu::util::DeviceExt;
 use winit::event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
@@ -16,27 +23,287 @@ const INSTANCE_DISPLACEMENT: cgmath::Vector3<f32> = cgmath::Vector3::new(
 );
 
 const N_PIPELINES: usize = 2;
+/// Factor the exposure is multiplied or divided by per key press.
+const EXPOSURE_STEP: f32 = 1.25;
+/// MSAA sample count used when the adapter supports it.
+const DEFAULT_SAMPLE_COUNT: u32 = 4;
+/// Degrees the lights orbit around the y axis on every update while
+/// orbiting.
+const LIGHT_ORBIT_SPEED: f32 = 1.0;
+/// Degrees the models turn around their own y axis on every update while
+/// spinning.
+const MODEL_SPIN_SPEED: f32 = 2.0;
+/// Height of the ground plane the cubes cast their shadows on.
+const GROUND_HEIGHT: f32 = -2.0;
+const GROUND_SIZE: f32 = 40.0;
+/// Width and height of the cubemap faces environment maps are projected on.
+const ENVIRONMENT_FACE_SIZE: u32 = 256;
+
+/// Error of the settings the adapter can't do, as opposed to ones that are
+/// wrong anywhere. Comes wrapped in an [`anyhow::Error`], find it with
+/// `downcast_ref`.
+#[derive(Debug)]
+pub struct Unsupported(pub String);
+
+impl std::fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Unsupported {}
+
+/// Where the frames produced by [`State::render`] end up.
+enum RenderTarget {
+    /// Presents to the surface of a winit window.
+    Window {
+        // Declared before `window` so it gets dropped first.
+        surface: wgpu::Surface,
+        window: Window,
+    },
+    /// Renders into a texture that can be read back, no display needed.
+    Offscreen { color_texture: texture::Texture },
+}
+
+/// What the scene pipelines are built from, kept around to rebuild them
+/// when the sample count changes.
+struct PipelineSources {
+    render_layout: wgpu::PipelineLayout,
+    shaders: [wgpu::ShaderModule; N_PIPELINES],
+    light_layout: wgpu::PipelineLayout,
+    light_shader: wgpu::ShaderModule,
+}
+
+impl PipelineSources {
+    /// The scene render pipelines and the light marker pipeline.
+    fn build(
+        &self,
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> ([wgpu::RenderPipeline; N_PIPELINES], wgpu::RenderPipeline) {
+        let render_pipelines = core::array::from_fn(|i| {
+            create_render_pipeline(
+                device,
+                &self.render_layout,
+                color_format,
+                Some(texture::Texture::DEPTH_FORMAT),
+                sample_count,
+                &[model::ModelVertex::desc(), instance::InstanceRaw::desc()],
+                &self.shaders[i],
+                format!("Render Pipeline {}", i + 1).as_str(),
+            )
+        });
+
+        let light_render_pipeline = create_render_pipeline(
+            device,
+            &self.light_layout,
+            color_format,
+            Some(texture::Texture::DEPTH_FORMAT),
+            sample_count,
+            &[model::ModelVertex::desc()],
+            &self.light_shader,
+            "Light Render Pipeline",
+        );
+
+        (render_pipelines, light_render_pipeline)
+    }
+}
+
+fn scene_model_mut(models: &mut [SceneModel], id: scene::ModelId) -> Result<&mut SceneModel> {
+    models
+        .get_mut(id.0)
+        .ok_or_else(|| anyhow!("There's no model {id:?}."))
+}
+
+/// A model the scene nodes can draw, with the instances of all the nodes
+/// drawing it.
+struct SceneModel {
+    model: model::Model,
+    /// Asset the model was loaded from, to save it in scene files.
+    file_name: Option<String>,
+    /// Factors of the materials as loaded, scene file overrides apply to
+    /// these rather than to the ones of the last scene.
+    base_factors: Vec<model::MaterialFactors>,
+    /// Every instance, they all cast shadows.
+    instances: instance::InstanceBuffer,
+    /// Instances in the camera frustum, when culling on the CPU.
+    visible_instances: instance::InstanceBuffer,
+    /// Ranges of `visible_instances` drawing each level of detail.
+    visible_levels: Vec<Range<u32>>,
+    /// Culls `instances` when the device can do it on the GPU.
+    gpu_culling: Option<gpu_culling::ModelCulling>,
+    /// Assets the levels of detail after the first were loaded from, to
+    /// save them in scene files.
+    lod_files: Vec<Option<String>>,
+    /// Level of detail each node drawing the model used last.
+    levels: BTreeMap<scene::NodeId, usize>,
+    /// Instances of each level of detail as of the last update, culled ones
+    /// included. `instances` has them one level after the other.
+    level_counts: Vec<u32>,
+}
+
+impl SceneModel {
+    fn new(
+        device: &wgpu::Device,
+        gpu_culling: Option<&gpu_culling::GpuCulling>,
+        model: model::Model,
+        file_name: Option<&str>,
+    ) -> Self {
+        let instances = match gpu_culling {
+            Some(_) => instance::InstanceBuffer::with_usage(device, 1, wgpu::BufferUsages::STORAGE),
+            None => instance::InstanceBuffer::new(device, 1),
+        };
+        let gpu_culling = gpu_culling
+            .map(|culling| gpu_culling::ModelCulling::new(device, culling, &model, &instances));
+        Self {
+            base_factors: model
+                .materials
+                .iter()
+                .map(|material| material.factors)
+                .collect(),
+            model,
+            file_name: file_name.map(str::to_string),
+            instances,
+            visible_instances: instance::InstanceBuffer::new(device, 1),
+            visible_levels: Vec::new(),
+            gpu_culling,
+            lod_files: Vec::new(),
+            levels: BTreeMap::new(),
+            level_counts: Vec::new(),
+        }
+    }
+
+    fn add_lod(
+        &mut self,
+        device: &wgpu::Device,
+        gpu_culling: Option<&gpu_culling::GpuCulling>,
+        lod: lod::Lod,
+        file_name: Option<&str>,
+    ) -> Result<()> {
+        self.model.add_lod(lod)?;
+        self.lod_files.push(file_name.map(str::to_string));
+        self.rebuild_gpu_culling(device, gpu_culling);
+
+        Ok(())
+    }
+
+    /// Replaces the levels of detail with `lods`, which
+    /// [`model::Model::check_lods`] accepted.
+    fn replace_lods(
+        &mut self,
+        device: &wgpu::Device,
+        gpu_culling: Option<&gpu_culling::GpuCulling>,
+        lods: Vec<(lod::Lod, String)>,
+    ) {
+        (self.model.lods, self.lod_files) = lods
+            .into_iter()
+            .map(|(lod, file_name)| (lod, Some(file_name)))
+            .unzip();
+        self.rebuild_gpu_culling(device, gpu_culling);
+    }
+
+    /// Makes room for the draw arguments of every level of detail.
+    fn rebuild_gpu_culling(
+        &mut self,
+        device: &wgpu::Device,
+        gpu_culling: Option<&gpu_culling::GpuCulling>,
+    ) {
+        self.gpu_culling = gpu_culling.map(|culling| {
+            gpu_culling::ModelCulling::new(device, culling, &self.model, &self.instances)
+        });
+    }
+
+    /// Picks the level of detail of every node of `nodes` drawing the model
+    /// and sorts them by level. Returns whether any node changed level.
+    fn sort_by_level(
+        &mut self,
+        camera: &camera::Camera,
+        nodes: &mut [(scene::NodeId, cgmath::Matrix4<f32>)],
+    ) -> bool {
+        let bounds = match self.model.bounds() {
+            Some(bounds) if !self.model.lods.is_empty() => bounds,
+            _ => {
+                self.levels.clear();
+                self.level_counts = vec![nodes.len() as u32];
+                return false;
+            }
+        };
+
+        let mut changed = false;
+        let mut levels = BTreeMap::new();
+        for (id, transform) in nodes.iter() {
+            let current = self.levels.get(id).copied().unwrap_or(0);
+            let metrics = lod::LodMetrics::new(camera, &bounds, transform);
+            let level = self.model.select_level(&metrics, current);
+            changed |= level != current;
+            levels.insert(*id, level);
+        }
+        nodes.sort_by_key(|(id, _)| levels[id]);
+
+        self.level_counts = vec![0; self.model.level_count()];
+        for level in levels.values() {
+            self.level_counts[*level] += 1;
+        }
+        self.levels = levels;
+
+        changed
+    }
+}
 
 pub struct State {
-    surface: wgpu::Surface,
+    target: RenderTarget,
     device: wgpu::Device,
     queue: wgpu::Queue,
+    /// Describes the render target, also for offscreen states where there
+    /// is no surface to configure.
     config: wgpu::SurfaceConfiguration,
     pub size: winit::dpi::PhysicalSize<u32>,
-    window: Window,
+    pipeline_sources: PipelineSources,
     render_pipelines: [wgpu::RenderPipeline; N_PIPELINES],
     current_pipeline: usize,
-    diffuse_bind_group: wgpu::BindGroup,
-    _diffuse_texture: texture::Texture,
+    light_render_pipeline: wgpu::RenderPipeline,
     camera: camera::Camera,
     pub camera_controller: camera::CameraController,
+    camera_bind_group_layout: wgpu::BindGroupLayout,
     camera_bind_group: wgpu::BindGroup,
     camera_buffer: wgpu::Buffer,
     camera_uniform: camera::CameraUniform,
-    instances: Vec<instance::Instance>,
-    instance_buffer: wgpu::Buffer,
+    ibl: ibl::Ibl,
+    /// Asset the environment was loaded from, to save it in scene files.
+    environment_file: Option<String>,
+    pub lights: light::Lights,
+    shadow_map: shadow::ShadowMap,
+    scene: scene::Scene,
+    models: Vec<SceneModel>,
+    /// Drawn as the light markers.
+    light_model: scene::ModelId,
+    spinning: bool,
+    /// Only the demo scene's lights orbit, the ones of a scene file stay
+    /// where the file puts them.
+    orbiting_lights: bool,
+    frustum_culling: bool,
+    /// Instances left out of the last frame by frustum culling on the CPU.
+    culled_count: usize,
+    /// `None` where compute shaders aren't available, e.g. WebGL2.
+    gpu_culling: Option<gpu_culling::GpuCulling>,
+    culling_on_gpu: bool,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    /// `None` where the adapter can't render into the texture formats, the
+    /// mips are downsampled on the CPU then.
+    mipmap_generator: Option<texture::MipmapGenerator>,
     depth_texture: texture::Texture,
-    obj_model: model::Model,
+    skybox: Option<skybox::Skybox>,
+    hdr: hdr::Hdr,
+    bloom: bloom::Bloom,
+    bloom_enabled: bool,
+    post_process: post::PostProcess,
+    sample_count: u32,
+    supported_sample_counts: Vec<u32>,
+    /// Rendered into instead of the target when using MSAA.
+    multisampled_texture: Option<texture::Texture>,
+    ground_model: model::Model,
+    ground_instance_buffer: wgpu::Buffer,
 }
 
 impl State {
@@ -66,21 +333,7 @@ impl State {
             .unwrap();
 
         // # Device and Queue
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::empty(),
-                    limits: if cfg!(target_arch = "wasm32") {
-                        wgpu::Limits::downlevel_webgl2_defaults()
-                    } else {
-                        wgpu::Limits::default()
-                    },
-                    label: None,
-                },
-                None,
-            )
-            .await
-            .unwrap();
+        let (device, queue) = Self::request_device(&adapter).await.unwrap();
 
         // # Surface Configuration
         let surface_caps = surface.get_capabilities(&adapter);
@@ -104,52 +357,131 @@ impl State {
 
         surface.configure(&device, &config);
 
-        // # Textures
+        Self::with_target(
+            RenderTarget::Window { surface, window },
+            &adapter,
+            device,
+            queue,
+            config,
+        )
+        .await
+    }
 
-        let diffuse_bytes = include_bytes!("../assets/happy-tree.png");
-        let diffuse_texture =
-            texture::Texture::from_bytes(&device, &queue, diffuse_bytes, "happy-tree.png").unwrap();
+    /// Creates a `State` that renders into an offscreen texture instead of a
+    /// window, so it works without a display (e.g. in CI).
+    ///
+    /// Prefers a fallback (software) adapter and uses whatever adapter is
+    /// available when there is none.
+    pub async fn new_headless(width: u32, height: u32) -> Result<Self> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            dx12_shader_compiler: Default::default(),
+        });
 
-        let diffuse_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("texture_bind_group_layout"),
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            multisampled: false,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-            });
+        let mut adapter = None;
+        for force_fallback_adapter in [true, false] {
+            adapter = instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::default(),
+                    compatible_surface: None,
+                    force_fallback_adapter,
+                })
+                .await;
+            if adapter.is_some() {
+                break;
+            }
+        }
+        let adapter =
+            adapter.ok_or(anyhow!("Couldn't find an adapter for offscreen rendering."))?;
 
-        let diffuse_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("diffuse_bind_group"),
-            layout: &diffuse_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
+        let (device, queue) = Self::request_device(&adapter).await?;
+
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            width,
+            height,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+            view_formats: vec![],
+        };
+
+        let color_texture =
+            texture::Texture::create_render_target(&device, &config, "offscreen_texture");
+
+        Ok(Self::with_target(
+            RenderTarget::Offscreen { color_texture },
+            &adapter,
+            device,
+            queue,
+            config,
+        )
+        .await)
+    }
+
+    async fn request_device(adapter: &wgpu::Adapter) -> Result<(wgpu::Device, wgpu::Queue)> {
+        let device = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    // Needed for sample counts other than 1 and 4.
+                    features: adapter.features()
+                        & wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES,
+                    limits: if cfg!(target_arch = "wasm32") {
+                        wgpu::Limits::downlevel_webgl2_defaults()
+                    } else {
+                        wgpu::Limits::default()
+                    },
+                    label: None,
                 },
-            ],
-        });
+                None,
+            )
+            .await?;
+
+        Ok(device)
+    }
+
+    /// Builds everything that doesn't depend on where the frames go.
+    async fn with_target(
+        target: RenderTarget,
+        adapter: &wgpu::Adapter,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        config: wgpu::SurfaceConfiguration,
+    ) -> Self {
+        let size = winit::dpi::PhysicalSize::new(config.width, config.height);
 
+        // # Textures
+
+        let texture_bind_group_layout = model::Material::bind_group_layout(&device);
+        let mipmap_generator = texture::MipmapGenerator::is_supported(adapter)
+            .then(|| texture::MipmapGenerator::new(&device));
+
+        // # HDR
+        let hdr = hdr::Hdr::new(adapter, &device, &config);
+        let bloom = bloom::Bloom::new(
+            &device,
+            &config,
+            hdr.format(),
+            hdr.view(),
+            bloom::BloomSettings::default(),
+        );
+        let post_process = post::PostProcess::new(&device, &config);
+
+        // # Multisampling
+        let supported_sample_counts = supported_sample_counts(
+            adapter,
+            &device,
+            &[hdr.format(), texture::Texture::DEPTH_FORMAT],
+        );
+        let sample_count = if supported_sample_counts.contains(&DEFAULT_SAMPLE_COUNT) {
+            DEFAULT_SAMPLE_COUNT
+        } else {
+            1
+        };
         let depth_texture =
-            texture::Texture::create_depth_texture(&device, &config, "depth_texture");
+            texture::Texture::create_depth_texture(&device, &config, sample_count, "depth_texture");
+        let multisampled_texture =
+            create_multisampled_texture(&device, &config, hdr.format(), sample_count);
 
         // # Camera
         let camera = camera::Camera {
@@ -171,161 +503,872 @@ impl State {
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
+        // # Image-based lighting
+        // The environment maps go with the camera, the bind groups are all
+        // taken on WebGL2.
+        let ibl = ibl::Ibl::new(&device, &queue, hdr.format());
+
+        let mut camera_layout_entries = vec![wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            count: None,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+        }];
+        camera_layout_entries.extend(ibl::Ibl::bind_group_layout_entries(1));
         let camera_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: Some("camera_bind_group_layout"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    count: None,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    visibility: wgpu::ShaderStages::VERTEX,
-                }],
+                entries: &camera_layout_entries,
             });
 
-        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("camera_bind_group"),
-            layout: &camera_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: camera_buffer.as_entire_binding(),
-            }],
-        });
+        let camera_bind_group =
+            create_camera_bind_group(&device, &camera_bind_group_layout, &camera_buffer, &ibl);
 
         let camera_controller = camera::CameraController::new(0.1);
 
-        // # Instance Buffers
-        let instances: Vec<_> = (0..N_INSTANCES[0])
-            .flat_map(|z| {
-                (0..N_INSTANCES[1]).map(move |x| {
-                    let position = cgmath::Vector3 {
-                        x: SPACE_BETWEEN * (x as f32),
-                        y: 0.0,
-                        z: SPACE_BETWEEN * (z as f32),
-                    } - INSTANCE_DISPLACEMENT;
-
-                    instance::Instance {
-                        position,
-                        rotation: if position.is_zero() {
-                            cgmath::Quaternion::from_axis_angle(
-                                cgmath::Vector3::unit_z(),
-                                cgmath::Deg(0.0),
-                            )
-                        } else {
-                            cgmath::Quaternion::from_axis_angle(
-                                position.normalize(),
-                                cgmath::Deg(45.0),
-                            )
-                        },
-                    }
-                })
-            })
-            .collect();
+        // # Lights
+        // The light markers read the lights from the vertex shader, which
+        // not every downlevel adapter can do with storage buffers.
+        let uses_storage = device.limits().max_storage_buffers_per_shader_stage > 0
+            && adapter
+                .get_downlevel_capabilities()
+                .flags
+                .contains(wgpu::DownlevelFlags::VERTEX_STORAGE);
+        let mut lights = light::Lights::new(&device, uses_storage);
+        lights
+            .add(light::Light::point(
+                (2.0, 2.0, 2.0).into(),
+                20.0,
+                [1.0, 1.0, 1.0],
+                8.0,
+            ))
+            .unwrap();
+        lights
+            .add(light::Light::directional(
+                (-1.0, -1.5, -0.5).into(),
+                [1.0, 0.95, 0.8],
+                3.0,
+            ))
+            .unwrap();
+        lights.update(&device, &queue);
+
+        // # Shadows
+        let shadow_map = shadow::ShadowMap::new(&device, shadow::ShadowSettings::default());
+        shadow_map.update(&queue, &lights);
 
-        let instance_data: Vec<_> = instances.iter().map(instance::Instance::to_raw).collect();
-        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Instance Buffer"),
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-            contents: bytemuck::cast_slice(&instance_data),
+        // # Instance Buffers
+        let ground_instance = instance::Instance {
+            position: cgmath::Vector3::new(0.0, GROUND_HEIGHT, 0.0),
+            rotation: cgmath::Quaternion::one(),
+            scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+        };
+        let ground_instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Ground Instance Buffer"),
+            usage: wgpu::BufferUsages::VERTEX,
+            contents: bytemuck::cast_slice(&[ground_instance.to_raw()]),
         });
 
         // # Render Pipeline
         let shaders = [
-            device.create_shader_module(wgpu::include_wgsl!("shaders/shader1.wgsl")),
+            device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("shader1.wgsl"),
+                source: wgpu::ShaderSource::Wgsl(
+                    lights
+                        .shader_source(include_str!("shaders/shader1.wgsl"), 2)
+                        .into(),
+                ),
+            }),
             device.create_shader_module(wgpu::include_wgsl!("shaders/shader2.wgsl")),
         ];
 
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[&diffuse_bind_group_layout, &camera_bind_group_layout],
+                bind_group_layouts: &[
+                    &texture_bind_group_layout,
+                    &camera_bind_group_layout,
+                    lights.bind_group_layout(),
+                    shadow_map.bind_group_layout(),
+                ],
                 push_constant_ranges: &[],
             });
 
-        let render_pipelines = core::array::from_fn(|i| {
-            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                label: Some(format!("Render Pipeline {}", i + 1).as_str()),
-                layout: Some(&render_pipeline_layout),
-                vertex: wgpu::VertexState {
-                    module: &shaders[i],
-                    entry_point: "vs_main",
-                    buffers: &[model::ModelVertex::desc(), instance::InstanceRaw::desc()],
-                },
-                fragment: Some(wgpu::FragmentState {
-                    module: &shaders[i],
-                    entry_point: "fs_main",
-                    targets: &[Some(wgpu::ColorTargetState {
-                        format: config.format,
-                        blend: Some(wgpu::BlendState::REPLACE),
-                        write_mask: wgpu::ColorWrites::ALL,
-                    })],
-                }),
-                primitive: wgpu::PrimitiveState {
-                    topology: wgpu::PrimitiveTopology::TriangleList,
-                    strip_index_format: None,
-                    front_face: wgpu::FrontFace::Ccw,
-                    cull_mode: Some(wgpu::Face::Back),
-                    polygon_mode: wgpu::PolygonMode::Fill,
-                    unclipped_depth: false,
-                    conservative: false,
-                },
-                depth_stencil: Some(wgpu::DepthStencilState {
-                    format: texture::Texture::DEPTH_FORMAT,
-                    depth_write_enabled: true,
-                    depth_compare: wgpu::CompareFunction::Less,
-                    stencil: wgpu::StencilState::default(),
-                    bias: wgpu::DepthBiasState::default(),
-                }),
-                multisample: wgpu::MultisampleState {
-                    count: 1,
-                    // not 0 => 0xffffffff all bits set to 1
-                    // this enables all samples
-                    mask: !0,
-                    // anti-aliasing related
-                    alpha_to_coverage_enabled: false,
-                },
-                multiview: None,
-            })
+        let light_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Light Pipeline Layout"),
+            bind_group_layouts: &[&camera_bind_group_layout, lights.bind_group_layout()],
+            push_constant_ranges: &[],
+        });
+        let light_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("light.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(
+                lights
+                    .shader_source(include_str!("shaders/light.wgsl"), 1)
+                    .into(),
+            ),
         });
 
+        let pipeline_sources = PipelineSources {
+            render_layout: render_pipeline_layout,
+            shaders,
+            light_layout,
+            light_shader,
+        };
+        let (render_pipelines, light_render_pipeline) =
+            pipeline_sources.build(&device, hdr.format(), sample_count);
+
         let current_pipeline = 0;
 
-        let obj_model = assets::load_model("cube.obj", &device, &queue, &diffuse_bind_group_layout)
-            .await
-            .unwrap();
+        // # Scene
+        let cube_model = assets::load_model(
+            "cube.obj",
+            &device,
+            &queue,
+            &texture_bind_group_layout,
+            mipmap_generator.as_ref(),
+        )
+        .await
+        .unwrap();
+        let gpu_culling = gpu_culling::GpuCulling::is_supported(adapter, &device)
+            .then(|| gpu_culling::GpuCulling::new(&device));
+        let models = vec![SceneModel::new(
+            &device,
+            gpu_culling.as_ref(),
+            cube_model,
+            Some("cube.obj"),
+        )];
+        let light_model = scene::ModelId(0);
+        let scene = create_cube_grid(light_model);
 
-        Self {
-            window,
-            surface,
+        let ground_model = assets::create_plane(
+            GROUND_SIZE,
+            [0.45, 0.45, 0.45, 1.0],
+            &device,
+            &queue,
+            &texture_bind_group_layout,
+        )
+        .unwrap();
+
+        let mut state = Self {
+            target,
             device,
             queue,
             config,
             size,
+            pipeline_sources,
             render_pipelines,
             current_pipeline,
-            diffuse_bind_group,
-            _diffuse_texture: diffuse_texture,
+            light_render_pipeline,
             camera,
+            camera_bind_group_layout,
             camera_bind_group,
             camera_buffer,
             camera_uniform,
+            ibl,
+            environment_file: None,
             camera_controller,
-            instances,
-            instance_buffer,
+            lights,
+            shadow_map,
+            scene,
+            models,
+            light_model,
+            spinning: false,
+            orbiting_lights: true,
+            frustum_culling: true,
+            culled_count: 0,
+            culling_on_gpu: gpu_culling.is_some(),
+            gpu_culling,
+            texture_bind_group_layout,
+            mipmap_generator,
             depth_texture,
-            obj_model,
+            skybox: None,
+            hdr,
+            bloom,
+            bloom_enabled: true,
+            post_process,
+            sample_count,
+            supported_sample_counts,
+            multisampled_texture,
+            ground_model,
+            ground_instance_buffer,
+        };
+        state.update_instance_buffers();
+
+        state
+    }
+
+    pub fn scene(&self) -> &scene::Scene {
+        &self.scene
+    }
+
+    /// Changes show up after the next [`Self::update`].
+    pub fn scene_mut(&mut self) -> &mut scene::Scene {
+        &mut self.scene
+    }
+
+    /// Makes `model` available to the scene nodes. Models that weren't
+    /// loaded from a file are left out of [`Self::scene_file`].
+    pub fn add_model(&mut self, model: model::Model) -> scene::ModelId {
+        self.models.push(SceneModel::new(
+            &self.device,
+            self.gpu_culling.as_ref(),
+            model,
+            None,
+        ));
+        scene::ModelId(self.models.len() - 1)
+    }
+
+    /// Loads a model with [`assets::load_model`] and adds it with
+    /// [`Self::add_model`].
+    pub async fn load_model(&mut self, file_name: &str) -> Result<scene::ModelId> {
+        let model = assets::load_model(
+            file_name,
+            &self.device,
+            &self.queue,
+            &self.texture_bind_group_layout,
+            self.mipmap_generator.as_ref(),
+        )
+        .await?;
+        self.models.push(SceneModel::new(
+            &self.device,
+            self.gpu_culling.as_ref(),
+            model,
+            Some(file_name),
+        ));
+
+        Ok(scene::ModelId(self.models.len() - 1))
+    }
+
+    /// Loads a scene file with [`assets::load_scene`] and replaces the
+    /// current scene with it, see [`Self::apply_scene`].
+    pub async fn load_scene(&mut self, file_name: &str) -> Result<()> {
+        let scene_file = assets::load_scene(file_name).await?;
+        self.apply_scene(&scene_file).await
+    }
+
+    /// Replaces the scene graph, lights, camera and environment with the
+    /// ones of `scene_file`. Models already loaded from the same file are
+    /// reused, with the material overrides applied to the factors they were
+    /// loaded with.
+    ///
+    /// Everything that can fail is done before the current scene is
+    /// replaced, so on error it's left as it was, apart from the models
+    /// loaded on the way.
+    pub async fn apply_scene(&mut self, scene_file: &scene_file::SceneFile) -> Result<()> {
+        let mut model_ids = std::collections::HashMap::new();
+        let mut new_lods = Vec::new();
+        for description in &scene_file.models {
+            let loaded = self
+                .models
+                .iter()
+                .position(|scene_model| scene_model.file_name.as_ref() == Some(&description.file));
+            let id = match loaded {
+                Some(index) => scene::ModelId(index),
+                None => self.load_model(&description.file).await?,
+            };
+            if let Some(lods) = self.prepare_lods(id, &description.lods).await? {
+                new_lods.push((id, lods));
+            }
+            model_ids.insert(description.name.as_str(), id);
+        }
+
+        let mut scene = scene::Scene::new();
+        add_scene_nodes(&mut scene, &scene_file.nodes, None, &model_ids)?;
+        let lights = scene_file
+            .lights
+            .iter()
+            .map(|description| description.clone().into())
+            .collect();
+        // `None` when the environment stays the same.
+        let environment = if scene_file.environment == self.environment_file {
+            None
+        } else if let Some(file_name) = &scene_file.environment {
+            let environment = assets::load_equirectangular(
+                file_name,
+                ENVIRONMENT_FACE_SIZE,
+                &self.device,
+                &self.queue,
+            )
+            .await?;
+            Some(Some(environment))
+        } else {
+            Some(None)
+        };
+        self.lights.replace_all(lights)?;
+
+        for (id, lods) in new_lods {
+            self.models[id.0].replace_lods(&self.device, self.gpu_culling.as_ref(), lods);
+        }
+        for description in &scene_file.models {
+            let scene_model = &mut self.models[model_ids[description.name.as_str()].0];
+            let materials = scene_model.model.materials.iter_mut();
+            for (material, base_factors) in materials.zip(&scene_model.base_factors) {
+                let mut factors = *base_factors;
+                for material_override in &description.materials {
+                    if material_override.applies_to(material) {
+                        material_override.apply(&mut factors);
+                    }
+                }
+                material.set_factors(&self.queue, factors);
+            }
+        }
+        self.scene = scene;
+        self.orbiting_lights = false;
+
+        let camera = &scene_file.camera;
+        self.camera.eye = camera.eye.into();
+        self.camera.target = camera.target.into();
+        self.camera.fovy = camera.fovy;
+        self.camera.znear = camera.znear;
+        self.camera.zfar = camera.zfar;
+
+        if let Some(environment) = environment {
+            self.set_environment(environment);
+            self.environment_file = scene_file.environment.clone();
+        }
+
+        Ok(())
+    }
+
+    /// Loads the levels of detail `lods` of the model `id` and checks they
+    /// can replace its current ones. `None` when it was already loaded with
+    /// them.
+    async fn prepare_lods(
+        &self,
+        id: scene::ModelId,
+        lods: &[scene_file::LodDescription],
+    ) -> Result<Option<Vec<(lod::Lod, String)>>> {
+        let scene_model = &self.models[id.0];
+        let loaded = scene_model
+            .lod_files
+            .iter()
+            .zip(&scene_model.model.lods)
+            .map(|(file, lod)| (file.as_deref(), lod.threshold));
+        let wanted = lods
+            .iter()
+            .map(|lod| (Some(lod.file.as_str()), lod.threshold));
+        if loaded.eq(wanted) {
+            return Ok(None);
+        }
+
+        let mut loaded = Vec::new();
+        for description in lods {
+            let lod = assets::load_lod(
+                &description.file,
+                description.threshold,
+                &scene_model.model,
+                &self.device,
+            )
+            .await?;
+            loaded.push(lod);
+        }
+        scene_model.model.check_lods(&loaded)?;
+
+        Ok(Some(
+            loaded
+                .into_iter()
+                .zip(lods.iter().map(|description| description.file.clone()))
+                .collect(),
+        ))
+    }
+
+    /// Describes the current scene, to save it with
+    /// [`scene_file::SceneFile::save`]. Models are named after their file.
+    pub fn scene_file(&self) -> scene_file::SceneFile {
+        let models = self
+            .models
+            .iter()
+            .filter_map(|scene_model| {
+                let file_name = scene_model.file_name.as_ref()?;
+                Some(scene_file::ModelDescription {
+                    name: file_name.clone(),
+                    file: file_name.clone(),
+                    materials: scene_model
+                        .model
+                        .materials
+                        .iter()
+                        .map(scene_file::MaterialOverride::from_material)
+                        .collect(),
+                    // Generated levels have no file to load them from.
+                    lods: scene_model
+                        .lod_files
+                        .iter()
+                        .zip(&scene_model.model.lods)
+                        .filter_map(|(file, lod)| {
+                            Some(scene_file::LodDescription {
+                                file: file.clone()?,
+                                threshold: lod.threshold,
+                            })
+                        })
+                        .collect(),
+                })
+            })
+            .collect();
+
+        scene_file::SceneFile {
+            camera: scene_file::CameraDescription {
+                eye: self.camera.eye.into(),
+                target: self.camera.target.into(),
+                fovy: self.camera.fovy,
+                znear: self.camera.znear,
+                zfar: self.camera.zfar,
+            },
+            environment: self.environment_file.clone(),
+            models,
+            nodes: self.describe_nodes(self.scene.roots()),
+            lights: self.lights.iter().map(|(_, light)| light.into()).collect(),
+        }
+    }
+
+    fn describe_nodes(&self, ids: &[scene::NodeId]) -> Vec<scene_file::NodeDescription> {
+        ids.iter()
+            .filter_map(|&id| self.scene.get(id))
+            .map(|node| scene_file::NodeDescription {
+                model: node.model().and_then(|model| {
+                    let Some(scene_model) = self.models.get(model.0) else {
+                        log::warn!("Node {} draws a model of another State.", node.name());
+                        return None;
+                    };
+                    scene_model.file_name.clone()
+                }),
+                children: self.describe_nodes(node.children()),
+                ..scene_file::NodeDescription::from_node(node)
+            })
+            .collect()
+    }
+
+    /// Saves the current scene, see [`Self::scene_file`].
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save_scene(&self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        self.scene_file().save(path)
+    }
+
+    pub fn spinning(&self) -> bool {
+        self.spinning
+    }
+
+    /// Turns every node drawing a model a bit on each update, see
+    /// [`MODEL_SPIN_SPEED`].
+    pub fn set_spinning(&mut self, spinning: bool) {
+        self.spinning = spinning;
+    }
+
+    pub fn orbiting_lights(&self) -> bool {
+        self.orbiting_lights
+    }
+
+    /// Moves every positioned light around the y axis a bit on each update,
+    /// see [`LIGHT_ORBIT_SPEED`]. [`Self::apply_scene`] turns it off.
+    pub fn set_orbiting_lights(&mut self, orbiting_lights: bool) {
+        self.orbiting_lights = orbiting_lights;
+    }
+
+    pub fn model(&self, id: scene::ModelId) -> Option<&model::Model> {
+        self.models.get(id.0).map(|scene_model| &scene_model.model)
+    }
+
+    /// Adds a level of detail less detailed than the others to the model
+    /// `id`, see [`model::Model::add_lod`].
+    pub fn add_lod(&mut self, id: scene::ModelId, lod: lod::Lod) -> Result<()> {
+        scene_model_mut(&mut self.models, id)?.add_lod(
+            &self.device,
+            self.gpu_culling.as_ref(),
+            lod,
+            None,
+        )
+    }
+
+    /// Loads a level of detail of the model `id` with [`assets::load_lod`]
+    /// and adds it with [`Self::add_lod`].
+    pub async fn load_lod(
+        &mut self,
+        id: scene::ModelId,
+        file_name: &str,
+        threshold: lod::LodThreshold,
+    ) -> Result<()> {
+        let scene_model = scene_model_mut(&mut self.models, id)?;
+        let lod = assets::load_lod(file_name, threshold, &scene_model.model, &self.device).await?;
+        scene_model.add_lod(
+            &self.device,
+            self.gpu_culling.as_ref(),
+            lod,
+            Some(file_name),
+        )
+    }
+
+    /// Instances of the model `id` drawn with each of its levels of detail as
+    /// of the last [`Self::update`], including the culled ones.
+    pub fn level_counts(&self, id: scene::ModelId) -> Option<&[u32]> {
+        self.models
+            .get(id.0)
+            .map(|scene_model| scene_model.level_counts.as_slice())
+    }
+
+    /// Writes the instances that changed since the last update, growing the
+    /// buffers that got too small.
+    /// When culling on the CPU, the instances in the camera frustum are
+    /// compacted into a buffer of their own and the others are counted in
+    /// [`Self::culled_count`]. Otherwise [`Self::draw`] culls them on the
+    /// GPU.
+    ///
+    /// Instances are sorted by level of detail, see [`lod`].
+    fn update_instance_buffers(&mut self) {
+        let scene_changed = self.scene.update_world_transforms();
+
+        let mut nodes = vec![Vec::new(); self.models.len()];
+        for (id, node) in self.scene.iter() {
+            if let Some(model) = node.model() {
+                // Ids come from State::add_model, but nothing stops one of
+                // another State from ending up in the scene.
+                match nodes.get_mut(model.0) {
+                    Some(nodes) => nodes.push((id, node.world_transform())),
+                    None => log::warn!("Node {} draws a model of another State.", node.name()),
+                }
+            }
+        }
+
+        let frustum = self.camera.frustum();
+        self.culled_count = 0;
+        for (scene_model, mut nodes) in self.models.iter_mut().zip(nodes) {
+            let levels_changed = scene_model.sort_by_level(&self.camera, &mut nodes);
+            let instances: Vec<_> = nodes
+                .iter()
+                .map(|&(_, transform)| instance::InstanceRaw::from_matrix(transform))
+                .collect();
+            if scene_changed || levels_changed {
+                scene_model
+                    .instances
+                    .update(&self.device, &self.queue, &instances);
+            }
+
+            if let (Some(model_culling), Some(culling), true) = (
+                &mut scene_model.gpu_culling,
+                &self.gpu_culling,
+                self.culling_on_gpu,
+            ) {
+                model_culling.update(
+                    &self.device,
+                    &self.queue,
+                    culling,
+                    &scene_model.model,
+                    &scene_model.instances,
+                    &scene_model.level_counts,
+                    self.frustum_culling.then_some(&frustum),
+                );
+                continue;
+            }
+
+            let bounds = scene_model.model.bounds().filter(|_| self.frustum_culling);
+            let mut visible_instances = Vec::with_capacity(instances.len());
+            scene_model.visible_levels.clear();
+            let mut start = 0;
+            for &count in &scene_model.level_counts {
+                let level_start = visible_instances.len() as u32;
+                let end = start + count as usize;
+                for ((_, transform), instance) in
+                    nodes[start..end].iter().zip(&instances[start..end])
+                {
+                    if bounds.is_none_or(|bounds| frustum.intersects(&bounds.transform(transform)))
+                    {
+                        visible_instances.push(*instance);
+                    }
+                }
+                scene_model
+                    .visible_levels
+                    .push(level_start..visible_instances.len() as u32);
+                start = end;
+            }
+            self.culled_count += instances.len() - visible_instances.len();
+            scene_model
+                .visible_instances
+                .update(&self.device, &self.queue, &visible_instances);
+        }
+    }
+
+    pub fn frustum_culling(&self) -> bool {
+        self.frustum_culling
+    }
+
+    /// Draws every instance when disabled, for comparison.
+    pub fn set_frustum_culling(&mut self, enabled: bool) {
+        self.frustum_culling = enabled;
+    }
+
+    /// Instances frustum culling on the CPU left out as of the last
+    /// [`Self::update`], always 0 when culling on the GPU, see
+    /// [`Self::read_culled_count`].
+    pub fn culled_count(&self) -> usize {
+        self.culled_count
+    }
+
+    /// Instances frustum culling left out of the last frame, read back from
+    /// the GPU when culling there.
+    pub async fn read_culled_count(&self) -> Result<usize> {
+        if !self.culling_on_gpu {
+            return Ok(self.culled_count);
+        }
+
+        let mut culled_count = 0;
+        for scene_model in &self.models {
+            if let Some(model_culling) = &scene_model.gpu_culling {
+                let visible = model_culling
+                    .read_visible_indices(&self.device, &self.queue)
+                    .await?;
+                culled_count += scene_model.instances.len() as usize - visible.len();
+            }
+        }
+
+        Ok(culled_count)
+    }
+
+    /// Whether frustum culling runs in a compute shader rather than on the
+    /// CPU.
+    pub fn gpu_culling(&self) -> bool {
+        self.culling_on_gpu
+    }
+
+    /// Fails when the device can't cull on the GPU, e.g. with the WebGL2
+    /// limits, culling on the CPU still works there.
+    pub fn set_gpu_culling(&mut self, enabled: bool) -> Result<()> {
+        if enabled && self.gpu_culling.is_none() {
+            return Err(Unsupported("The device can't cull on the GPU.".to_string()).into());
         }
+        self.culling_on_gpu = enabled;
+
+        Ok(())
     }
 
     pub fn next_pipeline(&mut self) {
         self.current_pipeline = (self.current_pipeline + 1) % N_PIPELINES;
     }
 
-    pub fn window(&self) -> &Window {
-        &self.window
+    pub fn set_pipeline(&mut self, index: usize) {
+        self.current_pipeline = index % N_PIPELINES;
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    /// Sample counts [`State::set_sample_count`] accepts on this adapter,
+    /// in increasing order. Always contains 1.
+    pub fn supported_sample_counts(&self) -> &[u32] {
+        &self.supported_sample_counts
+    }
+
+    /// Switches MSAA to `sample_count` samples per pixel, 1 turns it off.
+    pub fn set_sample_count(&mut self, sample_count: u32) -> Result<()> {
+        if !self.supported_sample_counts.contains(&sample_count) {
+            return Err(Unsupported(format!(
+                "Sample count {sample_count} isn't supported, use one of {:?}.",
+                self.supported_sample_counts
+            ))
+            .into());
+        }
+
+        self.sample_count = sample_count;
+        (self.render_pipelines, self.light_render_pipeline) =
+            self.pipeline_sources
+                .build(&self.device, self.hdr.format(), sample_count);
+        if let Some(skybox) = &mut self.skybox {
+            skybox.set_target(&self.device, self.hdr.format(), sample_count);
+        }
+        self.create_size_dependent_textures();
+
+        Ok(())
+    }
+
+    /// Cycles through the supported sample counts.
+    pub fn next_sample_count(&mut self) {
+        let counts = &self.supported_sample_counts;
+        let index = counts.iter().position(|&count| count == self.sample_count);
+        let next = counts[index.map_or(0, |i| (i + 1) % counts.len())];
+        self.set_sample_count(next).unwrap();
+        log::info!("MSAA sample count: {next}");
+    }
+
+    fn create_size_dependent_textures(&mut self) {
+        self.depth_texture = texture::Texture::create_depth_texture(
+            &self.device,
+            &self.config,
+            self.sample_count,
+            "depth_texture",
+        );
+        self.multisampled_texture = create_multisampled_texture(
+            &self.device,
+            &self.config,
+            self.hdr.format(),
+            self.sample_count,
+        );
+        self.hdr.resize(&self.device, &self.config);
+        self.bloom
+            .resize(&self.device, &self.config, self.hdr.view());
+        self.post_process.resize(&self.device, &self.config);
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.hdr.exposure()
+    }
+
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.hdr.set_exposure(&self.queue, exposure.max(0.0));
+    }
+
+    pub fn tonemapper(&self) -> hdr::Tonemapper {
+        self.hdr.tonemapper()
+    }
+
+    pub fn set_tonemapper(&mut self, tonemapper: hdr::Tonemapper) {
+        self.hdr.set_tonemapper(&self.queue, tonemapper);
+    }
+
+    pub fn camera(&self) -> &camera::Camera {
+        &self.camera
+    }
+
+    /// Changes show up after the next [`Self::update`].
+    pub fn camera_mut(&mut self) -> &mut camera::Camera {
+        &mut self.camera
+    }
+
+    pub fn skybox(&self) -> Option<&skybox::Skybox> {
+        self.skybox.as_ref()
+    }
+
+    /// Draws `cubemap` behind the scene instead of the clear color, `None`
+    /// goes back to the clear color.
+    pub fn set_skybox(&mut self, cubemap: Option<texture::Texture>) {
+        self.skybox = cubemap.map(|cubemap| {
+            skybox::Skybox::new(
+                &self.device,
+                cubemap,
+                &self.camera_bind_group_layout,
+                self.hdr.format(),
+                self.sample_count,
+            )
+        });
+    }
+
+    /// Lights the scene with `environment` and draws it as the skybox,
+    /// `None` goes back to a uniform ambient light and the clear color. See
+    /// [`ibl::Ibl::set_environment`].
+    pub fn set_environment(&mut self, environment: Option<texture::Texture>) {
+        self.environment_file = None;
+        self.ibl
+            .set_environment(&self.device, &self.queue, environment.as_ref());
+        self.camera_bind_group = create_camera_bind_group(
+            &self.device,
+            &self.camera_bind_group_layout,
+            &self.camera_buffer,
+            &self.ibl,
+        );
+        self.set_skybox(environment);
+    }
+
+    /// Loads an equirectangular environment map, e.g. a `.hdr` file, and
+    /// sets it with [`Self::set_environment`].
+    pub async fn load_environment(&mut self, file_name: &str) -> Result<()> {
+        let environment = assets::load_equirectangular(
+            file_name,
+            ENVIRONMENT_FACE_SIZE,
+            &self.device,
+            &self.queue,
+        )
+        .await?;
+        self.set_environment(Some(environment));
+        self.environment_file = Some(file_name.to_string());
+
+        Ok(())
+    }
+
+    pub fn environment_intensity(&self) -> f32 {
+        self.ibl.intensity()
+    }
+
+    pub fn set_environment_intensity(&mut self, intensity: f32) {
+        self.ibl.set_intensity(&self.queue, intensity.max(0.0));
+    }
+
+    pub fn bloom_enabled(&self) -> bool {
+        self.bloom_enabled
+    }
+
+    pub fn set_bloom_enabled(&mut self, enabled: bool) {
+        self.bloom_enabled = enabled;
+    }
+
+    pub fn bloom_settings(&self) -> bloom::BloomSettings {
+        self.bloom.settings()
+    }
+
+    pub fn set_bloom_settings(&mut self, settings: bloom::BloomSettings) {
+        self.bloom.set_settings(&self.queue, settings);
+    }
+
+    /// Passes applied after tonemapping, see [`post::PostProcess`].
+    pub fn post_process(&self) -> &post::PostProcess {
+        &self.post_process
+    }
+
+    pub fn post_process_mut(&mut self) -> &mut post::PostProcess {
+        &mut self.post_process
+    }
+
+    /// Appends an enabled pass to the post-processing chain, see
+    /// [`post::PostProcess::add_pass`].
+    pub async fn add_post_pass(
+        &mut self,
+        name: &str,
+        shader: &str,
+        params: [f32; 4],
+    ) -> Result<()> {
+        self.post_process
+            .add_pass(&self.device, name, shader, params)
+            .await
+    }
+
+    /// Enables or disables the post-processing pass at `index`.
+    pub fn toggle_post_pass(&mut self, index: usize) {
+        if let Some(pass) = self.post_process.passes_mut().get_mut(index) {
+            pass.enabled = !pass.enabled;
+            log::info!("Post-processing pass {}: {}", pass.name(), pass.enabled);
+        }
+    }
+
+    pub fn shadow_settings(&self) -> shadow::ShadowSettings {
+        self.shadow_map.settings()
+    }
+
+    pub fn set_shadow_settings(&mut self, settings: shadow::ShadowSettings) {
+        self.shadow_map.set_settings(&self.device, settings);
+        self.shadow_map.update(&self.queue, &self.lights);
+    }
+
+    /// Device the state renders with, to create resources such as textures
+    /// for it.
+    pub fn device(&self) -> &wgpu::Device {
+        &self.device
+    }
+
+    pub fn queue(&self) -> &wgpu::Queue {
+        &self.queue
+    }
+
+    /// What loading textures generates their mips with, to pass to
+    /// [`assets::load_model`].
+    pub fn mipmap_generator(&self) -> Option<&texture::MipmapGenerator> {
+        self.mipmap_generator.as_ref()
+    }
+
+    /// The window being rendered to, `None` for offscreen states.
+    pub fn window(&self) -> Option<&Window> {
+        match &self.target {
+            RenderTarget::Window { window, .. } => Some(window),
+            RenderTarget::Offscreen { .. } => None,
+        }
     }
 
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
@@ -333,9 +1376,19 @@ impl State {
             self.size = new_size;
             self.config.width = new_size.width;
             self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
-            self.depth_texture =
-                texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture")
+            match &mut self.target {
+                RenderTarget::Window { surface, .. } => {
+                    surface.configure(&self.device, &self.config)
+                }
+                RenderTarget::Offscreen { color_texture } => {
+                    *color_texture = texture::Texture::create_render_target(
+                        &self.device,
+                        &self.config,
+                        "offscreen_texture",
+                    )
+                }
+            }
+            self.create_size_dependent_textures();
         }
     }
 
@@ -355,6 +1408,14 @@ impl State {
                 ..
             } => *control_flow = ControlFlow::Exit,
             WE::KeyboardInput { input, .. } => self.keyboard_input(input),
+            WE::MouseInput { state, button, .. } => {
+                self.camera_controller.process_mouse_button(state, button)
+            }
+            WE::CursorMoved { position, .. } => {
+                self.camera_controller.process_cursor_moved(*position)
+            }
+            WE::CursorLeft { .. } => self.camera_controller.process_cursor_left(),
+            WE::MouseWheel { delta, .. } => self.camera_controller.process_scroll(delta),
             _ => {}
         };
     }
@@ -367,6 +1428,52 @@ impl State {
                 virtual_keycode: Some(VirtualKeyCode::Q),
                 ..
             } => self.next_pipeline(),
+            Input {
+                state: ElementState::Pressed,
+                virtual_keycode: Some(VirtualKeyCode::P),
+                ..
+            } => self.screenshot(),
+            Input {
+                state: ElementState::Pressed,
+                virtual_keycode: Some(VirtualKeyCode::M),
+                ..
+            } => self.next_sample_count(),
+            Input {
+                state: ElementState::Pressed,
+                virtual_keycode: Some(VirtualKeyCode::T),
+                ..
+            } => self.set_tonemapper(self.tonemapper().next()),
+            Input {
+                state: ElementState::Pressed,
+                virtual_keycode: Some(VirtualKeyCode::B),
+                ..
+            } => self.set_bloom_enabled(!self.bloom_enabled()),
+            Input {
+                state: ElementState::Pressed,
+                virtual_keycode: Some(VirtualKeyCode::R),
+                ..
+            } => self.set_spinning(!self.spinning()),
+            Input {
+                state: ElementState::Pressed,
+                virtual_keycode: Some(VirtualKeyCode::Equals | VirtualKeyCode::NumpadAdd),
+                ..
+            } => self.set_exposure(self.exposure() * EXPOSURE_STEP),
+            Input {
+                state: ElementState::Pressed,
+                virtual_keycode: Some(VirtualKeyCode::Minus | VirtualKeyCode::NumpadSubtract),
+                ..
+            } => self.set_exposure(self.exposure() / EXPOSURE_STEP),
+            Input {
+                state: ElementState::Pressed,
+                virtual_keycode:
+                    Some(
+                        key @ (VirtualKeyCode::Key1
+                        | VirtualKeyCode::Key2
+                        | VirtualKeyCode::Key3
+                        | VirtualKeyCode::Key4),
+                    ),
+                ..
+            } => self.toggle_post_pass(*key as usize - VirtualKeyCode::Key1 as usize),
             Input {
                 state: input_state,
                 virtual_keycode: Some(virtual_keycode),
@@ -386,25 +1493,165 @@ impl State {
             0,
             bytemuck::cast_slice(&[self.camera_uniform]),
         );
+
+        if self.orbiting_lights {
+            let rotation = cgmath::Quaternion::from_axis_angle(
+                cgmath::Vector3::unit_y(),
+                cgmath::Deg(LIGHT_ORBIT_SPEED),
+            );
+            self.lights
+                .iter_mut()
+                .filter_map(|(_, light)| light.position_mut())
+                .for_each(|position| *position = rotation * *position);
+        }
+        self.lights.update(&self.device, &self.queue);
+        self.shadow_map.update(&self.queue, &self.lights);
+        self.post_process.update(&self.queue);
+
+        if self.spinning {
+            let spin = cgmath::Quaternion::from_angle_y(cgmath::Deg(MODEL_SPIN_SPEED));
+            let drawn_nodes: Vec<_> = self
+                .scene
+                .iter()
+                .filter(|(_, node)| node.model().is_some())
+                .map(|(id, _)| id)
+                .collect();
+            for id in drawn_nodes {
+                let transform = self.scene.transform_mut(id).unwrap();
+                transform.rotation = transform.rotation * spin;
+            }
+        }
+        self.update_instance_buffers();
     }
 
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let output = self.surface.get_current_texture()?;
-        let view = output
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("Render Encoder"),
             });
 
+        match &self.target {
+            RenderTarget::Window { surface, .. } => {
+                let output = surface.get_current_texture()?;
+                let view = output
+                    .texture
+                    .create_view(&wgpu::TextureViewDescriptor::default());
+                self.draw(&mut encoder, &view);
+                self.queue.submit(std::iter::once(encoder.finish()));
+                output.present();
+            }
+            RenderTarget::Offscreen { color_texture } => {
+                self.draw(&mut encoder, &color_texture.view);
+                self.queue.submit(std::iter::once(encoder.finish()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Renders a frame offscreen and reads it back from the GPU.
+    pub async fn render_to_image(&mut self) -> Result<image::RgbaImage> {
+        self.render()?;
+        self.capture_frame().read(&self.device).await
+    }
+
+    /// Copies the current frame into a staging buffer to be read with
+    /// [`capture::Readback::read`].
+    ///
+    /// Surface textures can't always be copied from (e.g. on WebGL2), so for
+    /// windows the scene is drawn again into a texture of the same format.
+    pub fn capture_frame(&mut self) -> capture::Readback {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Capture Encoder"),
+            });
+
+        let readback = match &self.target {
+            RenderTarget::Window { .. } => {
+                let capture_texture = texture::Texture::create_render_target(
+                    &self.device,
+                    &self.config,
+                    "capture_texture",
+                );
+                self.draw(&mut encoder, &capture_texture.view);
+                capture::Readback::new(&self.device, &mut encoder, &capture_texture.texture)
+            }
+            RenderTarget::Offscreen { color_texture } => {
+                capture::Readback::new(&self.device, &mut encoder, &color_texture.texture)
+            }
+        };
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        readback
+    }
+
+    /// Saves the current frame as a PNG in the working directory, or hands
+    /// it to the browser as a download on wasm.
+    pub fn screenshot(&mut self) {
+        let file_name = format!("screenshot-{}.png", capture::timestamp());
+        let frame = self.capture_frame().read(&self.device);
+
+        cfg_if::cfg_if! {
+            if #[cfg(target_arch = "wasm32")] {
+                wasm_bindgen_futures::spawn_local(async move {
+                    match frame.await.and_then(|img| capture::download_png(&img, &file_name)) {
+                        Ok(url) => log::info!("Screenshot available at {url}"),
+                        Err(e) => log::error!("Couldn't take screenshot: {e:?}"),
+                    }
+                });
+            } else {
+                match pollster::block_on(frame).and_then(|img| capture::save_png(&img, &file_name)) {
+                    Ok(()) => log::info!("Screenshot saved to {file_name}"),
+                    Err(e) => log::error!("Couldn't take screenshot: {e:?}"),
+                }
+            }
+        }
+    }
+
+    /// Records the shadow, scene, bloom, tonemapping and post-processing
+    /// render passes into `view`.
+    fn draw(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        let drawn_models: Vec<_> = self
+            .models
+            .iter()
+            .filter(|scene_model| !scene_model.instances.is_empty())
+            .collect();
+        let casters: Vec<_> = drawn_models
+            .iter()
+            .map(|scene_model| {
+                (
+                    &scene_model.model,
+                    scene_model.instances.buffer(),
+                    0..scene_model.instances.len(),
+                )
+            })
+            .collect();
+        self.shadow_map.render(encoder, &casters);
+
+        if let (Some(culling), true) = (&self.gpu_culling, self.culling_on_gpu) {
+            let models: Vec<_> = drawn_models
+                .iter()
+                .filter_map(|scene_model| scene_model.gpu_culling.as_ref())
+                .collect();
+            culling.cull(encoder, &models);
+        }
+
+        // The scene goes into the HDR texture, with MSAA the samples are
+        // resolved into it at the end of the pass and don't need to be
+        // stored.
+        let (scene_view, resolve_target) = match &self.multisampled_texture {
+            Some(multisampled_texture) => (&multisampled_texture.view, Some(self.hdr.view())),
+            None => (self.hdr.view(), None),
+        };
+
         // Start render pass
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Render Pass"),
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: &view,
-                resolve_target: None,
+                view: scene_view,
+                resolve_target,
                 ops: wgpu::Operations {
                     load: wgpu::LoadOp::Clear(wgpu::Color {
                         r: 0.1,
@@ -412,7 +1659,7 @@ impl State {
                         b: 0.3,
                         a: 1.0,
                     }),
-                    store: true,
+                    store: resolve_target.is_none(),
                 },
             })],
             depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
@@ -425,20 +1672,274 @@ impl State {
             }),
         });
 
-        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-        render_pass.set_pipeline(&self.render_pipelines[self.current_pipeline]);
-        render_pass.draw_model_instanced(
-            &self.obj_model,
+        if let Some(skybox) = &self.skybox {
+            skybox.render(&mut render_pass, &self.camera_bind_group);
+        }
+
+        render_pass.set_pipeline(&self.light_render_pipeline);
+        render_pass.draw_light_model_instanced(
+            &self.models[self.light_model.0].model,
             &self.camera_bind_group,
-            0..self.instances.len() as u32,
+            self.lights.bind_group(),
+            0..self.lights.len() as u32,
         );
 
-        drop(render_pass);
+        render_pass.set_pipeline(&self.render_pipelines[self.current_pipeline]);
+        render_pass.set_bind_group(3, self.shadow_map.bind_group(), &[]);
+        // Each level of detail starts its own instances at the start of the
+        // instance buffer, backends without a first instance (e.g. WebGL2)
+        // need that.
+        let instance_offset = |start: u32| {
+            start as wgpu::BufferAddress
+                * std::mem::size_of::<instance::InstanceRaw>() as wgpu::BufferAddress
+        };
+        for scene_model in &self.models {
+            let model = &scene_model.model;
+            match &scene_model.gpu_culling {
+                Some(model_culling) if self.culling_on_gpu => {
+                    for level in 0..model.level_count() {
+                        let Some((start, draw_offset)) = model_culling.level(level) else {
+                            continue;
+                        };
+                        render_pass.set_vertex_buffer(
+                            1,
+                            model_culling
+                                .visible_buffer()
+                                .slice(instance_offset(start)..),
+                        );
+                        render_pass.draw_model_level_indirect(
+                            model,
+                            level,
+                            &self.camera_bind_group,
+                            self.lights.bind_group(),
+                            model_culling.draw_buffer(),
+                            draw_offset,
+                        );
+                    }
+                }
+                _ => {
+                    for (level, range) in scene_model.visible_levels.iter().enumerate() {
+                        if range.is_empty() {
+                            continue;
+                        }
+                        render_pass.set_vertex_buffer(
+                            1,
+                            scene_model
+                                .visible_instances
+                                .buffer()
+                                .slice(instance_offset(range.start)..),
+                        );
+                        render_pass.draw_model_level_instanced(
+                            model,
+                            level,
+                            &self.camera_bind_group,
+                            self.lights.bind_group(),
+                            0..range.len() as u32,
+                        );
+                    }
+                }
+            }
+        }
+        render_pass.set_vertex_buffer(1, self.ground_instance_buffer.slice(..));
+        render_pass.draw_model(
+            &self.ground_model,
+            &self.camera_bind_group,
+            self.lights.bind_group(),
+        );
         // End render pass, releases `encoder`
+        drop(render_pass);
 
-        self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+        if self.bloom_enabled {
+            self.bloom.render(encoder, self.hdr.view());
+        }
+        if self.post_process.is_active() {
+            self.hdr.tonemap(encoder, self.post_process.input_view());
+            self.post_process.render(encoder, view);
+        } else {
+            self.hdr.tonemap(encoder, view);
+        }
+    }
+}
 
-        Ok(())
+/// The default scene: a grid of cubes drawing `model` under a root node
+/// centering it, each tilted away from the center.
+fn create_cube_grid(model: scene::ModelId) -> scene::Scene {
+    let mut scene = scene::Scene::new();
+    let grid = scene
+        .add_node(
+            "grid",
+            scene::Transform::from_translation(-INSTANCE_DISPLACEMENT),
+            None,
+            None,
+        )
+        .unwrap();
+
+    for z in 0..N_INSTANCES[0] {
+        for x in 0..N_INSTANCES[1] {
+            let translation = cgmath::Vector3 {
+                x: SPACE_BETWEEN * (x as f32),
+                y: 0.0,
+                z: SPACE_BETWEEN * (z as f32),
+            };
+            let position = translation - INSTANCE_DISPLACEMENT;
+            let transform = scene::Transform {
+                translation,
+                rotation: if position.is_zero() {
+                    cgmath::Quaternion::one()
+                } else {
+                    cgmath::Quaternion::from_axis_angle(position.normalize(), cgmath::Deg(45.0))
+                },
+                ..Default::default()
+            };
+            scene
+                .add_node(&format!("cube {x} {z}"), transform, Some(model), Some(grid))
+                .unwrap();
+        }
     }
+
+    scene
+}
+
+/// Adds the nodes of `descriptions` and their descendants under `parent`.
+fn add_scene_nodes(
+    scene: &mut scene::Scene,
+    descriptions: &[scene_file::NodeDescription],
+    parent: Option<scene::NodeId>,
+    model_ids: &std::collections::HashMap<&str, scene::ModelId>,
+) -> Result<()> {
+    for description in descriptions {
+        let model =
+            match &description.model {
+                Some(name) => Some(*model_ids.get(name.as_str()).ok_or_else(|| {
+                    anyhow!("Node {} uses unknown model {name}.", description.name)
+                })?),
+                None => None,
+            };
+        let id = scene.add_node(&description.name, description.transform(), model, parent)?;
+        add_scene_nodes(scene, &description.children, Some(id), model_ids)?;
+    }
+
+    Ok(())
+}
+
+fn create_camera_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    camera_buffer: &wgpu::Buffer,
+    ibl: &ibl::Ibl,
+) -> wgpu::BindGroup {
+    let mut entries = vec![wgpu::BindGroupEntry {
+        binding: 0,
+        resource: camera_buffer.as_entire_binding(),
+    }];
+    entries.extend(ibl.bind_group_entries(1));
+
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("camera_bind_group"),
+        layout,
+        entries: &entries,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+fn create_render_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    depth_format: Option<wgpu::TextureFormat>,
+    sample_count: u32,
+    vertex_layouts: &[wgpu::VertexBufferLayout],
+    shader: &wgpu::ShaderModule,
+    label: &str,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: vertex_layouts,
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: color_format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            polygon_mode: wgpu::PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
+            format,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            // not 0 => 0xffffffff all bits set to 1
+            // this enables all samples
+            mask: !0,
+            // anti-aliasing related
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
+
+/// `None` when `sample_count` is 1 and the target can be rendered into
+/// directly.
+fn create_multisampled_texture(
+    device: &wgpu::Device,
+    config: &wgpu::SurfaceConfiguration,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> Option<texture::Texture> {
+    (sample_count > 1).then(|| {
+        texture::Texture::create_multisampled_target(
+            device,
+            config,
+            format,
+            sample_count,
+            "multisampled_texture",
+        )
+    })
+}
+
+/// Sample counts all of `formats` can be rendered with, and color formats
+/// resolved from. Without `TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES` only
+/// 1 and 4 are allowed.
+fn supported_sample_counts(
+    adapter: &wgpu::Adapter,
+    device: &wgpu::Device,
+    formats: &[wgpu::TextureFormat],
+) -> Vec<u32> {
+    let adapter_specific = device
+        .features()
+        .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+
+    [1, 2, 4, 8]
+        .into_iter()
+        .filter(|&count| {
+            count == 1
+                || (adapter_specific || count == 4)
+                    && formats.iter().all(|&format| {
+                        let flags = adapter.get_texture_format_features(format).flags;
+                        flags.sample_count_supported(count)
+                            && (format.is_depth_stencil_format()
+                                || flags
+                                    .contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE))
+                    })
+        })
+        .collect()
 }
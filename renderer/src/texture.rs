@@ -1,6 +1,47 @@
+use std::{cell::RefCell, collections::HashMap};
+
 use anyhow::*;
 use image::GenericImageView;
 
+/// `VertexOutput` and `vs_main` of full-screen passes, see
+/// [`create_fullscreen_shader`].
+pub(crate) const FULLSCREEN_SHADER: &str = include_str!("shaders/fullscreen.wgsl");
+
+/// Shader module of a full-screen pass: `shader` after the full-screen
+/// triangle's `vs_main`, so it only defines the fragment shader.
+pub(crate) fn create_fullscreen_shader(
+    device: &wgpu::Device,
+    label: &str,
+    shader: &str,
+) -> wgpu::ShaderModule {
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(label),
+        source: wgpu::ShaderSource::Wgsl(format!("{FULLSCREEN_SHADER}\n{shader}").into()),
+    })
+}
+
+/// How [`Texture::from_image`] builds a texture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextureOptions {
+    /// Textures storing data rather than colors, like normal maps, should be
+    /// linear so they don't get an sRGB format.
+    pub is_linear: bool,
+    /// Generates the full mip chain and samples it trilinearly, so textures
+    /// don't shimmer in the distance.
+    pub mipmaps: bool,
+}
+
+impl TextureOptions {
+    pub const COLOR: Self = Self {
+        is_linear: false,
+        mipmaps: true,
+    };
+    pub const LINEAR: Self = Self {
+        is_linear: true,
+        mipmaps: true,
+    };
+}
+
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
@@ -13,11 +54,23 @@ impl Texture {
     pub fn create_depth_texture(
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+        label: &str,
+    ) -> Self {
+        Self::create_depth_texture_sized(device, config.width, config.height, sample_count, label)
+    }
+
+    /// Depth texture that isn't tied to the surface size, e.g. shadow maps.
+    pub fn create_depth_texture_sized(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        sample_count: u32,
         label: &str,
     ) -> Self {
         let size = wgpu::Extent3d {
-            width: config.width,
-            height: config.height,
+            width,
+            height,
             depth_or_array_layers: 1,
         };
 
@@ -25,10 +78,16 @@ impl Texture {
             label: Some(label),
             size,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            // Multisampled textures that are only rendered into don't need
+            // multisampled texture support (GLES 3.0 doesn't have it).
+            usage: if sample_count > 1 {
+                wgpu::TextureUsages::RENDER_ATTACHMENT
+            } else {
+                wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING
+            },
             view_formats: &[],
         });
 
@@ -54,21 +113,227 @@ impl Texture {
         }
     }
 
+    /// Color texture that can be rendered into and copied out of, used
+    /// instead of a surface texture when rendering offscreen.
+    pub fn create_render_target(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        label: &str,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        };
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.format,
+            usage: config.usage
+                | wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// Multisampled color texture rendered into and then resolved into the
+    /// actual render target.
+    pub fn create_multisampled_target(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        label: &str,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        };
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// Color texture of the surface size rendered into by one pass and
+    /// sampled by the next, e.g. the HDR scene before tonemapping.
+    pub fn create_intermediate_target(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> Self {
+        Self::create_intermediate_target_sized(device, config.width, config.height, format, label)
+    }
+
+    /// Intermediate target that isn't tied to the surface size, e.g. the
+    /// smaller levels of a blur.
+    pub fn create_intermediate_target_sized(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// Cube texture with `mip_level_count` mips rendered into one face and
+    /// mip at a time, through views of a single layer.
+    pub fn create_cube_target(
+        device: &wgpu::Device,
+        size: u32,
+        mip_level_count: u32,
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 6,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some(label),
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
     pub fn from_bytes(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         bytes: &[u8],
         label: &str,
+        options: TextureOptions,
+        mipmap_generator: Option<&MipmapGenerator>,
     ) -> Result<Self> {
         let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, Some(label))
+        Self::from_image(device, queue, &img, Some(label), options, mipmap_generator)
+    }
+
+    /// 1x1 texture of a single color, used when a material doesn't have a
+    /// texture for some map.
+    pub fn from_color(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color: [u8; 4],
+        label: &str,
+        is_linear: bool,
+    ) -> Result<Self> {
+        let img =
+            image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba(color)));
+        let options = TextureOptions {
+            is_linear,
+            mipmaps: false,
+        };
+        Self::from_image(device, queue, &img, Some(label), options, None)
     }
 
+    /// Generates the mips with `mipmap_generator` if there's one, otherwise
+    /// downsamples them on the CPU.
     pub fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
         label: Option<&str>,
+        options: TextureOptions,
+        mipmap_generator: Option<&MipmapGenerator>,
     ) -> Result<Self> {
         let rgba = img.to_rgba8();
         let dimensions = img.dimensions();
@@ -78,49 +343,424 @@ impl Texture {
             height: dimensions.1,
             depth_or_array_layers: 1,
         };
+        let mip_level_count = if options.mipmaps {
+            size.max_mips(wgpu::TextureDimension::D2)
+        } else {
+            1
+        };
+        let mipmap_generator = mipmap_generator.filter(|_| mip_level_count > 1);
+
+        let format = if options.is_linear {
+            wgpu::TextureFormat::Rgba8Unorm
+        } else {
+            wgpu::TextureFormat::Rgba8UnormSrgb
+        };
+        let mut usage = wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST;
+        if mipmap_generator.is_some() {
+            usage |= wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC;
+        }
 
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label,
             size,
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage,
+            view_formats: &[],
+        });
+
+        let write_mip = |mip_level, image: &image::RgbaImage| {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                image,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * image.width()),
+                    rows_per_image: Some(image.height()),
+                },
+                size.mip_level_size(mip_level, wgpu::TextureDimension::D2),
+            );
+        };
+
+        write_mip(0, &rgba);
+        if let Some(mipmap_generator) = mipmap_generator {
+            mipmap_generator.generate(device, queue, &texture, format, mip_level_count);
+        } else {
+            for mip_level in 1..mip_level_count {
+                let mip_size = size.mip_level_size(mip_level, wgpu::TextureDimension::D2);
+                let mip = image::imageops::resize(
+                    &rgba,
+                    mip_size.width,
+                    mip_size.height,
+                    image::imageops::FilterType::Triangle,
+                );
+                write_mip(mip_level, &mip);
+            }
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: if options.mipmaps {
+                wgpu::FilterMode::Linear
+            } else {
+                wgpu::FilterMode::Nearest
+            },
+            mipmap_filter: if options.mipmaps {
+                wgpu::FilterMode::Linear
+            } else {
+                wgpu::FilterMode::Nearest
+            },
+            ..Default::default()
+        });
+
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+        })
+    }
+
+    /// Cube texture from its six faces, in the order of the cube layers:
+    /// +X, -X, +Y, -Y, +Z, -Z. The faces must be square and the same size.
+    pub fn create_cubemap(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        faces: &[image::DynamicImage; 6],
+        label: &str,
+    ) -> Result<Self> {
+        let (width, height) = faces[0].dimensions();
+        if width != height
+            || faces
+                .iter()
+                .any(|face| face.dimensions() != (width, height))
+        {
+            bail!("The faces of {label} aren't squares of the same size");
+        }
+
+        let data: Vec<u8> = faces
+            .iter()
+            .flat_map(|face| face.to_rgba8().into_raw())
+            .collect();
+
+        Ok(Self::create_cubemap_from_data(
+            device,
+            queue,
+            width,
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+            &data,
+            label,
+        ))
+    }
+
+    /// Cube texture with faces of `face_size` texels, projected from an
+    /// equirectangular (latitude/longitude) panorama like HDR environment
+    /// maps. The colors are kept in a float format so they can go over 1.
+    ///
+    /// The projection happens on the CPU, the GPU might not be able to
+    /// render into float textures.
+    pub fn create_cubemap_from_equirectangular(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        face_size: u32,
+        label: &str,
+    ) -> Self {
+        let panorama = img.to_rgb32f();
+        let mut data = Vec::with_capacity((6 * face_size * face_size * 4) as usize);
+        for face in 0..6 {
+            for y in 0..face_size {
+                for x in 0..face_size {
+                    // Texel center in -1..1 on the face
+                    let u = 2.0 * (x as f32 + 0.5) / face_size as f32 - 1.0;
+                    let v = 2.0 * (y as f32 + 0.5) / face_size as f32 - 1.0;
+                    let direction = cube_face_direction(face, u, v);
+                    let [r, g, b] = sample_equirectangular(&panorama, direction);
+                    data.extend([r, g, b, 1.0].map(half::f16::from_f32));
+                }
+            }
+        }
+
+        Self::create_cubemap_from_data(
+            device,
+            queue,
+            face_size,
+            wgpu::TextureFormat::Rgba16Float,
+            bytemuck::cast_slice(&data),
+            label,
+        )
+    }
+
+    pub(crate) fn create_cubemap_from_data(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        face_size: u32,
+        format: wgpu::TextureFormat,
+        data: &[u8],
+        label: &str,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: face_size,
+            height: face_size,
+            depth_or_array_layers: 6,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            format,
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
             view_formats: &[],
         });
 
+        let bytes_per_texel = format.block_size(None).unwrap();
         queue.write_texture(
-            wgpu::ImageCopyTexture {
-                aspect: wgpu::TextureAspect::All,
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            &rgba,
+            texture.as_image_copy(),
+            data,
             wgpu::ImageDataLayout {
                 offset: 0,
-                bytes_per_row: Some(4 * dimensions.0),
-                rows_per_image: Some(dimensions.1),
+                bytes_per_row: Some(bytes_per_texel * face_size),
+                rows_per_image: Some(face_size),
             },
             size,
         );
 
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some(label),
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
             address_mode_w: wgpu::AddressMode::ClampToEdge,
             mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Linear,
             mipmap_filter: wgpu::FilterMode::Nearest,
             ..Default::default()
         });
 
-        Ok(Self {
+        Self {
             texture,
             view,
             sampler,
-        })
+        }
+    }
+}
+
+/// Direction through the point `(u, v)` of a cube face, both in `-1..1`
+/// with `v` going down, following the layer order of cube textures.
+fn cube_face_direction(face: u32, u: f32, v: f32) -> [f32; 3] {
+    match face {
+        0 => [1.0, -v, -u],
+        1 => [-1.0, -v, u],
+        2 => [u, 1.0, v],
+        3 => [u, -1.0, -v],
+        4 => [u, -v, 1.0],
+        _ => [-u, -v, -1.0],
+    }
+}
+
+/// Bilinearly filtered color of the panorama seen in `direction`, which
+/// doesn't need to be normalized. The panorama wraps around horizontally,
+/// its center looking down -Z.
+fn sample_equirectangular(panorama: &image::Rgb32FImage, direction: [f32; 3]) -> [f32; 3] {
+    use std::f32::consts::PI;
+
+    let [x, y, z] = direction;
+    let longitude = x.atan2(-z);
+    let latitude = (y / (x * x + y * y + z * z).sqrt()).asin();
+    let (width, height) = panorama.dimensions();
+    // Texel coordinates, texel centers at .5
+    let px = (0.5 + longitude / (2.0 * PI)) * width as f32 - 0.5;
+    let py = (0.5 - latitude / PI) * height as f32 - 0.5;
+
+    let x0 = px.floor();
+    let y0 = py.floor();
+    let (fx, fy) = (px - x0, py - y0);
+    let texel = |x: f32, y: f32| {
+        let x = (x as i64).rem_euclid(width as i64) as u32;
+        let y = (y as i64).clamp(0, height as i64 - 1) as u32;
+        panorama.get_pixel(x, y).0
+    };
+
+    let mut color = [0.0; 3];
+    for (texel, weight) in [
+        (texel(x0, y0), (1.0 - fx) * (1.0 - fy)),
+        (texel(x0 + 1.0, y0), fx * (1.0 - fy)),
+        (texel(x0, y0 + 1.0), (1.0 - fx) * fy),
+        (texel(x0 + 1.0, y0 + 1.0), fx * fy),
+    ] {
+        for (channel, value) in color.iter_mut().zip(texel) {
+            *channel += value * weight;
+        }
+    }
+
+    color
+}
+
+/// Fills the mip chains of textures on the GPU, by rendering each level into
+/// the next one with a linear filter. The pipelines are built the first time
+/// a texture of their format needs them.
+///
+/// The GL backend can't sample a view of a single mip level, so each level
+/// is copied into a scratch texture to be sampled from instead.
+pub struct MipmapGenerator {
+    shader: wgpu::ShaderModule,
+    sampler: wgpu::Sampler,
+    pipelines: RefCell<HashMap<wgpu::TextureFormat, wgpu::RenderPipeline>>,
+}
+
+impl MipmapGenerator {
+    /// Formats [`Texture::from_image`] creates textures with.
+    const FORMATS: [wgpu::TextureFormat; 2] = [
+        wgpu::TextureFormat::Rgba8Unorm,
+        wgpu::TextureFormat::Rgba8UnormSrgb,
+    ];
+
+    /// Whether `adapter` can render into and filter the texture formats,
+    /// otherwise the mips are downsampled on the CPU.
+    pub fn is_supported(adapter: &wgpu::Adapter) -> bool {
+        let flags = adapter.get_downlevel_capabilities().flags;
+        flags.contains(wgpu::DownlevelFlags::WEBGPU_TEXTURE_FORMAT_SUPPORT)
+            || Self::FORMATS.iter().all(|&format| {
+                let features = adapter.get_texture_format_features(format);
+                features
+                    .allowed_usages
+                    .contains(wgpu::TextureUsages::RENDER_ATTACHMENT)
+                    && features
+                        .flags
+                        .contains(wgpu::TextureFormatFeatureFlags::FILTERABLE)
+            })
+    }
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self {
+            shader: create_fullscreen_shader(
+                device,
+                "blit.wgsl",
+                include_str!("shaders/blit.wgsl"),
+            ),
+            sampler: device.create_sampler(&wgpu::SamplerDescriptor {
+                label: Some("mipmap_sampler"),
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                ..Default::default()
+            }),
+            pipelines: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Fills mip levels `1..mip_level_count` of `texture` from level 0.
+    pub fn generate(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+        format: wgpu::TextureFormat,
+        mip_level_count: u32,
+    ) {
+        let mut pipelines = self.pipelines.borrow_mut();
+        let pipeline = pipelines.entry(format).or_insert_with(|| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Mipmap Pipeline"),
+                layout: None,
+                vertex: wgpu::VertexState {
+                    module: &self.shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &self.shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(format.into())],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        });
+        let bind_group_layout = pipeline.get_bind_group_layout(0);
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Mipmap Encoder"),
+        });
+        for mip_level in 1..mip_level_count {
+            let source_size = texture
+                .size()
+                .mip_level_size(mip_level - 1, wgpu::TextureDimension::D2);
+            let source = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("mipmap_source_texture"),
+                size: source_size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            });
+            encoder.copy_texture_to_texture(
+                wgpu::ImageCopyTexture {
+                    texture,
+                    mip_level: mip_level - 1,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                source.as_image_copy(),
+                source_size,
+            );
+
+            let source_view = source.create_view(&wgpu::TextureViewDescriptor::default());
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("mipmap_bind_group"),
+                layout: &bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&source_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                ],
+            });
+
+            let target_view = texture.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("mipmap_target_view"),
+                base_mip_level: mip_level,
+                mip_level_count: Some(1),
+                ..Default::default()
+            });
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Mipmap Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &target_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, &bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
     }
 }
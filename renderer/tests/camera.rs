@@ -0,0 +1,143 @@
+//! Orbiting, panning and zooming the camera with the keyboard and mouse.
+
+use cgmath::prelude::*;
+use renderer::camera::{self, Camera, CameraController};
+use winit::dpi::PhysicalPosition;
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, VirtualKeyCode};
+
+fn camera() -> Camera {
+    Camera {
+        eye: (0.0, 0.0, 10.0).into(),
+        target: (0.0, 0.0, 0.0).into(),
+        up: cgmath::Vector3::unit_y(),
+        aspect: 1.0,
+        fovy: 45.0,
+        znear: 0.1,
+        zfar: 100.0,
+    }
+}
+
+fn drag(controller: &mut CameraController, button: MouseButton, (dx, dy): (f64, f64)) {
+    controller.process_cursor_moved(PhysicalPosition::new(100.0, 100.0));
+    controller.process_mouse_button(&ElementState::Pressed, &button);
+    controller.process_cursor_moved(PhysicalPosition::new(100.0 + dx, 100.0 + dy));
+    controller.process_mouse_button(&ElementState::Released, &button);
+}
+
+fn distance(camera: &Camera) -> f32 {
+    camera.eye.distance(camera.target)
+}
+
+#[test]
+fn dragging_orbits_around_the_target() {
+    let mut camera = camera();
+    let mut controller = CameraController::new(0.1);
+    drag(&mut controller, MouseButton::Left, (100.0, 0.0));
+    controller.update_camera(&mut camera);
+
+    assert!((distance(&camera) - 10.0).abs() < 1e-4);
+    assert!(camera.eye.x < 0.0, "{:?}", camera.eye);
+    assert!(camera.eye.y.abs() < 1e-4);
+    assert_eq!(camera.target, (0.0, 0.0, 0.0).into());
+
+    // Nothing moves once the drag has been applied.
+    let eye = camera.eye;
+    controller.update_camera(&mut camera);
+    assert_eq!(camera.eye, eye);
+
+    // Moving without a button pressed doesn't orbit.
+    controller.process_cursor_moved(PhysicalPosition::new(500.0, 500.0));
+    controller.update_camera(&mut camera);
+    assert_eq!(camera.eye, eye);
+}
+
+#[test]
+fn pitch_is_clamped() {
+    let mut camera = camera();
+    let mut controller = CameraController::new(0.1);
+    for dy in [10_000.0, -10_000.0] {
+        drag(&mut controller, MouseButton::Left, (0.0, dy));
+        controller.update_camera(&mut camera);
+
+        let pitch = cgmath::Rad(((camera.eye.y - camera.target.y) / distance(&camera)).asin());
+        let max_pitch: cgmath::Rad<f32> = camera::MAX_PITCH.into();
+        assert!((pitch.0.abs() - max_pitch.0).abs() < 1e-3, "{pitch:?}");
+        assert_eq!(pitch.0 > 0.0, dy > 0.0);
+        assert!((distance(&camera) - 10.0).abs() < 1e-3);
+    }
+}
+
+#[test]
+fn right_dragging_pans() {
+    let mut camera = camera();
+    let mut controller = CameraController::new(0.1);
+    drag(&mut controller, MouseButton::Right, (100.0, 0.0));
+    controller.update_camera(&mut camera);
+
+    // The scene follows the cursor to the right, so the camera goes left.
+    assert!(camera.target.x < 0.0);
+    assert_eq!(camera.eye - camera.target, cgmath::vec3(0.0, 0.0, 10.0));
+}
+
+#[test]
+fn zoom_is_limited() {
+    let mut camera = camera();
+    let mut controller = CameraController::new(0.1);
+    controller.process_scroll(&MouseScrollDelta::LineDelta(0.0, 1.0));
+    controller.update_camera(&mut camera);
+    assert!((distance(&camera) - 9.0).abs() < 1e-4);
+
+    controller.process_scroll(&MouseScrollDelta::LineDelta(0.0, 1000.0));
+    controller.update_camera(&mut camera);
+    assert!((distance(&camera) - camera::MIN_DISTANCE).abs() < 1e-4);
+
+    controller.process_scroll(&MouseScrollDelta::PixelDelta(PhysicalPosition::new(
+        0.0, -1e5,
+    )));
+    controller.update_camera(&mut camera);
+    assert!((distance(&camera) - camera::MAX_DISTANCE).abs() < 1e-3);
+}
+
+#[test]
+fn keys_orbit_the_same_distance_at_any_range() {
+    for range in [5.0, 10.0, 20.0] {
+        let mut camera = camera();
+        camera.eye = (0.0, 0.0, range).into();
+        let mut controller = CameraController::new(0.1);
+        controller.process_events(&ElementState::Pressed, &VirtualKeyCode::D);
+        controller.update_camera(&mut camera);
+
+        // The eye moves about `speed` along the circle, leftwards.
+        let moved = camera.eye.distance((0.0, 0.0, range).into());
+        assert!((moved - 0.1).abs() < 1e-3, "{moved} at {range}");
+        assert!(camera.eye.x < 0.0);
+        assert!((distance(&camera) - range).abs() < 1e-3);
+    }
+}
+
+#[test]
+fn panning_looking_along_up_stays_finite() {
+    let mut camera = camera();
+    camera.eye = (0.0, 10.0, 0.0).into();
+    let mut controller = CameraController::new(0.1);
+    drag(&mut controller, MouseButton::Right, (100.0, 50.0));
+    controller.update_camera(&mut camera);
+
+    let finite = |p: cgmath::Point3<f32>| p.x.is_finite() && p.y.is_finite() && p.z.is_finite();
+    assert!(finite(camera.eye) && finite(camera.target));
+    assert_ne!(camera.target, (0.0, 0.0, 0.0).into());
+    assert_eq!(camera.eye - camera.target, cgmath::vec3(0.0, 10.0, 0.0));
+}
+
+#[test]
+fn zooming_with_the_eye_on_the_target_stays_finite() {
+    let mut camera = camera();
+    camera.eye = camera.target;
+    let mut controller = CameraController::new(0.1);
+    controller.process_scroll(&MouseScrollDelta::LineDelta(0.0, -1.0));
+    controller.update_camera(&mut camera);
+
+    // The eye backs off to the closest distance allowed.
+    assert!((distance(&camera) - camera::MIN_DISTANCE).abs() < 1e-4);
+    assert_eq!(camera.target, (0.0, 0.0, 0.0).into());
+}
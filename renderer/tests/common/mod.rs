@@ -0,0 +1,40 @@
+//! Helpers shared by the integration tests.
+
+// Each test crate compiles its own copy and doesn't use every helper.
+#![allow(dead_code)]
+
+use renderer::{State, Unsupported};
+
+/// Set to skip the tests that render instead of failing them, on machines
+/// without any adapter (not even a software one).
+pub const SKIP_GPU_TESTS: &str = "SKIP_GPU_TESTS";
+
+/// Headless [`State`] rendering `width` by `height` for the test `name`.
+/// Panics when there's no adapter, unless [`SKIP_GPU_TESTS`] is set, then
+/// the test should return on `None`.
+pub fn headless_state(name: &str, width: u32, height: u32) -> Option<State> {
+    match pollster::block_on(State::new_headless(width, height)) {
+        Ok(state) => Some(state),
+        Err(e) if std::env::var_os(SKIP_GPU_TESTS).is_some() => {
+            eprintln!("Skipping {name}: {e}");
+            None
+        }
+        Err(e) => {
+            panic!("{name} couldn't create a State ({e}), set {SKIP_GPU_TESTS}=1 to skip it.")
+        }
+    }
+}
+
+/// The value of `result` for the test `name`, `None` when it failed because
+/// the adapter doesn't support something, then the test should return.
+/// Panics on any other error.
+pub fn skip_unsupported<T>(name: &str, result: anyhow::Result<T>) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(e) if e.downcast_ref::<Unsupported>().is_some() => {
+            eprintln!("Skipping {name}: {e}");
+            None
+        }
+        Err(e) => panic!("{name} failed: {e:?}"),
+    }
+}
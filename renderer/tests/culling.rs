@@ -0,0 +1,113 @@
+//! Frustum tests on their own and culling of the default scene.
+
+mod common;
+
+use renderer::{
+    camera::Camera,
+    culling::{Aabb, Frustum},
+    State,
+};
+
+fn camera() -> Camera {
+    Camera {
+        eye: (0.0, 0.0, 5.0).into(),
+        target: (0.0, 0.0, 0.0).into(),
+        up: cgmath::Vector3::unit_y(),
+        aspect: 1.0,
+        fovy: 90.0,
+        znear: 0.1,
+        zfar: 100.0,
+    }
+}
+
+fn unit_box_at(x: f32, y: f32, z: f32) -> Aabb {
+    Aabb::new(
+        (x - 0.5, y - 0.5, z - 0.5).into(),
+        (x + 0.5, y + 0.5, z + 0.5).into(),
+    )
+}
+
+#[test]
+fn frustum_keeps_boxes_in_view() {
+    let frustum = camera().frustum();
+    assert!(frustum.intersects(&unit_box_at(0.0, 0.0, 0.0)));
+    // Straddling the right plane, x = 5 at the origin.
+    assert!(frustum.intersects(&unit_box_at(5.3, 0.0, 0.0)));
+    // Straddling the near plane.
+    assert!(frustum.intersects(&unit_box_at(0.0, 0.0, 5.0)));
+}
+
+#[test]
+fn frustum_culls_boxes_out_of_view() {
+    let frustum = camera().frustum();
+    assert!(!frustum.intersects(&unit_box_at(7.0, 0.0, 0.0)));
+    assert!(!frustum.intersects(&unit_box_at(0.0, -7.0, 0.0)));
+    assert!(!frustum.intersects(&unit_box_at(0.0, 0.0, 7.0)));
+    assert!(!frustum.intersects(&unit_box_at(0.0, 0.0, -96.0)));
+}
+
+#[test]
+fn transformed_box_contains_rotated_corners() {
+    let rotation = cgmath::Matrix4::from_angle_y(cgmath::Deg(45.0));
+    let aabb = unit_box_at(0.0, 0.0, 0.0).transform(&rotation);
+    let half_diagonal = 0.5 * 2f32.sqrt();
+    assert!((aabb.max.x - half_diagonal).abs() < 1e-5);
+    assert!((aabb.max.y - 0.5).abs() < 1e-5);
+    assert!(
+        Frustum::from_view_projection(&camera().build_view_projection_matrix()).intersects(&aabb)
+    );
+}
+
+#[test]
+fn culls_the_default_scene() {
+    let Some(mut state) = common::headless_state("culls_the_default_scene", 64, 64) else {
+        return;
+    };
+    state.set_gpu_culling(false).unwrap();
+
+    // The default camera only sees the middle of the grid.
+    state.update();
+    let culled = state.culled_count();
+    assert!(culled > 0 && culled < 100, "{culled} culled");
+
+    let camera = state.camera_mut();
+    camera.target = (0.0, 50.0, 0.0).into();
+    camera.eye = (0.0, 20.0, 0.0).into();
+    camera.up = cgmath::Vector3::unit_z();
+    state.update();
+    assert_eq!(state.culled_count(), 100);
+
+    state.set_frustum_culling(false);
+    state.update();
+    assert_eq!(state.culled_count(), 0);
+}
+
+#[test]
+fn gpu_culling_matches_cpu_culling() {
+    let Some(mut state) = common::headless_state("gpu_culling_matches_cpu_culling", 64, 64) else {
+        return;
+    };
+    let supported = state.set_gpu_culling(true);
+    if common::skip_unsupported("gpu_culling_matches_cpu_culling", supported).is_none() {
+        return;
+    }
+
+    let cull = |state: &mut State, gpu| {
+        state.set_gpu_culling(gpu).unwrap();
+        state.update();
+        pollster::block_on(state.render_to_image()).unwrap();
+        pollster::block_on(state.read_culled_count()).unwrap()
+    };
+    let culled = cull(&mut state, false);
+    assert!(culled > 0);
+    assert_eq!(cull(&mut state, true), culled);
+    // Drawing again doesn't count the instances twice.
+    pollster::block_on(state.render_to_image()).unwrap();
+    assert_eq!(
+        pollster::block_on(state.read_culled_count()).unwrap(),
+        culled
+    );
+
+    state.set_frustum_culling(false);
+    assert_eq!(cull(&mut state, true), 0);
+}
@@ -0,0 +1,69 @@
+//! Loading glTF models: buffers in data URIs, node transforms, several
+//! primitives and the default material.
+
+mod common;
+
+use renderer::{assets, model, State};
+
+/// Fixtures are read through the asset loader, relative to `assets/`.
+const FIXTURES: &str = "../tests/fixtures";
+
+fn load(state: &State, file_name: &str) -> anyhow::Result<model::Model> {
+    let layout = model::Material::bind_group_layout(state.device());
+    pollster::block_on(assets::load_model(
+        &format!("{FIXTURES}/{file_name}"),
+        state.device(),
+        state.queue(),
+        &layout,
+        state.mipmap_generator(),
+    ))
+}
+
+#[test]
+fn primitives_are_loaded_with_their_node_transform() {
+    let Some(state) = common::headless_state("gltf_primitives", 64, 64) else {
+        return;
+    };
+    let model = load(&state, "two_primitives.gltf").unwrap();
+
+    // One mesh per primitive, the one without a material uses the default
+    // material added after the file's own.
+    assert_eq!(model.meshes.len(), 2);
+    assert_eq!(model.materials.len(), 2);
+    assert_eq!(model.materials[0].name, "red");
+    assert_eq!(model.materials[0].factors.roughness, 0.25);
+    assert_eq!(model.materials[1].name, "default");
+    assert_eq!(
+        model
+            .meshes
+            .iter()
+            .map(|mesh| mesh.material)
+            .collect::<Vec<_>>(),
+        [0, 1]
+    );
+    assert!(model.meshes.iter().all(|mesh| mesh.num_elements == 3));
+
+    // The node moves the triangles by 10 on X and stretches them twice as
+    // wide.
+    let bounds = |mesh: &model::Mesh| {
+        let (min, max) = (mesh.bounds.min, mesh.bounds.max);
+        ([min.x, min.y, min.z], [max.x, max.y, max.z])
+    };
+    assert_eq!(
+        bounds(&model.meshes[0]),
+        ([10.0, 0.0, 0.0], [12.0, 1.0, 0.0])
+    );
+    assert_eq!(
+        bounds(&model.meshes[1]),
+        ([10.0, 0.0, 1.0], [12.0, 1.0, 1.0])
+    );
+}
+
+#[test]
+fn out_of_range_indices_are_an_error() {
+    let Some(state) = common::headless_state("gltf_out_of_range_indices", 64, 64) else {
+        return;
+    };
+    let error = load(&state, "index_out_of_range.gltf").err().unwrap();
+    assert!(error.to_string().contains("vertex 5"), "{error}");
+}
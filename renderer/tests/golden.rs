@@ -0,0 +1,419 @@
+//! Golden image tests: renders known scenes offscreen and compares them with
+//! the references in `tests/golden/`.
+//!
+//! Run with `UPDATE_GOLDEN=1` to regenerate the references after an intended
+//! visual change. Failing comparisons write the rendered image and a diff
+//! next to each other in the cargo target tmp dir.
+
+mod common;
+
+use std::path::{Path, PathBuf};
+
+use cgmath::{Angle, Rotation3};
+use renderer::{
+    bloom::BloomSettings,
+    hdr::Tonemapper,
+    lod::{Lod, LodThreshold},
+    model::{Mesh, ModelVertex},
+    post::Effect,
+    scene::Transform,
+    texture::Texture,
+    State,
+};
+
+const WIDTH: u32 = 256;
+const HEIGHT: u32 = 192;
+
+/// Max difference allowed per channel before a pixel counts as different.
+const CHANNEL_TOLERANCE: u8 = 8;
+/// Fraction of pixels allowed to differ, rasterizers don't agree on every
+/// edge pixel.
+const MAX_DIFFERENT_PIXELS: f64 = 0.005;
+
+struct Comparison {
+    different_pixels: usize,
+    diff: image::RgbaImage,
+}
+
+fn compare(expected: &image::RgbaImage, actual: &image::RgbaImage) -> Comparison {
+    let mut different_pixels = 0;
+    let diff = image::RgbaImage::from_fn(actual.width(), actual.height(), |x, y| {
+        let a = actual.get_pixel(x, y);
+        let e = expected.get_pixel(x, y);
+        let is_different =
+            a.0.iter()
+                .zip(e.0.iter())
+                .any(|(a, e)| a.abs_diff(*e) > CHANNEL_TOLERANCE);
+
+        if is_different {
+            different_pixels += 1;
+            image::Rgba([255, 0, 255, 255])
+        } else {
+            // Dimmed copy of the render so the differences stand out.
+            let [r, g, b, _] = a.0;
+            image::Rgba([r / 4, g / 4, b / 4, 255])
+        }
+    });
+
+    Comparison {
+        different_pixels,
+        diff,
+    }
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{name}.png"))
+}
+
+fn failure_path(name: &str, kind: &str) -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR"))
+        .join("golden")
+        .join(format!("{name}.{kind}.png"))
+}
+
+/// Renders the default scene without MSAA, after `configure` changes the
+/// settings under test, and checks it against the reference image called
+/// `name`. Settings the adapter doesn't support ([`renderer::Unsupported`])
+/// skip the test, any other error fails it.
+fn assert_golden(name: &str, configure: impl FnOnce(&mut State) -> anyhow::Result<()>) {
+    let Some(mut state) = common::headless_state(name, WIDTH, HEIGHT) else {
+        return;
+    };
+    let configured = state
+        .set_sample_count(1)
+        .and_then(|_| configure(&mut state));
+    if common::skip_unsupported(name, configured).is_none() {
+        return;
+    }
+    state.update();
+    let actual = pollster::block_on(state.render_to_image()).unwrap();
+
+    let golden = golden_path(name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        actual.save(&golden).unwrap();
+        return;
+    }
+
+    let expected = image::open(&golden)
+        .unwrap_or_else(|e| panic!("Couldn't open {golden:?} ({e}), run with UPDATE_GOLDEN=1."))
+        .to_rgba8();
+    assert_eq!(
+        expected.dimensions(),
+        actual.dimensions(),
+        "{name} has a different size than its reference"
+    );
+
+    let comparison = compare(&expected, &actual);
+    let allowed = (MAX_DIFFERENT_PIXELS * (WIDTH * HEIGHT) as f64) as usize;
+    if comparison.different_pixels > allowed {
+        let actual_path = failure_path(name, "actual");
+        let diff_path = failure_path(name, "diff");
+        std::fs::create_dir_all(actual_path.parent().unwrap()).unwrap();
+        actual.save(&actual_path).unwrap();
+        comparison.diff.save(&diff_path).unwrap();
+
+        panic!(
+            "{name}: {} pixels differ (allowed {allowed}), see {actual_path:?} and {diff_path:?}",
+            comparison.different_pixels
+        );
+    }
+}
+
+#[test]
+fn cube_grid_textured() {
+    assert_golden("cube_grid_textured", |_| Ok(()));
+}
+
+#[test]
+fn cube_grid_uv() {
+    assert_golden("cube_grid_uv", |state| {
+        state.set_pipeline(1);
+        Ok(())
+    });
+}
+
+#[test]
+fn cube_grid_msaa() {
+    assert_golden("cube_grid_msaa", |state| state.set_sample_count(4));
+}
+
+#[test]
+fn cube_grid_reinhard_exposure() {
+    assert_golden("cube_grid_reinhard_exposure", |state| {
+        state.set_tonemapper(Tonemapper::Reinhard);
+        state.set_exposure(2.0);
+        Ok(())
+    });
+}
+
+#[test]
+fn cube_grid_strong_bloom() {
+    assert_golden("cube_grid_strong_bloom", |state| {
+        state.set_bloom_settings(BloomSettings {
+            intensity: 1.0,
+            threshold: 0.3,
+            radius: 2.0,
+        });
+        Ok(())
+    });
+}
+
+#[test]
+fn cube_grid_scene_graph() {
+    assert_golden("cube_grid_scene_graph", |state| {
+        let scene = state.scene_mut();
+        let grid = scene.find("grid").unwrap();
+        // The whole grid turns with its root.
+        let grid_transform = scene.transform_mut(grid).unwrap();
+        grid_transform.rotation = cgmath::Quaternion::from_angle_y(cgmath::Deg(30.0));
+
+        let cube = scene.find("cube 4 5").unwrap();
+        scene.transform_mut(cube).unwrap().scale = (2.0, 2.0, 2.0).into();
+        let model = scene.get(cube).unwrap().model();
+        let child = Transform {
+            translation: (0.0, 1.5, 0.0).into(),
+            scale: (0.5, 0.5, 0.5).into(),
+            ..Default::default()
+        };
+        scene.add_node("child", child, model, Some(cube))?;
+
+        let camera = state.camera_mut();
+        camera.eye = (0.0, 12.0, 22.0).into();
+        camera.target = (0.0, 0.0, 0.0).into();
+        Ok(())
+    });
+}
+
+#[test]
+fn cube_grid_dynamic_instances() {
+    assert_golden("cube_grid_dynamic_instances", |state| {
+        let camera = state.camera_mut();
+        camera.eye = (0.0, 12.0, 22.0).into();
+        camera.target = (0.0, 0.0, 0.0).into();
+        // Fills the instance buffer before changing it.
+        state.update();
+        pollster::block_on(state.render_to_image())?;
+
+        let scene = state.scene_mut();
+        for x in 0..10 {
+            let cube = scene.find(&format!("cube {x} 0")).unwrap();
+            scene.remove_node(cube);
+        }
+        // More cubes than the buffer has room for.
+        let center = scene.find("cube 5 5").unwrap();
+        let model = scene.get(center).unwrap().model();
+        for i in 0..60 {
+            let angle = cgmath::Deg(6.0 * i as f32);
+            let transform = Transform {
+                translation: (10.0 * angle.cos(), 5.0, 10.0 * angle.sin()).into(),
+                scale: (0.25, 0.25, 0.25).into(),
+                ..Default::default()
+            };
+            scene.add_node(&format!("ring {i}"), transform, model, None)?;
+        }
+        state.update();
+        pollster::block_on(state.render_to_image())?;
+
+        // Only part of the buffer changes.
+        let scene = state.scene_mut();
+        scene.transform_mut(center).unwrap().rotation =
+            cgmath::Quaternion::from_angle_y(cgmath::Deg(45.0));
+        let ring = scene.find("ring 45").unwrap();
+        scene.remove_node(ring);
+        Ok(())
+    });
+}
+
+/// Far cubes turn into flat squares, so it shows which level each one uses.
+fn add_square_lod(state: &mut State) -> anyhow::Result<()> {
+    let vertex = |x, z, u, v| ModelVertex {
+        position: [x, 0.0, z],
+        tex_coords: [u, v],
+        normal: [0.0, 1.0, 0.0],
+        tangent: [1.0, 0.0, 0.0],
+        bitangent: [0.0, 0.0, 1.0],
+    };
+    let vertices = [
+        vertex(-1.0, -1.0, 0.0, 0.0),
+        vertex(-1.0, 1.0, 0.0, 1.0),
+        vertex(1.0, 1.0, 1.0, 1.0),
+        vertex(1.0, -1.0, 1.0, 0.0),
+    ];
+    let square = Mesh::new(state.device(), "square", &vertices, &[0, 1, 2, 0, 2, 3], 0);
+
+    let cube = state.scene().find("cube 0 0").unwrap();
+    let model = state.scene().get(cube).unwrap().model().unwrap();
+    state.add_lod(
+        model,
+        Lod {
+            meshes: vec![square],
+            threshold: LodThreshold::Distance(18.0),
+        },
+    )
+}
+
+#[test]
+fn cube_grid_lod() {
+    assert_golden("cube_grid_lod", |state| {
+        add_square_lod(state)?;
+        let camera = state.camera_mut();
+        camera.eye = (0.0, 12.0, 22.0).into();
+        camera.target = (0.0, 0.0, 0.0).into();
+        Ok(())
+    });
+}
+
+#[test]
+fn cube_grid_lod_cpu_culling() {
+    assert_golden("cube_grid_lod", |state| {
+        state.set_gpu_culling(false)?;
+        add_square_lod(state)?;
+        let camera = state.camera_mut();
+        camera.eye = (0.0, 12.0, 22.0).into();
+        camera.target = (0.0, 0.0, 0.0).into();
+        Ok(())
+    });
+}
+
+/// Every cube uses `cube_lod2.obj`, made with `renderer simplify cube.obj 0.5 0.2`.
+#[test]
+fn cube_grid_simplified() {
+    assert_golden("cube_grid_simplified", |state| {
+        let cube = state.scene().find("cube 0 0").unwrap();
+        let model = state.scene().get(cube).unwrap().model().unwrap();
+        pollster::block_on(state.load_lod(model, "cube_lod2.obj", LodThreshold::Distance(0.0)))?;
+        let camera = state.camera_mut();
+        camera.eye = (0.0, 12.0, 22.0).into();
+        camera.target = (0.0, 0.0, 0.0).into();
+        Ok(())
+    });
+}
+
+#[test]
+fn scene_file() {
+    assert_golden("scene_file", |state| {
+        pollster::block_on(state.load_scene("scene.ron"))
+    });
+}
+
+/// Moves the camera back and tilts it up towards -Z and a bit to +X, so the
+/// sky fills the upper half.
+fn look_at_sky(state: &mut State) {
+    let camera = state.camera_mut();
+    camera.eye = (0.0, 2.0, 8.0).into();
+    camera.target = (3.0, 3.5, 0.0).into();
+}
+
+#[test]
+fn cube_grid_skybox_faces() {
+    assert_golden("cube_grid_skybox_faces", |state| {
+        let colors = [
+            [255, 0, 0],
+            [0, 255, 255],
+            [0, 255, 0],
+            [255, 0, 255],
+            [0, 0, 255],
+            [255, 255, 0],
+        ];
+        let faces = colors.map(|color| {
+            image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(8, 8, image::Rgb(color)))
+        });
+        let cubemap = Texture::create_cubemap(state.device(), state.queue(), &faces, "faces")?;
+        state.set_skybox(Some(cubemap));
+        look_at_sky(state);
+        Ok(())
+    });
+}
+
+/// Sky getting bluer towards the zenith over a dark ground, with a sun much
+/// brighter than 1 above the cubes.
+fn sky_panorama() -> image::DynamicImage {
+    let (width, height) = (64, 32);
+    let sky = image::Rgb32FImage::from_fn(width, height, |x, y| {
+        let latitude = 0.5 - (y as f32 + 0.5) / height as f32;
+        if (x, y) == (36, 12) {
+            image::Rgb([8.0, 7.0, 5.0])
+        } else if latitude >= 0.0 {
+            let t = latitude * 2.0;
+            image::Rgb([0.8 - 0.6 * t, 0.85 - 0.45 * t, 0.9 - 0.1 * t])
+        } else {
+            image::Rgb([0.2, 0.15, 0.1])
+        }
+    });
+    image::DynamicImage::ImageRgb32F(sky)
+}
+
+#[test]
+fn cube_grid_skybox_equirectangular() {
+    assert_golden("cube_grid_skybox_equirectangular", |state| {
+        let cubemap = Texture::create_cubemap_from_equirectangular(
+            state.device(),
+            state.queue(),
+            &sky_panorama(),
+            32,
+            "sky",
+        );
+        state.set_skybox(Some(cubemap));
+        look_at_sky(state);
+        Ok(())
+    });
+}
+
+#[test]
+fn cube_grid_ibl() {
+    assert_golden("cube_grid_ibl", |state| {
+        let environment = Texture::create_cubemap_from_equirectangular(
+            state.device(),
+            state.queue(),
+            &sky_panorama(),
+            32,
+            "sky",
+        );
+        state.set_environment(Some(environment));
+        look_at_sky(state);
+        Ok(())
+    });
+}
+
+/// Inverts the colors of its input, to check user passes run in the chain.
+const INVERT_SHADER: &str = "
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_input, s_input, in.tex_coords);
+    return vec4<f32>(1.0 - color.rgb, color.a);
+}
+";
+
+#[test]
+fn cube_grid_post_process() {
+    assert_golden("cube_grid_post_process", |state| {
+        pollster::block_on(state.add_post_pass("invert", INVERT_SHADER, [0.0; 4]))?;
+        let post_process = state.post_process_mut();
+        for effect in [Effect::Grayscale, Effect::Vignette, Effect::Fxaa] {
+            post_process.pass_mut(effect.name()).unwrap().enabled = true;
+        }
+        post_process.pass_mut("grayscale").unwrap().params[0] = 0.7;
+        // The vignette darkens the inverted colors instead of lightening them.
+        let invert = post_process.position("invert").unwrap();
+        let vignette = post_process.position("vignette").unwrap();
+        post_process.move_pass(invert, vignette);
+        Ok(())
+    });
+}
+
+#[test]
+fn compare_ignores_small_differences() {
+    let expected = image::RgbaImage::from_pixel(4, 4, image::Rgba([100, 100, 100, 255]));
+    let mut actual = image::RgbaImage::from_pixel(4, 4, image::Rgba([104, 96, 100, 255]));
+    assert_eq!(compare(&expected, &actual).different_pixels, 0);
+
+    actual.put_pixel(1, 2, image::Rgba([200, 100, 100, 255]));
+    let comparison = compare(&expected, &actual);
+    assert_eq!(comparison.different_pixels, 1);
+    assert_eq!(
+        comparison.diff.get_pixel(1, 2),
+        &image::Rgba([255, 0, 255, 255])
+    );
+}
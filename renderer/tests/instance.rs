@@ -0,0 +1,46 @@
+//! Instance buffers only write what changed since the last update.
+
+mod common;
+
+use renderer::instance::{Instance, InstanceBuffer, InstanceRaw};
+
+fn instances(count: usize, y: f32) -> Vec<InstanceRaw> {
+    (0..count)
+        .map(|x| {
+            Instance {
+                position: cgmath::vec3(x as f32, y, 0.0),
+                rotation: cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0),
+                scale: cgmath::vec3(1.0, 1.0, 1.0),
+            }
+            .to_raw()
+        })
+        .collect()
+}
+
+#[test]
+fn updates_write_only_changed_instances() {
+    let Some(state) = common::headless_state("instance_updates", 64, 64) else {
+        return;
+    };
+    let (device, queue) = (state.device(), state.queue());
+    let mut buffer = InstanceBuffer::new(device, 4);
+
+    let mut current = instances(4, 0.0);
+    assert_eq!(buffer.update(device, queue, &current), 4);
+    assert_eq!(buffer.update(device, queue, &current), 0);
+
+    current[1] = instances(2, 1.0)[1];
+    assert_eq!(buffer.update(device, queue, &current), 1);
+
+    // Growing rewrites everything into the bigger buffer.
+    current.extend(instances(2, 2.0));
+    assert_eq!(buffer.update(device, queue, &current), 6);
+    assert_eq!(buffer.capacity(), 8);
+    assert_eq!(buffer.len(), 6);
+
+    // Shrinking keeps the buffer and writes nothing for the kept prefix.
+    current.truncate(3);
+    assert_eq!(buffer.update(device, queue, &current), 0);
+    assert_eq!(buffer.capacity(), 8);
+    assert_eq!(buffer.len(), 3);
+}
@@ -0,0 +1,130 @@
+//! Level of detail selection, and bucketing of the default scene's instances.
+
+mod common;
+
+use renderer::{
+    camera::Camera,
+    culling::Aabb,
+    lod::{self, LodMetrics, LodThreshold},
+};
+
+fn metrics(screen_size: f32, distance: f32) -> LodMetrics {
+    LodMetrics {
+        screen_size,
+        distance,
+    }
+}
+
+#[test]
+fn levels_switch_past_their_thresholds() {
+    let thresholds = [LodThreshold::Distance(10.0), LodThreshold::Distance(20.0)];
+    assert_eq!(lod::select_level(thresholds, &metrics(1.0, 5.0), 0), 0);
+    assert_eq!(lod::select_level(thresholds, &metrics(1.0, 15.0), 0), 1);
+    assert_eq!(lod::select_level(thresholds, &metrics(1.0, 30.0), 0), 2);
+
+    let thresholds = [LodThreshold::ScreenSize(0.5), LodThreshold::ScreenSize(0.1)];
+    assert_eq!(lod::select_level(thresholds, &metrics(0.8, 1.0), 0), 0);
+    assert_eq!(lod::select_level(thresholds, &metrics(0.3, 1.0), 0), 1);
+    assert_eq!(lod::select_level(thresholds, &metrics(0.05, 1.0), 0), 2);
+}
+
+#[test]
+fn hysteresis_keeps_the_current_level_near_a_threshold() {
+    let thresholds = [LodThreshold::Distance(10.0)];
+    // Just past the threshold isn't enough to get coarser...
+    assert_eq!(lod::select_level(thresholds, &metrics(1.0, 10.5), 0), 0);
+    assert_eq!(lod::select_level(thresholds, &metrics(1.0, 11.5), 0), 1);
+    // ...and just before it isn't enough to get finer again.
+    assert_eq!(lod::select_level(thresholds, &metrics(1.0, 9.5), 1), 1);
+    assert_eq!(lod::select_level(thresholds, &metrics(1.0, 8.5), 1), 0);
+}
+
+#[test]
+fn thresholds_follow_in_order() {
+    use LodThreshold::{Distance, ScreenSize};
+    assert!(Distance(12.0).follows(&Distance(8.0)));
+    assert!(!Distance(8.0).follows(&Distance(12.0)));
+    assert!(!Distance(8.0).follows(&Distance(8.0)));
+    assert!(ScreenSize(0.1).follows(&ScreenSize(0.3)));
+    assert!(!ScreenSize(0.3).follows(&ScreenSize(0.1)));
+    assert!(!ScreenSize(0.1).follows(&Distance(8.0)));
+    assert!(!Distance(12.0).follows(&ScreenSize(0.3)));
+}
+
+#[test]
+fn screen_size_shrinks_with_distance() {
+    let camera = Camera {
+        eye: (0.0, 0.0, 0.0).into(),
+        target: (0.0, 0.0, -1.0).into(),
+        up: cgmath::Vector3::unit_y(),
+        aspect: 1.0,
+        fovy: 90.0,
+        znear: 0.1,
+        zfar: 100.0,
+    };
+    let bounds = Aabb::new((-1.0, -1.0, -1.0).into(), (1.0, 1.0, 1.0).into());
+    let at = |z: f32, scale: f32| {
+        cgmath::Matrix4::from_translation((0.0, 0.0, z).into()) * cgmath::Matrix4::from_scale(scale)
+    };
+
+    let near = LodMetrics::new(&camera, &bounds, &at(-10.0, 1.0));
+    let far = LodMetrics::new(&camera, &bounds, &at(-20.0, 1.0));
+    assert!((near.distance - 10.0).abs() < 1e-5);
+    assert!((near.screen_size - 3f32.sqrt() / 10.0).abs() < 1e-5);
+    assert!((far.screen_size - near.screen_size / 2.0).abs() < 1e-5);
+
+    let scaled = LodMetrics::new(&camera, &bounds, &at(-20.0, 2.0));
+    assert!((scaled.screen_size - near.screen_size).abs() < 1e-5);
+}
+
+#[test]
+fn instances_are_bucketed_per_level() {
+    let Some(mut state) = common::headless_state("instances_are_bucketed_per_level", 64, 64) else {
+        return;
+    };
+    let cube = state.scene().find("cube 0 0").unwrap();
+    let model = state.scene().get(cube).unwrap().model().unwrap();
+    for distance in [8.0, 12.0] {
+        pollster::block_on(state.load_lod(model, "cube.obj", LodThreshold::Distance(distance)))
+            .unwrap();
+    }
+
+    state.update();
+    let counts = state.level_counts(model).unwrap().to_vec();
+    assert_eq!(counts.len(), 3);
+    assert_eq!(counts.iter().sum::<u32>(), 100);
+    assert!(counts.iter().all(|&count| count > 0), "{counts:?}");
+
+    // Both ways of culling draw the same instances of every level.
+    let mut culled_counts = Vec::new();
+    for gpu in [false, true] {
+        if state.set_gpu_culling(gpu).is_err() {
+            continue;
+        }
+        state.update();
+        pollster::block_on(state.render_to_image()).unwrap();
+        culled_counts.push(pollster::block_on(state.read_culled_count()).unwrap());
+    }
+    assert!(culled_counts.windows(2).all(|pair| pair[0] == pair[1]));
+
+    // Far from everything, all the cubes use the coarsest level.
+    state.camera_mut().eye = (0.0, 60.0, 60.0).into();
+    state.update();
+    assert_eq!(state.level_counts(model).unwrap(), [0, 0, 100]);
+}
+
+#[test]
+fn levels_out_of_order_are_rejected() {
+    let Some(mut state) = common::headless_state("levels_out_of_order_are_rejected", 64, 64) else {
+        return;
+    };
+    let cube = state.scene().find("cube 0 0").unwrap();
+    let model = state.scene().get(cube).unwrap().model().unwrap();
+    let mut load = |threshold| pollster::block_on(state.load_lod(model, "cube.obj", threshold));
+
+    load(LodThreshold::Distance(12.0)).unwrap();
+    assert!(load(LodThreshold::Distance(8.0)).is_err());
+    assert!(load(LodThreshold::ScreenSize(0.1)).is_err());
+    load(LodThreshold::Distance(20.0)).unwrap();
+    assert_eq!(state.model(model).unwrap().level_count(), 3);
+}
@@ -0,0 +1,28 @@
+//! Loading OBJ models with the PBR extension of MTL.
+
+mod common;
+
+use renderer::{assets, model};
+
+#[test]
+fn maps_without_factors_are_used_as_is() {
+    let Some(state) = common::headless_state("obj_pbr_maps", 64, 64) else {
+        return;
+    };
+    let layout = model::Material::bind_group_layout(state.device());
+    let model = pollster::block_on(assets::load_model(
+        "../tests/fixtures/pbr_maps.obj",
+        state.device(),
+        state.queue(),
+        &layout,
+        state.mipmap_generator(),
+    ))
+    .unwrap();
+
+    // The factors multiply the maps, so without `Pm`, `Pr` and `Ke` they
+    // must not scale the maps away, nor by the roughness `Ns` stands for.
+    let material = &model.materials[0];
+    assert_eq!(material.factors.metallic, 1.0);
+    assert_eq!(material.factors.roughness, 1.0);
+    assert_eq!(material.factors.emissive, [1.0; 3]);
+}
@@ -0,0 +1,28 @@
+//! Adding user passes to the post-processing chain.
+
+mod common;
+
+#[test]
+fn broken_shader_is_an_error() {
+    let Some(mut state) = common::headless_state("post_broken_shader", 64, 64) else {
+        return;
+    };
+    let passes = state.post_process().passes().len();
+
+    let result = pollster::block_on(state.add_post_pass("broken", "fn fs_main( {", [0.0; 4]));
+    assert!(result.is_err());
+    assert_eq!(state.post_process().passes().len(), passes);
+
+    // The chain still renders, and the name is free for a pass that works.
+    state.update();
+    pollster::block_on(state.render_to_image()).unwrap();
+    pollster::block_on(state.add_post_pass(
+        "broken",
+        "@fragment
+        fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+            return textureSample(t_input, s_input, in.tex_coords);
+        }",
+        [0.0; 4],
+    ))
+    .unwrap();
+}
@@ -0,0 +1,190 @@
+//! Scene files survive a round trip through both formats, and applying them
+//! replaces the current scene.
+
+mod common;
+
+use renderer::lod::LodThreshold;
+use renderer::scene_file::{LightDescription, LodDescription, SceneFile, SceneFormat};
+use renderer::State;
+
+const SCENE: &str = include_str!("../assets/scene.ron");
+
+#[test]
+fn round_trips_through_ron_and_json() {
+    let mut scene = SceneFile::parse(SCENE, SceneFormat::Ron).unwrap();
+    assert_eq!(scene.nodes.len(), 1);
+    assert_eq!(scene.nodes[0].children[0].children[0].name, "top");
+    assert!(matches!(
+        scene.lights[1],
+        LightDescription::Directional { .. }
+    ));
+    scene.models[0].lods.push(LodDescription {
+        file: "cube_lod1.obj".to_string(),
+        threshold: LodThreshold::ScreenSize(0.1),
+    });
+
+    for format in [SceneFormat::Ron, SceneFormat::Json] {
+        let text = scene.to_text(format).unwrap();
+        assert_eq!(SceneFile::parse(&text, format).unwrap(), scene);
+    }
+}
+
+#[test]
+fn missing_fields_use_defaults() {
+    let scene = SceneFile::parse(r#"{"nodes": [{"name": "empty"}]}"#, SceneFormat::Json).unwrap();
+    assert_eq!(scene.camera, Default::default());
+    assert_eq!(scene.nodes[0].scale, [1.0; 3]);
+    assert!(scene.models.is_empty());
+}
+
+#[test]
+fn format_goes_by_extension() {
+    assert_eq!(
+        SceneFormat::from_file_name("scenes/a.RON").unwrap(),
+        SceneFormat::Ron
+    );
+    assert_eq!(
+        SceneFormat::from_file_name("a.json").unwrap(),
+        SceneFormat::Json
+    );
+    assert!(SceneFormat::from_file_name("a.obj").is_err());
+}
+
+fn cube_roughness(state: &State) -> f32 {
+    let node = state.scene().find("cube 0 0").unwrap();
+    let model = state.scene().get(node).unwrap().model().unwrap();
+    state.model(model).unwrap().materials[0].factors.roughness
+}
+
+fn cube_scene(extra: &str) -> SceneFile {
+    let text = format!(
+        r#"{{
+            "models": [{{"name": "cube", "file": "cube.obj", {extra}}}],
+            "nodes": [{{"name": "cube 0 0", "model": "cube"}}]
+        }}"#
+    );
+    SceneFile::parse(&text, SceneFormat::Json).unwrap()
+}
+
+#[test]
+fn material_overrides_apply_to_the_loaded_factors() {
+    let Some(mut state) = common::headless_state("material_overrides", 64, 64) else {
+        return;
+    };
+    let loaded = cube_roughness(&state);
+
+    let overridden = cube_scene(r#""materials": [{"roughness": 0.125}]"#);
+    pollster::block_on(state.apply_scene(&overridden)).unwrap();
+    assert_eq!(cube_roughness(&state), 0.125);
+
+    // The next scene starts from the factors in the asset again.
+    pollster::block_on(state.apply_scene(&cube_scene(r#""materials": []"#))).unwrap();
+    assert_eq!(cube_roughness(&state), loaded);
+}
+
+#[test]
+fn failing_scenes_leave_the_current_one() {
+    let Some(mut state) = common::headless_state("failing_scenes", 64, 64) else {
+        return;
+    };
+    let roughness = cube_roughness(&state);
+    let light_count = state.scene_file().lights.len();
+    let eye = state.camera().eye;
+
+    let mut unknown_model = cube_scene(r#""materials": [{"roughness": 0.125}]"#);
+    unknown_model.nodes[0].model = Some("sphere".to_string());
+    let mut missing_environment = cube_scene(r#""materials": [{"roughness": 0.125}]"#);
+    missing_environment.environment = Some("missing.hdr".to_string());
+    missing_environment.camera.eye = [1.0, 2.0, 3.0];
+
+    for scene in [unknown_model, missing_environment] {
+        assert!(pollster::block_on(state.apply_scene(&scene)).is_err());
+        assert_eq!(cube_roughness(&state), roughness);
+        assert_eq!(state.scene_file().lights.len(), light_count);
+        assert_eq!(state.camera().eye, eye);
+        assert!(state.scene().find("cube 1 1").is_some());
+    }
+}
+
+#[test]
+fn nodes_with_models_of_another_state_are_skipped() {
+    // The models of both start out the same, so this one is past the end of
+    // the models of `state`. The GL backend can't have both at once.
+    let Some(mut other) = common::headless_state("unknown_model_other", 64, 64) else {
+        return;
+    };
+    let foreign = pollster::block_on(other.load_model("cube.obj")).unwrap();
+    drop(other);
+    let Some(mut state) = common::headless_state("unknown_model", 64, 64) else {
+        return;
+    };
+    state
+        .scene_mut()
+        .add_node("foreign", Default::default(), Some(foreign), None)
+        .unwrap();
+
+    state.update();
+    pollster::block_on(state.render_to_image()).unwrap();
+    let node = state
+        .scene_file()
+        .nodes
+        .into_iter()
+        .find(|node| node.name == "foreign")
+        .unwrap();
+    assert_eq!(node.model, None);
+}
+
+#[test]
+fn scene_lights_stay_where_the_file_puts_them() {
+    let Some(mut state) = common::headless_state("scene_lights", 64, 64) else {
+        return;
+    };
+    let scene = SceneFile::parse(SCENE, SceneFormat::Ron).unwrap();
+    pollster::block_on(state.apply_scene(&scene)).unwrap();
+    let lights = state.scene_file().lights;
+
+    // Saving the scene again gives back the same lights, however many
+    // frames went by.
+    for _ in 0..10 {
+        state.update();
+    }
+    assert_eq!(state.scene_file().lights, lights);
+}
+
+#[test]
+fn failing_scenes_keep_the_levels_of_detail() {
+    let Some(mut state) = common::headless_state("failing_scenes_lods", 64, 64) else {
+        return;
+    };
+    let with_lods = |thresholds: &[f32]| {
+        let mut scene = cube_scene(r#""materials": []"#);
+        scene.models[0].lods = ["cube_lod1.obj", "cube_lod2.obj"]
+            .iter()
+            .zip(thresholds)
+            .map(|(file, &distance)| LodDescription {
+                file: file.to_string(),
+                threshold: LodThreshold::Distance(distance),
+            })
+            .collect();
+        scene
+    };
+    let thresholds = |state: &State| {
+        let node = state.scene().find("cube 0 0").unwrap();
+        let model = state.scene().get(node).unwrap().model().unwrap();
+        let lods = &state.model(model).unwrap().lods;
+        lods.iter().map(|lod| lod.threshold).collect::<Vec<_>>()
+    };
+    pollster::block_on(state.apply_scene(&with_lods(&[10.0, 20.0]))).unwrap();
+    let applied = thresholds(&state);
+    assert_eq!(applied.len(), 2);
+
+    // Levels out of order, and levels that would load but come with an
+    // environment that doesn't.
+    let out_of_order = with_lods(&[20.0, 10.0]);
+    let mut missing_environment = with_lods(&[5.0]);
+    missing_environment.environment = Some("missing.hdr".to_string());
+    for scene in [out_of_order, missing_environment] {
+        assert!(pollster::block_on(state.apply_scene(&scene)).is_err());
+        assert_eq!(thresholds(&state), applied);
+    }
+}
@@ -0,0 +1,174 @@
+//! Simplification of meshes with quadric error metrics, and writing them out
+//! as OBJ.
+
+use std::collections::{HashMap, HashSet};
+
+use renderer::simplify;
+
+/// Flat grid of `size` by `size` quads from -1 to 1 on X and Z, with a UV
+/// seam down X = 0: the left half maps to U in 0..0.5, the right half to
+/// 0.5..1 flipped, so the two sides have their own vertices on the seam.
+fn seamed_grid(size: usize) -> tobj::Mesh {
+    let mut mesh = tobj::Mesh::default();
+    let mut vertices = HashMap::new();
+    let mut vertex = |mesh: &mut tobj::Mesh, i: usize, j: usize, right: bool| {
+        *vertices.entry((i, j, right)).or_insert_with(|| {
+            let (x, z) = (i as f32 / size as f32, j as f32 / size as f32);
+            mesh.positions.extend([x * 2.0 - 1.0, 0.0, z * 2.0 - 1.0]);
+            let u = if right { 1.5 - x } else { x / 2.0 };
+            mesh.texcoords.extend([u, z]);
+            mesh.normals.extend([0.0, 1.0, 0.0]);
+            (mesh.positions.len() / 3 - 1) as u32
+        })
+    };
+    for i in 0..size {
+        let right = i >= size / 2;
+        for j in 0..size {
+            let corners = [(i, j), (i, j + 1), (i + 1, j + 1), (i + 1, j)]
+                .map(|(i, j)| vertex(&mut mesh, i, j, right));
+            mesh.indices.extend([
+                corners[0], corners[1], corners[2], corners[0], corners[2], corners[3],
+            ]);
+        }
+    }
+
+    mesh
+}
+
+fn vertex_attributes(mesh: &tobj::Mesh) -> HashSet<[u32; 5]> {
+    (0..mesh.positions.len() / 3)
+        .map(|i| {
+            let [x, y, z] = [0, 1, 2].map(|k| mesh.positions[i * 3 + k].to_bits());
+            let [u, v] = [0, 1].map(|k| mesh.texcoords[i * 2 + k].to_bits());
+            [x, y, z, u, v]
+        })
+        .collect()
+}
+
+#[test]
+fn flat_grid_is_reduced_to_the_target() {
+    let mesh = seamed_grid(16);
+    let simplified = simplify::simplify(&mesh, 0.1);
+
+    let (before, after) = (mesh.indices.len() / 3, simplified.indices.len() / 3);
+    assert!(after <= before / 10, "{before} -> {after} triangles");
+    // Collapses only keep original vertices, with their own UVs.
+    assert!(vertex_attributes(&simplified).is_subset(&vertex_attributes(&mesh)));
+    // The corners of the grid stay where they are.
+    for corner in [[-1.0, -1.0], [-1.0, 1.0], [1.0, -1.0], [1.0, 1.0]] {
+        assert!(simplified
+            .positions
+            .chunks_exact(3)
+            .any(|p| [p[0], p[2]] == corner));
+    }
+}
+
+#[test]
+fn uv_seams_are_preserved() {
+    let mesh = seamed_grid(16);
+    let simplified = simplify::simplify(&mesh, 0.1);
+
+    let uv = |vertex: u32| {
+        let i = vertex as usize;
+        [simplified.texcoords[i * 2], simplified.texcoords[i * 2 + 1]]
+    };
+    for triangle in simplified.indices.chunks_exact(3) {
+        // Every triangle stays on one side of the seam, in both position and
+        // UV space.
+        let sides: HashSet<_> = triangle
+            .iter()
+            .map(|&vertex| (vertex, simplified.positions[vertex as usize * 3]))
+            .filter(|&(_, x)| x != 0.0)
+            .map(|(_, x)| x > 0.0)
+            .collect();
+        assert!(sides.len() <= 1, "triangle across the seam");
+        if let Some(&right) = sides.iter().next() {
+            assert!(triangle
+                .iter()
+                .all(|&vertex| (uv(vertex)[0] >= 0.5) == right));
+        }
+    }
+
+    // The seam still runs the whole length of the grid, on both sides.
+    for right in [false, true] {
+        for z in [-1.0, 1.0] {
+            assert!((0..simplified.positions.len() / 3).any(|i| {
+                simplified.positions[i * 3] == 0.0
+                    && simplified.positions[i * 3 + 2] == z
+                    && (simplified.texcoords[i * 2] > 0.5) == right
+            }));
+        }
+    }
+}
+
+#[test]
+fn cube_asset_is_simplified() {
+    let (models, _) = tobj::load_obj(
+        concat!(env!("CARGO_MANIFEST_DIR"), "/assets/cube.obj"),
+        &tobj::LoadOptions {
+            triangulate: true,
+            single_index: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let mesh = &models[0].mesh;
+    let simplified = simplify::simplify(mesh, 0.25);
+
+    let (before, after) = (mesh.indices.len() / 3, simplified.indices.len() / 3);
+    assert!(after < before / 2, "{before} -> {after} triangles");
+    assert_eq!(simplified.material_id, mesh.material_id);
+    assert_eq!(simplified.normals.len(), simplified.positions.len());
+    assert!(vertex_attributes(&simplified).is_subset(&vertex_attributes(mesh)));
+}
+
+#[test]
+fn obj_round_trips_through_tobj() {
+    let model = tobj::Model {
+        mesh: simplify::simplify(&seamed_grid(4), 0.5),
+        name: "grid".to_string(),
+    };
+    let other = tobj::Model {
+        mesh: seamed_grid(2),
+        name: "other".to_string(),
+    };
+    let obj = simplify::write_obj(&[model.clone(), other.clone()], &[], None);
+
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("round_trip.obj");
+    std::fs::write(&path, obj).unwrap();
+    let (loaded, _) = tobj::load_obj(
+        &path,
+        &tobj::LoadOptions {
+            single_index: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    assert_eq!(loaded.len(), 2);
+    for (loaded, written) in loaded.iter().zip([model, other]) {
+        assert_eq!(loaded.name, written.name);
+        assert_eq!(loaded.mesh.indices.len(), written.mesh.indices.len());
+        assert_eq!(
+            vertex_attributes(&loaded.mesh),
+            vertex_attributes(&written.mesh)
+        );
+    }
+}
+
+#[test]
+fn simplification_is_deterministic() {
+    let mesh = seamed_grid(16);
+    let first = simplify::simplify(&mesh, 0.2);
+    let second = simplify::simplify(&mesh, 0.2);
+    assert_eq!(first.indices, second.indices);
+    assert_eq!(first.positions, second.positions);
+    assert_eq!(first.texcoords, second.texcoords);
+}
+
+#[test]
+fn ratios_out_of_range_are_rejected() {
+    for ratio in [-0.5, 1.5, f32::NAN] {
+        assert!(simplify::simplify_asset("cube.obj", &[0.5, ratio]).is_err());
+    }
+}